@@ -1,6 +1,13 @@
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct RegisterRequest {}
+pub struct RegisterRequest {
+    /// ed25519 key the client proves its identity with
+    #[prost(bytes = "vec", tag = "1")]
+    pub public_key: ::prost::alloc::vec::Vec<u8>,
+    /// enrollment token given out by operator
+    #[prost(string, tag = "2")]
+    pub token: ::prost::alloc::string::String,
+}
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RegisterResponse {
@@ -9,10 +16,34 @@ pub struct RegisterResponse {
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChallengeRequest {}
+/// Nonce is signed by the client and sent back in x-nonce-bin and x-signature-bin headers
+/// of the next request. It can be used once
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChallengeResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+}
+/// Replaces the key of the client. The request is signed with the current key, the nonce is signed
+/// with the new one as well
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RotateKeyRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub public_key: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RotateKeyResponse {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Res {
     #[prost(string, tag = "1")]
     pub id: ::prost::alloc::string::String,
-    #[prost(oneof = "res::Result", tags = "2, 3, 4")]
+    #[prost(oneof = "res::Result", tags = "2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13")]
     pub result: ::core::option::Option<res::Result>,
 }
 /// Nested message and enum types in `Res`.
@@ -22,6 +53,12 @@ pub mod res {
     pub struct Pong {
         #[prost(string, tag = "1")]
         pub pong: ::prost::alloc::string::String,
+        /// echoed from ping
+        #[prost(int64, tag = "2")]
+        pub sent_at: i64,
+        /// client time in unix microseconds the ping arrived at
+        #[prost(int64, tag = "3")]
+        pub received_at: i64,
     }
     #[allow(clippy::derive_partial_eq_without_eq)]
     #[derive(Clone, PartialEq, ::prost::Message)]
@@ -35,6 +72,225 @@ pub mod res {
         pub stdout: ::prost::alloc::vec::Vec<u8>,
         #[prost(bytes = "vec", tag = "3")]
         pub stderr: ::prost::alloc::vec::Vec<u8>,
+        /// signal which terminated the process, 0 if exited normally
+        #[prost(int32, tag = "4")]
+        pub signal: i32,
+        /// unix time in milliseconds
+        #[prost(int64, tag = "5")]
+        pub started_at: i64,
+        #[prost(int64, tag = "6")]
+        pub finished_at: i64,
+    }
+    /// Chunk of output of a running shell command. Sent only if streaming is requested
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Output {
+        #[prost(enumeration = "output::Stream", tag = "1")]
+        pub stream: i32,
+        #[prost(bytes = "vec", tag = "2")]
+        pub data: ::prost::alloc::vec::Vec<u8>,
+    }
+    /// Nested message and enum types in `Output`.
+    pub mod output {
+        #[derive(
+            Clone,
+            Copy,
+            Debug,
+            PartialEq,
+            Eq,
+            Hash,
+            PartialOrd,
+            Ord,
+            ::prost::Enumeration
+        )]
+        #[repr(i32)]
+        pub enum Stream {
+            Stdout = 0,
+            Stderr = 1,
+        }
+        impl Stream {
+            /// String value of the enum field names used in the ProtoBuf definition.
+            ///
+            /// The values are not transformed in any way and thus are considered stable
+            /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+            pub fn as_str_name(&self) -> &'static str {
+                match self {
+                    Stream::Stdout => "STDOUT",
+                    Stream::Stderr => "STDERR",
+                }
+            }
+            /// Creates an enum from field names used in the ProtoBuf definition.
+            pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+                match value {
+                    "STDOUT" => Some(Self::Stdout),
+                    "STDERR" => Some(Self::Stderr),
+                    _ => None,
+                }
+            }
+        }
+    }
+    /// Bytes read from terminal of an interactive session
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SessionOutput {
+        #[prost(bytes = "vec", tag = "1")]
+        pub data: ::prost::alloc::vec::Vec<u8>,
+    }
+    /// Shell of an interactive session exited. Last message of the session
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SessionExit {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+        #[prost(int32, tag = "2")]
+        pub signal: i32,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PutFile {
+        #[prost(uint64, tag = "1")]
+        pub size: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub sha256: ::prost::alloc::vec::Vec<u8>,
+        /// set if the file could not be written
+        #[prost(string, tag = "3")]
+        pub error: ::prost::alloc::string::String,
+    }
+    /// Chunk of a file being fetched
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct FileChunk {
+        #[prost(uint64, tag = "1")]
+        pub offset: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub data: ::prost::alloc::vec::Vec<u8>,
+    }
+    /// File has been read completely. Last message of the file
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetFile {
+        #[prost(uint64, tag = "1")]
+        pub size: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub sha256: ::prost::alloc::vec::Vec<u8>,
+        #[prost(uint32, tag = "3")]
+        pub mode: u32,
+        /// unix time in milliseconds
+        #[prost(int64, tag = "4")]
+        pub modified_at: i64,
+        /// set if the file could not be read
+        #[prost(string, tag = "5")]
+        pub error: ::prost::alloc::string::String,
+    }
+    /// Action could not be executed at all
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Error {
+        #[prost(enumeration = "error::Kind", tag = "1")]
+        pub kind: i32,
+        #[prost(string, tag = "2")]
+        pub message: ::prost::alloc::string::String,
+    }
+    /// Description of the host the client runs on
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Facts {
+        #[prost(string, tag = "1")]
+        pub hostname: ::prost::alloc::string::String,
+        #[prost(string, tag = "2")]
+        pub machine_id: ::prost::alloc::string::String,
+        #[prost(string, tag = "3")]
+        pub os_release: ::prost::alloc::string::String,
+        #[prost(string, tag = "4")]
+        pub kernel: ::prost::alloc::string::String,
+        #[prost(string, tag = "5")]
+        pub arch: ::prost::alloc::string::String,
+        #[prost(uint32, tag = "6")]
+        pub cpus: u32,
+        /// bytes
+        #[prost(uint64, tag = "7")]
+        pub memory: u64,
+        #[prost(string, repeated, tag = "8")]
+        pub addresses: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        #[prost(string, tag = "9")]
+        pub agent_version: ::prost::alloc::string::String,
+        /// labels from client configuration
+        #[prost(map = "string, string", tag = "10")]
+        pub labels: ::std::collections::HashMap<
+            ::prost::alloc::string::String,
+            ::prost::alloc::string::String,
+        >,
+    }
+    /// Echoes the heartbeat back as is
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Heartbeat {
+        #[prost(uint64, tag = "1")]
+        pub seq: u64,
+        #[prost(int64, tag = "2")]
+        pub sent_at: i64,
+    }
+    /// Nested message and enum types in `Error`.
+    pub mod error {
+        #[derive(
+            Clone,
+            Copy,
+            Debug,
+            PartialEq,
+            Eq,
+            Hash,
+            PartialOrd,
+            Ord,
+            ::prost::Enumeration
+        )]
+        #[repr(i32)]
+        pub enum Kind {
+            Unknown = 0,
+            /// process could not be started
+            SpawnFailed = 1,
+            NotFound = 2,
+            PermissionDenied = 3,
+            /// command is not known to the client
+            Unsupported = 4,
+            /// command has been killed after its deadline passed
+            TimedOut = 5,
+            /// command has been stopped on request
+            Cancelled = 6,
+            /// action has been executed before, but its result did not survive
+            Lost = 7,
+        }
+        impl Kind {
+            /// String value of the enum field names used in the ProtoBuf definition.
+            ///
+            /// The values are not transformed in any way and thus are considered stable
+            /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+            pub fn as_str_name(&self) -> &'static str {
+                match self {
+                    Kind::Unknown => "UNKNOWN",
+                    Kind::SpawnFailed => "SPAWN_FAILED",
+                    Kind::NotFound => "NOT_FOUND",
+                    Kind::PermissionDenied => "PERMISSION_DENIED",
+                    Kind::Unsupported => "UNSUPPORTED",
+                    Kind::TimedOut => "TIMED_OUT",
+                    Kind::Cancelled => "CANCELLED",
+                    Kind::Lost => "LOST",
+                }
+            }
+            /// Creates an enum from field names used in the ProtoBuf definition.
+            pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+                match value {
+                    "UNKNOWN" => Some(Self::Unknown),
+                    "SPAWN_FAILED" => Some(Self::SpawnFailed),
+                    "NOT_FOUND" => Some(Self::NotFound),
+                    "PERMISSION_DENIED" => Some(Self::PermissionDenied),
+                    "UNSUPPORTED" => Some(Self::Unsupported),
+                    "TIMED_OUT" => Some(Self::TimedOut),
+                    "CANCELLED" => Some(Self::Cancelled),
+                    "LOST" => Some(Self::Lost),
+                    _ => None,
+                }
+            }
+        }
     }
     #[allow(clippy::derive_partial_eq_without_eq)]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
@@ -45,6 +301,26 @@ pub mod res {
         Purge(Purge),
         #[prost(message, tag = "4")]
         Shell(Shell),
+        #[prost(message, tag = "5")]
+        Output(Output),
+        #[prost(message, tag = "6")]
+        SessionOutput(SessionOutput),
+        #[prost(message, tag = "7")]
+        SessionExit(SessionExit),
+        #[prost(message, tag = "8")]
+        PutFile(PutFile),
+        #[prost(message, tag = "9")]
+        FileChunk(FileChunk),
+        #[prost(message, tag = "10")]
+        GetFile(GetFile),
+        #[prost(message, tag = "11")]
+        Error(Error),
+        /// sent on client's own accord with empty id
+        #[prost(message, tag = "12")]
+        Facts(Facts),
+        /// reply to heartbeat, with empty id
+        #[prost(message, tag = "13")]
+        Heartbeat(Heartbeat),
     }
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -52,7 +328,13 @@ pub mod res {
 pub struct Action {
     #[prost(string, tag = "1")]
     pub id: ::prost::alloc::string::String,
-    #[prost(oneof = "action::Command", tags = "2, 3, 4")]
+    /// milliseconds left until the deadline of the action, 0 if there is none
+    #[prost(uint64, tag = "12")]
+    pub timeout: u64,
+    /// chunks of output the client may send ahead of credit for them, 0 if there is no limit
+    #[prost(uint32, tag = "15")]
+    pub window: u32,
+    #[prost(oneof = "action::Command", tags = "2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 16")]
     pub command: ::core::option::Option<action::Command>,
 }
 /// Nested message and enum types in `Action`.
@@ -62,6 +344,9 @@ pub mod action {
     pub struct Ping {
         #[prost(string, tag = "1")]
         pub ping: ::prost::alloc::string::String,
+        /// server time in unix microseconds
+        #[prost(int64, tag = "2")]
+        pub sent_at: i64,
     }
     #[allow(clippy::derive_partial_eq_without_eq)]
     #[derive(Clone, PartialEq, ::prost::Message)]
@@ -75,6 +360,99 @@ pub mod action {
         pub args: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
         #[prost(bytes = "vec", tag = "3")]
         pub stdin: ::prost::alloc::vec::Vec<u8>,
+        /// Send output in chunks while the command runs. The final result carries no output then
+        #[prost(bool, tag = "4")]
+        pub stream: bool,
+    }
+    /// Opens an interactive session: a login shell on a new terminal. Following session messages
+    /// carry id of the action which opened it
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Session {
+        #[prost(uint32, tag = "1")]
+        pub rows: u32,
+        #[prost(uint32, tag = "2")]
+        pub cols: u32,
+        #[prost(string, tag = "3")]
+        pub term: ::prost::alloc::string::String,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SessionInput {
+        #[prost(bytes = "vec", tag = "1")]
+        pub data: ::prost::alloc::vec::Vec<u8>,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SessionResize {
+        #[prost(uint32, tag = "1")]
+        pub rows: u32,
+        #[prost(uint32, tag = "2")]
+        pub cols: u32,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SessionClose {}
+    /// Writes a file. Content follows in file chunks carrying id of this action
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PutFile {
+        #[prost(string, tag = "1")]
+        pub path: ::prost::alloc::string::String,
+        /// permission bits, 0 leaves the default
+        #[prost(uint32, tag = "2")]
+        pub mode: u32,
+        /// user\[:group\], empty leaves the default
+        #[prost(string, tag = "3")]
+        pub owner: ::prost::alloc::string::String,
+        #[prost(uint64, tag = "4")]
+        pub size: u64,
+        #[prost(bytes = "vec", tag = "5")]
+        pub sha256: ::prost::alloc::vec::Vec<u8>,
+        /// write to a temporary file and rename it over the destination once verified
+        #[prost(bool, tag = "6")]
+        pub atomic: bool,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct FileChunk {
+        #[prost(uint64, tag = "1")]
+        pub offset: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub data: ::prost::alloc::vec::Vec<u8>,
+    }
+    /// Reads a file. Content is sent back in file chunks carrying id of this action
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetFile {
+        #[prost(string, tag = "1")]
+        pub path: ::prost::alloc::string::String,
+    }
+    /// Stops the action with the same id
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Cancel {}
+    /// Checks the client is alive, it has to answer right away. Carries empty id and is never
+    /// stored
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Heartbeat {
+        #[prost(uint64, tag = "1")]
+        pub seq: u64,
+        /// server time in unix milliseconds
+        #[prost(int64, tag = "2")]
+        pub sent_at: i64,
+    }
+    /// Output chunks of the action have been taken by whoever watches it. Carries empty id, the
+    /// action is given inside
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Credit {
+        #[prost(string, tag = "1")]
+        pub id: ::prost::alloc::string::String,
+        /// 0 if nobody watches the output anymore, so it is not held back
+        #[prost(uint32, tag = "2")]
+        pub chunks: u32,
     }
     #[allow(clippy::derive_partial_eq_without_eq)]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
@@ -85,6 +463,26 @@ pub mod action {
         Purge(Purge),
         #[prost(message, tag = "4")]
         Shell(Shell),
+        #[prost(message, tag = "5")]
+        Session(Session),
+        #[prost(message, tag = "6")]
+        SessionInput(SessionInput),
+        #[prost(message, tag = "7")]
+        SessionResize(SessionResize),
+        #[prost(message, tag = "8")]
+        SessionClose(SessionClose),
+        #[prost(message, tag = "9")]
+        PutFile(PutFile),
+        #[prost(message, tag = "10")]
+        FileChunk(FileChunk),
+        #[prost(message, tag = "11")]
+        GetFile(GetFile),
+        #[prost(message, tag = "13")]
+        Cancel(Cancel),
+        #[prost(message, tag = "14")]
+        Heartbeat(Heartbeat),
+        #[prost(message, tag = "16")]
+        Credit(Credit),
     }
 }
 /// Generated client implementations.
@@ -216,6 +614,56 @@ pub mod not_ssh_client {
             req.extensions_mut().insert(GrpcMethod::new("notssh.NotSSH", "Poll"));
             self.inner.streaming(req, path, codec).await
         }
+        pub async fn challenge(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ChallengeRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ChallengeResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh.NotSSH/Challenge",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh.NotSSH", "Challenge"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn rotate_key(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RotateKeyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RotateKeyResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh.NotSSH/RotateKey",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh.NotSSH", "RotateKey"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -242,6 +690,14 @@ pub mod not_ssh_server {
             &self,
             request: tonic::Request<tonic::Streaming<super::Res>>,
         ) -> std::result::Result<tonic::Response<Self::PollStream>, tonic::Status>;
+        async fn challenge(
+            &self,
+            request: tonic::Request<super::ChallengeRequest>,
+        ) -> std::result::Result<tonic::Response<super::ChallengeResponse>, tonic::Status>;
+        async fn rotate_key(
+            &self,
+            request: tonic::Request<super::RotateKeyRequest>,
+        ) -> std::result::Result<tonic::Response<super::RotateKeyResponse>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct NotSshServer<T: NotSsh> {
@@ -407,6 +863,94 @@ pub mod not_ssh_server {
                     };
                     Box::pin(fut)
                 }
+                "/notssh.NotSSH/Challenge" => {
+                    #[allow(non_camel_case_types)]
+                    struct ChallengeSvc<T: NotSsh>(pub Arc<T>);
+                    impl<
+                        T: NotSsh,
+                    > tonic::server::UnaryService<super::ChallengeRequest>
+                    for ChallengeSvc<T> {
+                        type Response = super::ChallengeResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ChallengeRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).challenge(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ChallengeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh.NotSSH/RotateKey" => {
+                    #[allow(non_camel_case_types)]
+                    struct RotateKeySvc<T: NotSsh>(pub Arc<T>);
+                    impl<
+                        T: NotSsh,
+                    > tonic::server::UnaryService<super::RotateKeyRequest>
+                    for RotateKeySvc<T> {
+                        type Response = super::RotateKeyResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RotateKeyRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).rotate_key(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = RotateKeySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
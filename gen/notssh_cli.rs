@@ -1,6 +1,10 @@
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct ListRequest {}
+pub struct ListRequest {
+    /// label selector (example: env=prod,role!=db,zone in (a,b),!legacy), all clients if empty
+    #[prost(string, tag = "1")]
+    pub selector: ::prost::alloc::string::String,
+}
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ListResponse {
@@ -16,6 +20,51 @@ pub mod list_response {
         pub id: ::prost::alloc::string::String,
         #[prost(bool, tag = "2")]
         pub connected: bool,
+        /// not given actions until approved
+        #[prost(bool, tag = "3")]
+        pub approved: bool,
+        /// unset until client has reported them
+        #[prost(message, optional, tag = "4")]
+        pub facts: ::core::option::Option<Facts>,
+        /// labels set by operator override the ones reported by client
+        #[prost(map = "string, string", tag = "5")]
+        pub labels: ::std::collections::HashMap<
+            ::prost::alloc::string::String,
+            ::prost::alloc::string::String,
+        >,
+        /// server instance holding the stream of connected client
+        #[prost(string, tag = "6")]
+        pub instance: ::prost::alloc::string::String,
+        /// round-trip time of the last heartbeat in milliseconds of connected client
+        #[prost(uint32, tag = "7")]
+        pub rtt: u32,
+    }
+    /// Host description last reported by client
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Facts {
+        #[prost(string, tag = "1")]
+        pub hostname: ::prost::alloc::string::String,
+        #[prost(string, tag = "2")]
+        pub machine_id: ::prost::alloc::string::String,
+        #[prost(string, tag = "3")]
+        pub os_release: ::prost::alloc::string::String,
+        #[prost(string, tag = "4")]
+        pub kernel: ::prost::alloc::string::String,
+        #[prost(string, tag = "5")]
+        pub arch: ::prost::alloc::string::String,
+        #[prost(uint32, tag = "6")]
+        pub cpus: u32,
+        /// bytes
+        #[prost(uint64, tag = "7")]
+        pub memory: u64,
+        #[prost(string, repeated, tag = "8")]
+        pub addresses: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        #[prost(string, tag = "9")]
+        pub agent_version: ::prost::alloc::string::String,
+        /// unix time in seconds
+        #[prost(int64, tag = "10")]
+        pub updated_at: i64,
     }
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -23,6 +72,9 @@ pub mod list_response {
 pub struct PurgeRequest {
     #[prost(string, tag = "1")]
     pub id: ::prost::alloc::string::String,
+    /// seconds, server default is used if 0
+    #[prost(uint64, tag = "2")]
+    pub timeout: u64,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -35,10 +87,20 @@ pub struct PurgeResponse {
 pub struct PingRequest {
     #[prost(string, tag = "1")]
     pub id: ::prost::alloc::string::String,
+    /// seconds, server default is used if 0
+    #[prost(uint64, tag = "2")]
+    pub timeout: u64,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct PingResponse {}
+pub struct PingResponse {
+    /// round-trip time in microseconds
+    #[prost(uint64, tag = "1")]
+    pub rtt: u64,
+    /// how far clock of the client is ahead of server clock, in microseconds
+    #[prost(int64, tag = "2")]
+    pub clock_offset: i64,
+}
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ShellRequest {
@@ -50,6 +112,14 @@ pub struct ShellRequest {
     pub args: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
     #[prost(bytes = "vec", tag = "4")]
     pub stdin: ::prost::alloc::vec::Vec<u8>,
+    /// seconds, server default is used if 0
+    #[prost(uint64, tag = "5")]
+    pub timeout: u64,
+    /// id of the action to create, so it can be cancelled. Generated by server if empty
+    #[prost(string, tag = "6")]
+    pub action_id: ::prost::alloc::string::String,
+    #[prost(enumeration = "Delivery", tag = "7")]
+    pub delivery: i32,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -58,6 +128,649 @@ pub struct ShellResponse {
     pub stdout: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "2")]
     pub stderr: ::prost::alloc::vec::Vec<u8>,
+    #[prost(int32, tag = "3")]
+    pub code: i32,
+    /// signal which terminated the process, 0 if exited normally
+    #[prost(int32, tag = "4")]
+    pub signal: i32,
+    /// unix time in milliseconds
+    #[prost(int64, tag = "5")]
+    pub started_at: i64,
+    #[prost(int64, tag = "6")]
+    pub finished_at: i64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ShellOutput {
+    #[prost(enumeration = "shell_output::Stream", tag = "1")]
+    pub stream: i32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+}
+/// Nested message and enum types in `ShellOutput`.
+pub mod shell_output {
+    #[derive(
+        Clone,
+        Copy,
+        Debug,
+        PartialEq,
+        Eq,
+        Hash,
+        PartialOrd,
+        Ord,
+        ::prost::Enumeration
+    )]
+    #[repr(i32)]
+    pub enum Stream {
+        Stdout = 0,
+        Stderr = 1,
+    }
+    impl Stream {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Stream::Stdout => "STDOUT",
+                Stream::Stderr => "STDERR",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "STDOUT" => Some(Self::Stdout),
+                "STDERR" => Some(Self::Stderr),
+                _ => None,
+            }
+        }
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ShellStreamResponse {
+    #[prost(oneof = "shell_stream_response::Event", tags = "1, 2")]
+    pub event: ::core::option::Option<shell_stream_response::Event>,
+}
+/// Nested message and enum types in `ShellStreamResponse`.
+pub mod shell_stream_response {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Event {
+        #[prost(message, tag = "1")]
+        Output(super::ShellOutput),
+        /// Last message of the stream
+        #[prost(message, tag = "2")]
+        Result(super::ShellResponse),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AttachRequest {
+    #[prost(oneof = "attach_request::Event", tags = "1, 2, 3")]
+    pub event: ::core::option::Option<attach_request::Event>,
+}
+/// Nested message and enum types in `AttachRequest`.
+pub mod attach_request {
+    /// Must be the first message of the stream
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Open {
+        #[prost(string, tag = "1")]
+        pub id: ::prost::alloc::string::String,
+        #[prost(uint32, tag = "2")]
+        pub rows: u32,
+        #[prost(uint32, tag = "3")]
+        pub cols: u32,
+        #[prost(string, tag = "4")]
+        pub term: ::prost::alloc::string::String,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Resize {
+        #[prost(uint32, tag = "1")]
+        pub rows: u32,
+        #[prost(uint32, tag = "2")]
+        pub cols: u32,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Event {
+        #[prost(message, tag = "1")]
+        Open(Open),
+        #[prost(bytes, tag = "2")]
+        Input(::prost::alloc::vec::Vec<u8>),
+        #[prost(message, tag = "3")]
+        Resize(Resize),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AttachResponse {
+    #[prost(oneof = "attach_response::Event", tags = "1, 2")]
+    pub event: ::core::option::Option<attach_response::Event>,
+}
+/// Nested message and enum types in `AttachResponse`.
+pub mod attach_response {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Exit {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+        #[prost(int32, tag = "2")]
+        pub signal: i32,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Event {
+        #[prost(bytes, tag = "1")]
+        Output(::prost::alloc::vec::Vec<u8>),
+        /// Last message of the stream
+        #[prost(message, tag = "2")]
+        Exit(Exit),
+    }
+}
+/// Stores file content on the server, so it can be put on many clients
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UploadRequest {
+    /// size and checksum are expected in the first message only
+    #[prost(uint64, tag = "1")]
+    pub size: u64,
+    #[prost(bytes = "vec", tag = "2")]
+    pub sha256: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UploadResponse {
+    #[prost(string, tag = "1")]
+    pub blob_id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PutFileRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub blob_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub path: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "4")]
+    pub mode: u32,
+    #[prost(string, tag = "5")]
+    pub owner: ::prost::alloc::string::String,
+    #[prost(bool, tag = "6")]
+    pub atomic: bool,
+    /// seconds, server default is used if 0
+    #[prost(uint64, tag = "7")]
+    pub timeout: u64,
+    /// id of the action to create, so it can be cancelled. Generated by server if empty
+    #[prost(string, tag = "8")]
+    pub action_id: ::prost::alloc::string::String,
+    #[prost(enumeration = "Delivery", tag = "9")]
+    pub delivery: i32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PutFileResponse {
+    #[prost(uint64, tag = "1")]
+    pub size: u64,
+    #[prost(bytes = "vec", tag = "2")]
+    pub sha256: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PullRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub path: ::prost::alloc::string::String,
+    /// seconds, server default is used if 0
+    #[prost(uint64, tag = "3")]
+    pub timeout: u64,
+    /// id of the action to create, so it can be cancelled. Generated by server if empty
+    #[prost(string, tag = "4")]
+    pub action_id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PullResponse {
+    #[prost(oneof = "pull_response::Event", tags = "1, 2")]
+    pub event: ::core::option::Option<pull_response::Event>,
+}
+/// Nested message and enum types in `PullResponse`.
+pub mod pull_response {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct File {
+        #[prost(uint64, tag = "1")]
+        pub size: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub sha256: ::prost::alloc::vec::Vec<u8>,
+        #[prost(uint32, tag = "3")]
+        pub mode: u32,
+        /// unix time in milliseconds
+        #[prost(int64, tag = "4")]
+        pub modified_at: i64,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Event {
+        #[prost(bytes, tag = "1")]
+        Data(::prost::alloc::vec::Vec<u8>),
+        /// Last message of the stream
+        #[prost(message, tag = "2")]
+        File(File),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CancelRequest {
+    #[prost(string, tag = "1")]
+    pub action_id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CancelResponse {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateTokenRequest {
+    /// may be used by any number of clients until revoked or expired
+    #[prost(bool, tag = "1")]
+    pub reusable: bool,
+    /// seconds, token does not expire if 0
+    #[prost(uint64, tag = "2")]
+    pub ttl: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateTokenResponse {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    /// shown once, server only keeps its hash
+    #[prost(string, tag = "2")]
+    pub token: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RevokeTokenRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RevokeTokenResponse {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ApproveRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ApproveResponse {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LabelRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(map = "string, string", tag = "2")]
+    pub set: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// only labels set by operator can be removed, client reports its own again
+    #[prost(string, repeated, tag = "3")]
+    pub remove: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LabelResponse {}
+/// Command executed on every target client by server, progress survives ctl going away
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubmitJobRequest {
+    /// targets are given either by ids or by label selector
+    #[prost(string, repeated, tag = "1")]
+    pub ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, tag = "2")]
+    pub selector: ::prost::alloc::string::String,
+    /// seconds every action may take, server default is used if 0
+    #[prost(uint64, tag = "3")]
+    pub timeout: u64,
+    #[prost(enumeration = "Delivery", tag = "7")]
+    pub delivery: i32,
+    #[prost(oneof = "submit_job_request::Command", tags = "4, 5, 6")]
+    pub command: ::core::option::Option<submit_job_request::Command>,
+}
+/// Nested message and enum types in `SubmitJobRequest`.
+pub mod submit_job_request {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Shell {
+        #[prost(string, tag = "1")]
+        pub cmd: ::prost::alloc::string::String,
+        #[prost(string, repeated, tag = "2")]
+        pub args: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub stdin: ::prost::alloc::vec::Vec<u8>,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Ping {}
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct PutFile {
+        /// uploaded before
+        #[prost(string, tag = "1")]
+        pub blob_id: ::prost::alloc::string::String,
+        #[prost(string, tag = "2")]
+        pub path: ::prost::alloc::string::String,
+        #[prost(uint32, tag = "3")]
+        pub mode: u32,
+        #[prost(string, tag = "4")]
+        pub owner: ::prost::alloc::string::String,
+        #[prost(bool, tag = "5")]
+        pub atomic: bool,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Command {
+        #[prost(message, tag = "4")]
+        Shell(Shell),
+        #[prost(message, tag = "5")]
+        Ping(Ping),
+        #[prost(message, tag = "6")]
+        PutFile(PutFile),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubmitJobResponse {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub client_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Shell command run on clients in batches, the next batch starts once the previous one is done
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RolloutRequest {
+    /// command to run, its id and action_id are ignored
+    #[prost(message, optional, tag = "1")]
+    pub shell: ::core::option::Option<ShellRequest>,
+    /// targets are given either by ids or by label selector
+    #[prost(string, repeated, tag = "2")]
+    pub ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, tag = "3")]
+    pub selector: ::prost::alloc::string::String,
+    /// clients per batch, or percentage of all targets if batch_size is 0
+    #[prost(uint32, tag = "4")]
+    pub batch_size: u32,
+    #[prost(uint32, tag = "5")]
+    pub batch_percent: u32,
+    /// seconds to wait between batches
+    #[prost(uint64, tag = "6")]
+    pub pause: u64,
+    /// remaining batches are not started once more clients than this have failed, no limit if unset
+    #[prost(uint32, optional, tag = "7")]
+    pub max_failures: ::core::option::Option<u32>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RolloutResponse {
+    #[prost(oneof = "rollout_response::Event", tags = "1, 2, 3")]
+    pub event: ::core::option::Option<rollout_response::Event>,
+}
+/// Nested message and enum types in `RolloutResponse`.
+pub mod rollout_response {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Batch {
+        #[prost(uint32, tag = "1")]
+        pub index: u32,
+        #[prost(string, repeated, tag = "2")]
+        pub ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+        /// actions started on the clients, in the same order, so they can be cancelled
+        #[prost(string, repeated, tag = "3")]
+        pub action_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Result {
+        #[prost(string, tag = "1")]
+        pub id: ::prost::alloc::string::String,
+        #[prost(message, optional, tag = "2")]
+        pub response: ::core::option::Option<super::ShellResponse>,
+        /// set if the command could not be run
+        #[prost(string, tag = "3")]
+        pub error: ::prost::alloc::string::String,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Aborted {
+        #[prost(uint32, tag = "1")]
+        pub failed: u32,
+        /// clients the command has not been run on
+        #[prost(string, repeated, tag = "2")]
+        pub skipped: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    }
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Event {
+        #[prost(message, tag = "1")]
+        Batch(Batch),
+        #[prost(message, tag = "2")]
+        Result(Result),
+        #[prost(message, tag = "3")]
+        Aborted(Aborted),
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JobStatusRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    /// include output of shell commands
+    #[prost(bool, tag = "2")]
+    pub output: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JobStatusResponse {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+    /// unix time in seconds
+    #[prost(int64, tag = "2")]
+    pub created_at: i64,
+    #[prost(message, repeated, tag = "3")]
+    pub targets: ::prost::alloc::vec::Vec<job_status_response::Target>,
+}
+/// Nested message and enum types in `JobStatusResponse`.
+pub mod job_status_response {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Target {
+        #[prost(string, tag = "1")]
+        pub client_id: ::prost::alloc::string::String,
+        #[prost(string, tag = "2")]
+        pub action_id: ::prost::alloc::string::String,
+        #[prost(enumeration = "target::State", tag = "3")]
+        pub state: i32,
+        /// why the target failed
+        #[prost(string, tag = "4")]
+        pub error: ::prost::alloc::string::String,
+        /// exit code of shell command
+        #[prost(int32, tag = "5")]
+        pub code: i32,
+        #[prost(bytes = "vec", tag = "6")]
+        pub stdout: ::prost::alloc::vec::Vec<u8>,
+        #[prost(bytes = "vec", tag = "7")]
+        pub stderr: ::prost::alloc::vec::Vec<u8>,
+    }
+    /// Nested message and enum types in `Target`.
+    pub mod target {
+        #[derive(
+            Clone,
+            Copy,
+            Debug,
+            PartialEq,
+            Eq,
+            Hash,
+            PartialOrd,
+            Ord,
+            ::prost::Enumeration
+        )]
+        #[repr(i32)]
+        pub enum State {
+            Pending = 0,
+            Running = 1,
+            Succeeded = 2,
+            Failed = 3,
+        }
+        impl State {
+            /// String value of the enum field names used in the ProtoBuf definition.
+            ///
+            /// The values are not transformed in any way and thus are considered stable
+            /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+            pub fn as_str_name(&self) -> &'static str {
+                match self {
+                    State::Pending => "PENDING",
+                    State::Running => "RUNNING",
+                    State::Succeeded => "SUCCEEDED",
+                    State::Failed => "FAILED",
+                }
+            }
+            /// Creates an enum from field names used in the ProtoBuf definition.
+            pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+                match value {
+                    "PENDING" => Some(Self::Pending),
+                    "RUNNING" => Some(Self::Running),
+                    "SUCCEEDED" => Some(Self::Succeeded),
+                    "FAILED" => Some(Self::Failed),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubmitResponse {
+    #[prost(string, tag = "1")]
+    pub action_id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetResultRequest {
+    #[prost(string, tag = "1")]
+    pub action_id: ::prost::alloc::string::String,
+    /// wait for the action to be done instead of returning its current state
+    #[prost(bool, tag = "2")]
+    pub wait: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetResultResponse {
+    #[prost(string, tag = "1")]
+    pub action_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub client_id: ::prost::alloc::string::String,
+    #[prost(enumeration = "get_result_response::State", tag = "3")]
+    pub state: i32,
+    /// why the action failed
+    #[prost(string, tag = "4")]
+    pub error: ::prost::alloc::string::String,
+    /// output of finished shell action
+    #[prost(message, optional, tag = "5")]
+    pub shell: ::core::option::Option<ShellResponse>,
+    /// checksum of the file written by finished put file action
+    #[prost(bytes = "vec", tag = "6")]
+    pub sha256: ::prost::alloc::vec::Vec<u8>,
+}
+/// Nested message and enum types in `GetResultResponse`.
+pub mod get_result_response {
+    #[derive(
+        Clone,
+        Copy,
+        Debug,
+        PartialEq,
+        Eq,
+        Hash,
+        PartialOrd,
+        Ord,
+        ::prost::Enumeration
+    )]
+    #[repr(i32)]
+    pub enum State {
+        Pending = 0,
+        Running = 1,
+        Finished = 2,
+        Failed = 3,
+        TimedOut = 4,
+        Cancelled = 5,
+        Lost = 6,
+    }
+    impl State {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                State::Pending => "PENDING",
+                State::Running => "RUNNING",
+                State::Finished => "FINISHED",
+                State::Failed => "FAILED",
+                State::TimedOut => "TIMED_OUT",
+                State::Cancelled => "CANCELLED",
+                State::Lost => "LOST",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "PENDING" => Some(Self::Pending),
+                "RUNNING" => Some(Self::Running),
+                "FINISHED" => Some(Self::Finished),
+                "FAILED" => Some(Self::Failed),
+                "TIMED_OUT" => Some(Self::TimedOut),
+                "CANCELLED" => Some(Self::Cancelled),
+                "LOST" => Some(Self::Lost),
+                _ => None,
+            }
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Delivery {
+    /// action is never executed twice, it is lost if the client disconnects while executing it
+    AtMostOnce = 0,
+    /// action is delivered again once the client reconnects, unless the client has executed it
+    AtLeastOnce = 1,
+}
+impl Delivery {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Delivery::AtMostOnce => "AT_MOST_ONCE",
+            Delivery::AtLeastOnce => "AT_LEAST_ONCE",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "AT_MOST_ONCE" => Some(Self::AtMostOnce),
+            "AT_LEAST_ONCE" => Some(Self::AtLeastOnce),
+            _ => None,
+        }
+    }
 }
 /// Generated client implementations.
 pub mod not_ssh_cli_client {
@@ -230,127 +943,1056 @@ pub mod not_ssh_cli_client {
                 .insert(GrpcMethod::new("notssh_cli.NotSshCli", "Shell"));
             self.inner.unary(req, path, codec).await
         }
-    }
-}
-/// Generated server implementations.
-pub mod not_ssh_cli_server {
-    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
-    use tonic::codegen::*;
-    /// Generated trait containing gRPC methods that should be implemented for use with NotSshCliServer.
-    #[async_trait]
-    pub trait NotSshCli: Send + Sync + 'static {
-        async fn list(
-            &self,
-            request: tonic::Request<super::ListRequest>,
-        ) -> std::result::Result<tonic::Response<super::ListResponse>, tonic::Status>;
-        async fn ping(
-            &self,
-            request: tonic::Request<super::PingRequest>,
-        ) -> std::result::Result<tonic::Response<super::PingResponse>, tonic::Status>;
-        async fn purge(
-            &self,
-            request: tonic::Request<super::PurgeRequest>,
-        ) -> std::result::Result<tonic::Response<super::PurgeResponse>, tonic::Status>;
-        async fn shell(
-            &self,
-            request: tonic::Request<super::ShellRequest>,
-        ) -> std::result::Result<tonic::Response<super::ShellResponse>, tonic::Status>;
-    }
-    #[derive(Debug)]
-    pub struct NotSshCliServer<T: NotSshCli> {
-        inner: _Inner<T>,
-        accept_compression_encodings: EnabledCompressionEncodings,
-        send_compression_encodings: EnabledCompressionEncodings,
-        max_decoding_message_size: Option<usize>,
-        max_encoding_message_size: Option<usize>,
-    }
-    struct _Inner<T>(Arc<T>);
-    impl<T: NotSshCli> NotSshCliServer<T> {
-        pub fn new(inner: T) -> Self {
-            Self::from_arc(Arc::new(inner))
-        }
-        pub fn from_arc(inner: Arc<T>) -> Self {
-            let inner = _Inner(inner);
-            Self {
-                inner,
-                accept_compression_encodings: Default::default(),
-                send_compression_encodings: Default::default(),
-                max_decoding_message_size: None,
-                max_encoding_message_size: None,
-            }
+        pub async fn shell_stream(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ShellRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::ShellStreamResponse>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/ShellStream",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "ShellStream"));
+            self.inner.server_streaming(req, path, codec).await
         }
-        pub fn with_interceptor<F>(
-            inner: T,
-            interceptor: F,
-        ) -> InterceptedService<Self, F>
-        where
-            F: tonic::service::Interceptor,
-        {
-            InterceptedService::new(Self::new(inner), interceptor)
+        pub async fn attach(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<Message = super::AttachRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::AttachResponse>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/Attach",
+            );
+            let mut req = request.into_streaming_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "Attach"));
+            self.inner.streaming(req, path, codec).await
         }
-        /// Enable decompressing requests with the given encoding.
-        #[must_use]
-        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
-            self.accept_compression_encodings.enable(encoding);
-            self
+        pub async fn upload(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<Message = super::UploadRequest>,
+        ) -> std::result::Result<tonic::Response<super::UploadResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/Upload",
+            );
+            let mut req = request.into_streaming_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "Upload"));
+            self.inner.client_streaming(req, path, codec).await
         }
-        /// Compress responses with the given encoding, if the client supports it.
-        #[must_use]
-        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
-            self.send_compression_encodings.enable(encoding);
-            self
+        pub async fn put_file(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PutFileRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::PutFileResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/PutFile",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "PutFile"));
+            self.inner.unary(req, path, codec).await
         }
-        /// Limits the maximum size of a decoded message.
-        ///
-        /// Default: `4MB`
-        #[must_use]
-        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
-            self.max_decoding_message_size = Some(limit);
-            self
+        pub async fn pull(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PullRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::PullResponse>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/Pull",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "Pull"));
+            self.inner.server_streaming(req, path, codec).await
         }
-        /// Limits the maximum size of an encoded message.
-        ///
-        /// Default: `usize::MAX`
-        #[must_use]
-        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
-            self.max_encoding_message_size = Some(limit);
-            self
+        pub async fn cancel(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CancelRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CancelResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/Cancel",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "Cancel"));
+            self.inner.unary(req, path, codec).await
         }
-    }
-    impl<T, B> tonic::codegen::Service<http::Request<B>> for NotSshCliServer<T>
-    where
-        T: NotSshCli,
-        B: Body + Send + 'static,
-        B::Error: Into<StdError> + Send + 'static,
-    {
-        type Response = http::Response<tonic::body::BoxBody>;
-        type Error = std::convert::Infallible;
-        type Future = BoxFuture<Self::Response, Self::Error>;
-        fn poll_ready(
+        pub async fn create_token(
             &mut self,
-            _cx: &mut Context<'_>,
-        ) -> Poll<std::result::Result<(), Self::Error>> {
-            Poll::Ready(Ok(()))
+            request: impl tonic::IntoRequest<super::CreateTokenRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CreateTokenResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/CreateToken",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "CreateToken"));
+            self.inner.unary(req, path, codec).await
         }
-        fn call(&mut self, req: http::Request<B>) -> Self::Future {
-            let inner = self.inner.clone();
-            match req.uri().path() {
-                "/notssh_cli.NotSshCli/List" => {
+        pub async fn revoke_token(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RevokeTokenRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RevokeTokenResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/RevokeToken",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "RevokeToken"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn approve(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ApproveRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ApproveResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/Approve",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "Approve"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn label(
+            &mut self,
+            request: impl tonic::IntoRequest<super::LabelRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::LabelResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/Label",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "Label"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn submit_job(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SubmitJobRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::SubmitJobResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/SubmitJob",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "SubmitJob"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn job_status(
+            &mut self,
+            request: impl tonic::IntoRequest<super::JobStatusRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::JobStatusResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/JobStatus",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "JobStatus"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn rollout(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RolloutRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::RolloutResponse>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/Rollout",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notssh_cli.NotSshCli", "Rollout"));
+            self.inner.server_streaming(req, path, codec).await
+        }
+        pub async fn submit_shell(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ShellRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::SubmitResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/SubmitShell",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notsshcli.NotSshCli", "SubmitShell"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn submit_put_file(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PutFileRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::SubmitResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/SubmitPutFile",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notsshcli.NotSshCli", "SubmitPutFile"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_result(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetResultRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetResultResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/notssh_cli.NotSshCli/GetResult",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("notsshcli.NotSshCli", "GetResult"));
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod not_ssh_cli_server {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with NotSshCliServer.
+    #[async_trait]
+    pub trait NotSshCli: Send + Sync + 'static {
+        async fn list(
+            &self,
+            request: tonic::Request<super::ListRequest>,
+        ) -> std::result::Result<tonic::Response<super::ListResponse>, tonic::Status>;
+        async fn ping(
+            &self,
+            request: tonic::Request<super::PingRequest>,
+        ) -> std::result::Result<tonic::Response<super::PingResponse>, tonic::Status>;
+        async fn purge(
+            &self,
+            request: tonic::Request<super::PurgeRequest>,
+        ) -> std::result::Result<tonic::Response<super::PurgeResponse>, tonic::Status>;
+        async fn shell(
+            &self,
+            request: tonic::Request<super::ShellRequest>,
+        ) -> std::result::Result<tonic::Response<super::ShellResponse>, tonic::Status>;
+        /// Server streaming response type for the ShellStream method.
+        type ShellStreamStream: futures_core::Stream<
+                Item = std::result::Result<super::ShellStreamResponse, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        async fn shell_stream(
+            &self,
+            request: tonic::Request<super::ShellRequest>,
+        ) -> std::result::Result<
+            tonic::Response<Self::ShellStreamStream>,
+            tonic::Status,
+        >;
+        /// Server streaming response type for the Attach method.
+        type AttachStream: futures_core::Stream<
+                Item = std::result::Result<super::AttachResponse, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        async fn attach(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::AttachRequest>>,
+        ) -> std::result::Result<tonic::Response<Self::AttachStream>, tonic::Status>;
+        async fn upload(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::UploadRequest>>,
+        ) -> std::result::Result<tonic::Response<super::UploadResponse>, tonic::Status>;
+        async fn put_file(
+            &self,
+            request: tonic::Request<super::PutFileRequest>,
+        ) -> std::result::Result<tonic::Response<super::PutFileResponse>, tonic::Status>;
+        /// Server streaming response type for the Pull method.
+        type PullStream: futures_core::Stream<
+                Item = std::result::Result<super::PullResponse, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        async fn pull(
+            &self,
+            request: tonic::Request<super::PullRequest>,
+        ) -> std::result::Result<tonic::Response<Self::PullStream>, tonic::Status>;
+        async fn cancel(
+            &self,
+            request: tonic::Request<super::CancelRequest>,
+        ) -> std::result::Result<tonic::Response<super::CancelResponse>, tonic::Status>;
+        async fn create_token(
+            &self,
+            request: tonic::Request<super::CreateTokenRequest>,
+        ) -> std::result::Result<tonic::Response<super::CreateTokenResponse>, tonic::Status>;
+        async fn revoke_token(
+            &self,
+            request: tonic::Request<super::RevokeTokenRequest>,
+        ) -> std::result::Result<tonic::Response<super::RevokeTokenResponse>, tonic::Status>;
+        async fn approve(
+            &self,
+            request: tonic::Request<super::ApproveRequest>,
+        ) -> std::result::Result<tonic::Response<super::ApproveResponse>, tonic::Status>;
+        async fn label(
+            &self,
+            request: tonic::Request<super::LabelRequest>,
+        ) -> std::result::Result<tonic::Response<super::LabelResponse>, tonic::Status>;
+        async fn submit_job(
+            &self,
+            request: tonic::Request<super::SubmitJobRequest>,
+        ) -> std::result::Result<tonic::Response<super::SubmitJobResponse>, tonic::Status>;
+        async fn job_status(
+            &self,
+            request: tonic::Request<super::JobStatusRequest>,
+        ) -> std::result::Result<tonic::Response<super::JobStatusResponse>, tonic::Status>;
+        /// Server streaming response type for the Rollout method.
+        type RolloutStream: futures_core::Stream<
+                Item = std::result::Result<super::RolloutResponse, tonic::Status>,
+            >
+            + Send
+            + 'static;
+        async fn rollout(
+            &self,
+            request: tonic::Request<super::RolloutRequest>,
+        ) -> std::result::Result<tonic::Response<Self::RolloutStream>, tonic::Status>;
+        async fn submit_shell(
+            &self,
+            request: tonic::Request<super::ShellRequest>,
+        ) -> std::result::Result<tonic::Response<super::SubmitResponse>, tonic::Status>;
+        async fn submit_put_file(
+            &self,
+            request: tonic::Request<super::PutFileRequest>,
+        ) -> std::result::Result<tonic::Response<super::SubmitResponse>, tonic::Status>;
+        async fn get_result(
+            &self,
+            request: tonic::Request<super::GetResultRequest>,
+        ) -> std::result::Result<tonic::Response<super::GetResultResponse>, tonic::Status>;
+    }
+    #[derive(Debug)]
+    pub struct NotSshCliServer<T: NotSshCli> {
+        inner: _Inner<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    struct _Inner<T>(Arc<T>);
+    impl<T: NotSshCli> NotSshCliServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            let inner = _Inner(inner);
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for NotSshCliServer<T>
+    where
+        T: NotSshCli,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            match req.uri().path() {
+                "/notssh_cli.NotSshCli/List" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<T: NotSshCli> tonic::server::UnaryService<super::ListRequest>
+                    for ListSvc<T> {
+                        type Response = super::ListResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).list(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/Ping" => {
+                    #[allow(non_camel_case_types)]
+                    struct PingSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<T: NotSshCli> tonic::server::UnaryService<super::PingRequest>
+                    for PingSvc<T> {
+                        type Response = super::PingResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PingRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).ping(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PingSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/Purge" => {
+                    #[allow(non_camel_case_types)]
+                    struct PurgeSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<T: NotSshCli> tonic::server::UnaryService<super::PurgeRequest>
+                    for PurgeSvc<T> {
+                        type Response = super::PurgeResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PurgeRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).purge(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PurgeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/Shell" => {
+                    #[allow(non_camel_case_types)]
+                    struct ShellSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<T: NotSshCli> tonic::server::UnaryService<super::ShellRequest>
+                    for ShellSvc<T> {
+                        type Response = super::ShellResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ShellRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).shell(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ShellSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/ShellStream" => {
+                    #[allow(non_camel_case_types)]
+                    struct ShellStreamSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::ServerStreamingService<super::ShellRequest>
+                    for ShellStreamSvc<T> {
+                        type Response = super::ShellStreamResponse;
+                        type ResponseStream = T::ShellStreamStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ShellRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).shell_stream(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ShellStreamSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/Attach" => {
+                    #[allow(non_camel_case_types)]
+                    struct AttachSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::StreamingService<super::AttachRequest>
+                    for AttachSvc<T> {
+                        type Response = super::AttachResponse;
+                        type ResponseStream = T::AttachStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                tonic::Streaming<super::AttachRequest>,
+                            >,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).attach(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = AttachSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/Upload" => {
+                    #[allow(non_camel_case_types)]
+                    struct UploadSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::ClientStreamingService<super::UploadRequest>
+                    for UploadSvc<T> {
+                        type Response = super::UploadResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                tonic::Streaming<super::UploadRequest>,
+                            >,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).upload(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = UploadSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.client_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/PutFile" => {
+                    #[allow(non_camel_case_types)]
+                    struct PutFileSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<T: NotSshCli> tonic::server::UnaryService<super::PutFileRequest>
+                    for PutFileSvc<T> {
+                        type Response = super::PutFileResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PutFileRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).put_file(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PutFileSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/Pull" => {
+                    #[allow(non_camel_case_types)]
+                    struct PullSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::ServerStreamingService<super::PullRequest>
+                    for PullSvc<T> {
+                        type Response = super::PullResponse;
+                        type ResponseStream = T::PullStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PullRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).pull(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PullSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/Cancel" => {
+                    #[allow(non_camel_case_types)]
+                    struct CancelSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::CancelRequest>
+                    for CancelSvc<T> {
+                        type Response = super::CancelResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CancelRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).cancel(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = CancelSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/CreateToken" => {
                     #[allow(non_camel_case_types)]
-                    struct ListSvc<T: NotSshCli>(pub Arc<T>);
-                    impl<T: NotSshCli> tonic::server::UnaryService<super::ListRequest>
-                    for ListSvc<T> {
-                        type Response = super::ListResponse;
+                    struct CreateTokenSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::CreateTokenRequest>
+                    for CreateTokenSvc<T> {
+                        type Response = super::CreateTokenResponse;
                         type Future = BoxFuture<
                             tonic::Response<Self::Response>,
                             tonic::Status,
                         >;
                         fn call(
                             &mut self,
-                            request: tonic::Request<super::ListRequest>,
+                            request: tonic::Request<super::CreateTokenRequest>,
                         ) -> Self::Future {
                             let inner = Arc::clone(&self.0);
-                            let fut = async move { (*inner).list(request).await };
+                            let fut = async move { (*inner).create_token(request).await };
                             Box::pin(fut)
                         }
                     }
@@ -361,7 +2003,7 @@ pub mod not_ssh_cli_server {
                     let inner = self.inner.clone();
                     let fut = async move {
                         let inner = inner.0;
-                        let method = ListSvc(inner);
+                        let method = CreateTokenSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec)
                             .apply_compression_config(
@@ -377,22 +2019,24 @@ pub mod not_ssh_cli_server {
                     };
                     Box::pin(fut)
                 }
-                "/notssh_cli.NotSshCli/Ping" => {
+                "/notssh_cli.NotSshCli/RevokeToken" => {
                     #[allow(non_camel_case_types)]
-                    struct PingSvc<T: NotSshCli>(pub Arc<T>);
-                    impl<T: NotSshCli> tonic::server::UnaryService<super::PingRequest>
-                    for PingSvc<T> {
-                        type Response = super::PingResponse;
+                    struct RevokeTokenSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::RevokeTokenRequest>
+                    for RevokeTokenSvc<T> {
+                        type Response = super::RevokeTokenResponse;
                         type Future = BoxFuture<
                             tonic::Response<Self::Response>,
                             tonic::Status,
                         >;
                         fn call(
                             &mut self,
-                            request: tonic::Request<super::PingRequest>,
+                            request: tonic::Request<super::RevokeTokenRequest>,
                         ) -> Self::Future {
                             let inner = Arc::clone(&self.0);
-                            let fut = async move { (*inner).ping(request).await };
+                            let fut = async move { (*inner).revoke_token(request).await };
                             Box::pin(fut)
                         }
                     }
@@ -403,7 +2047,7 @@ pub mod not_ssh_cli_server {
                     let inner = self.inner.clone();
                     let fut = async move {
                         let inner = inner.0;
-                        let method = PingSvc(inner);
+                        let method = RevokeTokenSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec)
                             .apply_compression_config(
@@ -419,22 +2063,24 @@ pub mod not_ssh_cli_server {
                     };
                     Box::pin(fut)
                 }
-                "/notssh_cli.NotSshCli/Purge" => {
+                "/notssh_cli.NotSshCli/Approve" => {
                     #[allow(non_camel_case_types)]
-                    struct PurgeSvc<T: NotSshCli>(pub Arc<T>);
-                    impl<T: NotSshCli> tonic::server::UnaryService<super::PurgeRequest>
-                    for PurgeSvc<T> {
-                        type Response = super::PurgeResponse;
+                    struct ApproveSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::ApproveRequest>
+                    for ApproveSvc<T> {
+                        type Response = super::ApproveResponse;
                         type Future = BoxFuture<
                             tonic::Response<Self::Response>,
                             tonic::Status,
                         >;
                         fn call(
                             &mut self,
-                            request: tonic::Request<super::PurgeRequest>,
+                            request: tonic::Request<super::ApproveRequest>,
                         ) -> Self::Future {
                             let inner = Arc::clone(&self.0);
-                            let fut = async move { (*inner).purge(request).await };
+                            let fut = async move { (*inner).approve(request).await };
                             Box::pin(fut)
                         }
                     }
@@ -445,7 +2091,7 @@ pub mod not_ssh_cli_server {
                     let inner = self.inner.clone();
                     let fut = async move {
                         let inner = inner.0;
-                        let method = PurgeSvc(inner);
+                        let method = ApproveSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec)
                             .apply_compression_config(
@@ -461,12 +2107,191 @@ pub mod not_ssh_cli_server {
                     };
                     Box::pin(fut)
                 }
-                "/notssh_cli.NotSshCli/Shell" => {
+                "/notssh_cli.NotSshCli/Label" => {
                     #[allow(non_camel_case_types)]
-                    struct ShellSvc<T: NotSshCli>(pub Arc<T>);
-                    impl<T: NotSshCli> tonic::server::UnaryService<super::ShellRequest>
-                    for ShellSvc<T> {
-                        type Response = super::ShellResponse;
+                    struct LabelSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::LabelRequest>
+                    for LabelSvc<T> {
+                        type Response = super::LabelResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::LabelRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).label(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = LabelSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/SubmitJob" => {
+                    #[allow(non_camel_case_types)]
+                    struct SubmitJobSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::SubmitJobRequest>
+                    for SubmitJobSvc<T> {
+                        type Response = super::SubmitJobResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SubmitJobRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).submit_job(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = SubmitJobSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/JobStatus" => {
+                    #[allow(non_camel_case_types)]
+                    struct JobStatusSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::JobStatusRequest>
+                    for JobStatusSvc<T> {
+                        type Response = super::JobStatusResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::JobStatusRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).job_status(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = JobStatusSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/Rollout" => {
+                    #[allow(non_camel_case_types)]
+                    struct RolloutSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::ServerStreamingService<super::RolloutRequest>
+                    for RolloutSvc<T> {
+                        type Response = super::RolloutResponse;
+                        type ResponseStream = T::RolloutStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RolloutRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).rollout(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = RolloutSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/SubmitShell" => {
+                    #[allow(non_camel_case_types)]
+                    struct SubmitShellSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::ShellRequest>
+                    for SubmitShellSvc<T> {
+                        type Response = super::SubmitResponse;
                         type Future = BoxFuture<
                             tonic::Response<Self::Response>,
                             tonic::Status,
@@ -476,7 +2301,7 @@ pub mod not_ssh_cli_server {
                             request: tonic::Request<super::ShellRequest>,
                         ) -> Self::Future {
                             let inner = Arc::clone(&self.0);
-                            let fut = async move { (*inner).shell(request).await };
+                            let fut = async move { (*inner).submit_shell(request).await };
                             Box::pin(fut)
                         }
                     }
@@ -487,7 +2312,95 @@ pub mod not_ssh_cli_server {
                     let inner = self.inner.clone();
                     let fut = async move {
                         let inner = inner.0;
-                        let method = ShellSvc(inner);
+                        let method = SubmitShellSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/SubmitPutFile" => {
+                    #[allow(non_camel_case_types)]
+                    struct SubmitPutFileSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::PutFileRequest>
+                    for SubmitPutFileSvc<T> {
+                        type Response = super::SubmitResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PutFileRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).submit_put_file(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = SubmitPutFileSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/notssh_cli.NotSshCli/GetResult" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetResultSvc<T: NotSshCli>(pub Arc<T>);
+                    impl<
+                        T: NotSshCli,
+                    > tonic::server::UnaryService<super::GetResultRequest>
+                    for GetResultSvc<T> {
+                        type Response = super::GetResultResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetResultRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { (*inner).get_result(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetResultSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec)
                             .apply_compression_config(
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS facts (
+    client_id varchar primary key REFERENCES clients(id) ON DELETE CASCADE,
+    hostname varchar NOT NULL,
+    machine_id varchar NOT NULL,
+    os_release varchar NOT NULL,
+    kernel varchar NOT NULL,
+    arch varchar NOT NULL,
+    cpus integer NOT NULL,
+    memory bigint NOT NULL,
+    addresses varchar[] NOT NULL,
+    agent_version varchar NOT NULL,
+    updated_at timestamp with time zone NOT NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS labels (
+    client_id varchar NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    key varchar NOT NULL,
+    value varchar NOT NULL,
+    operator boolean NOT NULL,
+    PRIMARY KEY (client_id, key, operator)
+);
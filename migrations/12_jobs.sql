@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS jobs (
+    id varchar primary key,
+    command smallint NOT NULL,
+    targets varchar NOT NULL,
+    created_at timestamp with time zone NOT NULL
+);
+ALTER TABLE actions ADD COLUMN IF NOT EXISTS job_id varchar REFERENCES jobs(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS actions_job_id_idx ON actions (job_id);
@@ -0,0 +1 @@
+ALTER TABLE actions ADD COLUMN IF NOT EXISTS finished_at timestamp with time zone;
@@ -0,0 +1 @@
+ALTER TABLE actions ADD COLUMN IF NOT EXISTS delivery smallint NOT NULL DEFAULT 0;
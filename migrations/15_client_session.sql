@@ -0,0 +1,2 @@
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS epoch bigint NOT NULL DEFAULT 0;
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS lease_expires_at timestamp with time zone;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS instances (
+    id varchar primary key,
+    started_at timestamp with time zone NOT NULL,
+    heartbeat_at timestamp with time zone NOT NULL
+);
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS instance_id varchar REFERENCES instances(id) ON DELETE SET NULL;
+CREATE TABLE IF NOT EXISTS relay (
+    id bigserial primary key,
+    message bytea NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT current_timestamp
+);
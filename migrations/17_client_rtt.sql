@@ -0,0 +1 @@
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS rtt_ms integer;
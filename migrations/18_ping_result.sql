@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS ping_result (
+    id varchar primary key,
+    rtt bigint NOT NULL,
+    clock_offset bigint NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS output_subscribers (
+    action_id varchar primary key,
+    instance_id varchar NOT NULL REFERENCES instances(id) ON DELETE CASCADE
+);
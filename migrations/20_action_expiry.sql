@@ -0,0 +1 @@
+ALTER TABLE actions ADD COLUMN IF NOT EXISTS expires_at timestamp with time zone;
@@ -0,0 +1,4 @@
+ALTER TABLE blob ADD COLUMN IF NOT EXISTS completed_at timestamp with time zone;
+ALTER TABLE blob ADD COLUMN IF NOT EXISTS updated_at timestamp with time zone;
+UPDATE blob SET completed_at = created_at, updated_at = created_at WHERE updated_at IS NULL;
+ALTER TABLE blob ALTER COLUMN updated_at SET NOT NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS shell_result (
+    id varchar primary key,
+    code integer NOT NULL,
+    signal integer,
+    stdout bytea NOT NULL,
+    stderr bytea NOT NULL,
+    started_at timestamp with time zone,
+    finished_at timestamp with time zone
+);
@@ -0,0 +1 @@
+ALTER TABLE shell ADD COLUMN IF NOT EXISTS stream boolean NOT NULL DEFAULT false;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS session (
+    id varchar primary key,
+    rows integer NOT NULL,
+    cols integer NOT NULL,
+    term varchar NOT NULL
+);
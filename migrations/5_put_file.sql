@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS blob (
+    id varchar primary key,
+    size bigint NOT NULL,
+    sha256 bytea NOT NULL,
+    chunks integer NOT NULL,
+    created_at timestamp with time zone NOT NULL
+);
+CREATE TABLE IF NOT EXISTS blob_chunk (
+    blob_id varchar NOT NULL REFERENCES blob (id) ON DELETE CASCADE,
+    seq integer NOT NULL,
+    data bytea NOT NULL,
+    PRIMARY KEY (blob_id, seq)
+);
+CREATE TABLE IF NOT EXISTS put_file (
+    id varchar primary key,
+    blob_id varchar NOT NULL,
+    path varchar NOT NULL,
+    mode integer NOT NULL,
+    owner varchar NOT NULL,
+    atomic boolean NOT NULL
+);
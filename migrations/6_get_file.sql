@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS get_file (
+    id varchar primary key,
+    path varchar NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS get_file_result (
+    id varchar primary key,
+    size bigint NOT NULL,
+    sha256 bytea NOT NULL,
+    mode integer NOT NULL,
+    modified_at timestamp with time zone
+);
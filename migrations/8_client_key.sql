@@ -0,0 +1,6 @@
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS public_key bytea;
+CREATE TABLE IF NOT EXISTS challenge (
+    nonce bytea primary key,
+    client_id varchar NOT NULL,
+    expires_at timestamp with time zone NOT NULL
+);
@@ -0,0 +1,8 @@
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS approved boolean NOT NULL DEFAULT true;
+CREATE TABLE IF NOT EXISTS enrollment_token (
+    id varchar primary key,
+    sha256 bytea NOT NULL UNIQUE,
+    reusable boolean NOT NULL,
+    created_at timestamp with time zone NOT NULL,
+    expires_at timestamp with time zone
+);
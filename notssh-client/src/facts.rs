@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    thread,
+};
+
+use crate::notssh::res;
+
+/// Collects description of the host. Anything that can't be found out is left empty
+pub fn collect(labels: HashMap<String, String>) -> res::Facts {
+    let (hostname, kernel, arch) = uname();
+    res::Facts {
+        hostname,
+        machine_id: read_first(&["/etc/machine-id", "/var/lib/dbus/machine-id"])
+            .map(|s| s.trim().to_owned())
+            .unwrap_or_default(),
+        os_release: read_first(&["/etc/os-release", "/usr/lib/os-release"])
+            .and_then(|s| pretty_name(&s))
+            .unwrap_or_default(),
+        kernel,
+        arch,
+        cpus: thread::available_parallelism()
+            .map(|n| n.get() as u32)
+            .unwrap_or_default(),
+        memory: memory(),
+        addresses: addresses(),
+        agent_version: env!("CARGO_PKG_VERSION").to_owned(),
+        labels,
+    }
+}
+
+fn read_first(paths: &[&str]) -> Option<String> {
+    paths.iter().find_map(|p| fs::read_to_string(p).ok())
+}
+
+fn pretty_name(os_release: &str) -> Option<String> {
+    os_release.lines().find_map(|line| {
+        line.strip_prefix("PRETTY_NAME=")
+            .map(|v| v.trim_matches(|c| c == '"' || c == '\'').to_owned())
+    })
+}
+
+// Hostname, kernel release and machine architecture
+fn uname() -> (String, String, String) {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return Default::default();
+    }
+    let field = |f: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(f.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    (
+        field(&uts.nodename),
+        field(&uts.release),
+        field(&uts.machine),
+    )
+}
+
+fn memory() -> u64 {
+    let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if pages <= 0 || page_size <= 0 {
+        return 0;
+    }
+    pages as u64 * page_size as u64
+}
+
+// Addresses of all interfaces except loopback
+fn addresses() -> Vec<String> {
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Vec::new();
+    }
+    let mut addresses = Vec::new();
+    let mut cur = ifaddrs;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let addr = match unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+        if !addr.is_loopback() {
+            addresses.push(addr.to_string());
+        }
+    }
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    addresses
+}
@@ -0,0 +1,236 @@
+use std::{ffi::CString, io, mem, os::unix::fs::PermissionsExt, path::PathBuf, ptr};
+
+use notssh_util::error;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc::UnboundedSender,
+    time::Instant,
+};
+
+use crate::{
+    notssh::{action, res, Res},
+    window::Window,
+};
+
+const CHUNK_SIZE: usize = 65536;
+
+/// File being written chunk by chunk
+pub struct Upload {
+    path: PathBuf,
+    // Temporary file renamed over the destination once complete, if atomic
+    tmp: Option<PathBuf>,
+    file: File,
+    hasher: Sha256,
+    written: u64,
+    size: u64,
+    sha256: Vec<u8>,
+    mode: u32,
+    owner: String,
+    deadline: Option<Instant>,
+}
+
+impl Upload {
+    pub async fn create(
+        id: &str,
+        put: action::PutFile,
+        deadline: Option<Instant>,
+    ) -> error::Result<Self> {
+        let path = PathBuf::from(put.path);
+        let name = path
+            .file_name()
+            .ok_or(error::Error::bad_request("destination is not a file path"))?
+            .to_string_lossy()
+            .into_owned();
+        let tmp = put
+            .atomic
+            .then(|| path.with_file_name(format!(".{}.notssh-{}", name, id)));
+        let file = File::create(tmp.as_ref().unwrap_or(&path))
+            .await
+            .map_err(io_error)?;
+        Ok(Self {
+            path,
+            tmp,
+            file,
+            hasher: Sha256::new(),
+            written: 0,
+            size: put.size,
+            sha256: put.sha256,
+            mode: put.mode,
+            owner: put.owner,
+            deadline,
+        })
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.written >= self.size
+    }
+
+    /// Time by which the whole content has to arrive
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub async fn write(&mut self, chunk: action::FileChunk) -> error::Result<()> {
+        if chunk.offset != self.written {
+            return Err(error::Error::bad_request(format!(
+                "expected chunk at offset {}, got {}",
+                self.written, chunk.offset
+            )));
+        }
+        self.file.write_all(&chunk.data).await?;
+        self.hasher.update(&chunk.data);
+        self.written += chunk.data.len() as u64;
+        Ok(())
+    }
+
+    /// Verifies content and moves the file in place. Returns size and checksum of the file
+    pub async fn finish(mut self) -> error::Result<(u64, Vec<u8>)> {
+        self.file.sync_all().await?;
+        let sha256 = mem::take(&mut self.hasher).finalize().to_vec();
+        if self.written != self.size || sha256 != self.sha256 {
+            return Err(error::Error::io(format!(
+                "content of {} does not match its checksum",
+                self.path.display()
+            )));
+        }
+
+        let written = self.tmp.as_ref().unwrap_or(&self.path);
+        if self.mode != 0 {
+            tokio::fs::set_permissions(written, PermissionsExt::from_mode(self.mode))
+                .await
+                .map_err(io_error)?;
+        }
+        if !self.owner.is_empty() {
+            let (uid, gid) = owner(&self.owner)?;
+            std::os::unix::fs::chown(written, uid, gid).map_err(io_error)?;
+        }
+        if let Some(tmp) = self.tmp.take() {
+            tokio::fs::rename(tmp, &self.path).await.map_err(io_error)?;
+        }
+        Ok((self.written, sha256))
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        // Unfinished temporary file is of no use to anyone
+        if let Some(tmp) = self.tmp.as_ref() {
+            let _ = std::fs::remove_file(tmp);
+        }
+    }
+}
+
+/// Reads the file and sends its content chunk by chunk. Returns the final result of the action
+pub async fn send(
+    id: &str,
+    get: action::GetFile,
+    tx: &UnboundedSender<Res>,
+    window: &Window,
+) -> error::Result<res::Result> {
+    let mut file = File::open(&get.path).await.map_err(io_error)?;
+    let meta = file.metadata().await?;
+    if !meta.is_file() {
+        return Err(error::Error::bad_request(format!(
+            "{} is not a regular file",
+            get.path
+        )));
+    }
+
+    let mut hasher = Sha256::new();
+    let mut offset = 0;
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        window.acquire(tx).await?;
+        tx.send(Res {
+            id: id.to_owned(),
+            result: Some(res::Result::file_chunk(offset, buf[..n].to_vec())),
+        })?;
+        offset += n as u64;
+    }
+
+    Ok(res::Result::get_file(
+        offset,
+        hasher.finalize().to_vec(),
+        meta.permissions().mode() & 0o7777,
+        meta.modified().ok(),
+    ))
+}
+
+// Tells missing files and lack of access apart from other failures, so they are reported as such
+fn io_error(e: io::Error) -> error::Error {
+    match e.kind() {
+        io::ErrorKind::NotFound => error::Error::not_found(e.to_string()),
+        io::ErrorKind::PermissionDenied => error::Error::permission_denied(e.to_string()),
+        _ => error::Error::io(e.to_string()),
+    }
+}
+
+// Resolves user[:group] into ids. Numeric ids are accepted as well
+fn owner(owner: &str) -> error::Result<(Option<u32>, Option<u32>)> {
+    let (user, group) = match owner.split_once(':') {
+        Some((u, g)) => (u, Some(g)),
+        None => (owner, None),
+    };
+    let uid = match user {
+        "" => None,
+        u => Some(u.parse().or_else(|_| lookup_user(u))?),
+    };
+    let gid = match group {
+        None | Some("") => None,
+        Some(g) => Some(g.parse().or_else(|_| lookup_group(g))?),
+    };
+    Ok((uid, gid))
+}
+
+fn lookup_user(name: &str) -> error::Result<u32> {
+    let cname = CString::new(name).map_err(|_| error::Error::bad_request("invalid user name"))?;
+    let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buf = vec![0; 4096];
+    let mut result = ptr::null_mut();
+    let r = unsafe {
+        libc::getpwnam_r(
+            cname.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if r != 0 {
+        return Err(io::Error::from_raw_os_error(r).into());
+    }
+    if result.is_null() {
+        return Err(error::Error::not_found(format!("user {} not found", name)));
+    }
+    Ok(pwd.pw_uid)
+}
+
+fn lookup_group(name: &str) -> error::Result<u32> {
+    let cname = CString::new(name).map_err(|_| error::Error::bad_request("invalid group name"))?;
+    let mut grp: libc::group = unsafe { mem::zeroed() };
+    let mut buf = vec![0; 4096];
+    let mut result = ptr::null_mut();
+    let r = unsafe {
+        libc::getgrnam_r(
+            cname.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if r != 0 {
+        return Err(io::Error::from_raw_os_error(r).into());
+    }
+    if result.is_null() {
+        return Err(error::Error::not_found(format!("group {} not found", name)));
+    }
+    Ok(grp.gr_gid)
+}
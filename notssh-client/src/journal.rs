@@ -0,0 +1,136 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use notssh_util::error;
+
+/// Ids of actions the client has executed, so an action delivered again after a lost connection
+/// is not executed twice. Stored one id per line, only the most recent ones are kept
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    ids: VecDeque<String>,
+    index: HashSet<String>,
+}
+
+impl Journal {
+    // Number of ids kept once the journal is compacted
+    const CAPACITY: usize = 4096;
+
+    pub fn load(path: impl Into<PathBuf>) -> error::Result<Self> {
+        let path = path.into();
+        let ids: VecDeque<String> = match fs::read_to_string(&path) {
+            Ok(data) => data.lines().map(str::to_owned).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let index = ids.iter().cloned().collect();
+        let file = open(&path)?;
+        Ok(Self {
+            path,
+            file,
+            ids,
+            index,
+        })
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.index.contains(id)
+    }
+
+    /// Records the action before its effects take place, so it is not repeated after a crash
+    pub fn record(&mut self, id: &str) -> error::Result<()> {
+        if !self.index.insert(id.to_owned()) {
+            return Ok(());
+        }
+        self.ids.push_back(id.to_owned());
+        writeln!(self.file, "{}", id)?;
+        self.file.sync_data()?;
+        if self.ids.len() > 2 * Self::CAPACITY {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    // Rewrites the journal with the most recent ids only
+    fn compact(&mut self) -> error::Result<()> {
+        while self.ids.len() > Self::CAPACITY {
+            if let Some(id) = self.ids.pop_front() {
+                self.index.remove(&id);
+            }
+        }
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut tmp = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        for id in &self.ids {
+            writeln!(tmp, "{}", id)?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        self.file = open(&self.path)?;
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> error::Result<File> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("notssh-journal-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn recorded_ids_survive_reload() {
+        let path = path("reload");
+        let mut journal = Journal::load(&path).unwrap();
+        journal.record("a").unwrap();
+        journal.record("b").unwrap();
+        journal.record("a").unwrap();
+        let journal = Journal::load(&path).unwrap();
+        assert!(journal.contains("a") && journal.contains("b"));
+        assert!(!journal.contains("c"));
+        assert_eq!(journal.ids.len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compaction_keeps_most_recent_ids() {
+        let path = path("compact");
+        let mut journal = Journal::load(&path).unwrap();
+        let total = 2 * Journal::CAPACITY + 1;
+        for i in 0..total {
+            journal.record(&i.to_string()).unwrap();
+        }
+        let oldest = total - Journal::CAPACITY;
+        for journal in [journal, Journal::load(&path).unwrap()] {
+            assert_eq!(journal.ids.len(), Journal::CAPACITY);
+            assert!(!journal.contains(&(oldest - 1).to_string()));
+            assert!(journal.contains(&oldest.to_string()));
+            assert!(journal.contains(&(total - 1).to_string()));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
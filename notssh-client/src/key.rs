@@ -0,0 +1,120 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{Signer, SigningKey, SECRET_KEY_LENGTH};
+use notssh_util::error;
+use rand::RngCore;
+
+/// Key the client proves its identity with. Stored as raw secret bytes
+pub struct Keypair {
+    path: PathBuf,
+    key: SigningKey,
+    // Key the client is rotating to, kept next to the current one until server accepts it
+    next: Option<SigningKey>,
+}
+
+impl Keypair {
+    /// Loads the key, generating one on first start. Rotation interrupted before is resumed
+    pub fn load(path: impl Into<PathBuf>) -> error::Result<Self> {
+        let path = path.into();
+        let key = match read(&path)? {
+            Some(key) => key,
+            None => {
+                let key = generate();
+                write(&path, &key)?;
+                log::info!("generated new key at {}", path.display());
+                key
+            }
+        };
+        let next = read(&next_path(&path))?;
+        Ok(Self { path, key, next })
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.key.verifying_key().to_bytes().to_vec()
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Vec<u8> {
+        self.key.sign(msg).to_bytes().to_vec()
+    }
+
+    /// Starts rotation to a freshly generated key
+    pub fn rotate(&mut self) -> error::Result<()> {
+        let next = generate();
+        write(&next_path(&self.path), &next)?;
+        self.next = Some(next);
+        Ok(())
+    }
+
+    /// Offers the current key to server, for clients registered before they had keys
+    pub fn enroll(&mut self) {
+        self.next = Some(self.key.clone());
+    }
+
+    /// Public key and signature of the key being rotated to, if any
+    pub fn sign_next(&self, msg: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.next.as_ref().map(|k| {
+            (
+                k.verifying_key().to_bytes().to_vec(),
+                k.sign(msg).to_bytes().to_vec(),
+            )
+        })
+    }
+
+    /// Makes the next key current once server has accepted it
+    pub fn commit(&mut self) -> error::Result<()> {
+        let next = match self.next.take() {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+        match fs::rename(next_path(&self.path), &self.path) {
+            Ok(_) => {}
+            // Enrolled key is the current one, nothing to replace
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.key = next;
+        Ok(())
+    }
+}
+
+fn next_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".next");
+    PathBuf::from(name)
+}
+
+fn generate() -> SigningKey {
+    let mut secret = [0; SECRET_KEY_LENGTH];
+    rand::thread_rng().fill_bytes(&mut secret);
+    SigningKey::from_bytes(&secret)
+}
+
+fn read(path: &Path) -> error::Result<Option<SigningKey>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let secret = data
+        .as_slice()
+        .try_into()
+        .map_err(|_| error::Error::bad_request(format!("{} is not a key", path.display())))?;
+    Ok(Some(SigningKey::from_bytes(secret)))
+}
+
+fn write(path: &Path, key: &SigningKey) -> error::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(key.as_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
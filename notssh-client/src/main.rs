@@ -1,52 +1,209 @@
 use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
     str::FromStr,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::Context;
 use clap::Parser;
 use log::LevelFilter;
-use notssh::{not_ssh_client::NotSshClient, res, RegisterRequest, Res};
+use notssh::{
+    not_ssh_client::NotSshClient, res, ChallengeRequest, RegisterRequest, Res, RotateKeyRequest,
+};
 use notssh_util::error;
-use tokio::sync::mpsc::unbounded_channel;
+use prost::Message;
+use rand::Rng;
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, Semaphore,
+    },
+    time::Instant,
+};
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use tonic::transport::Endpoint;
+use tonic::{
+    codegen::InterceptedService,
+    metadata::MetadataValue,
+    service::Interceptor,
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity},
+};
 
+mod facts;
+mod file;
+mod journal;
+mod key;
+mod session;
 mod shell;
+mod window;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+const FACTS_INTERVAL: Duration = Duration::from_secs(3600);
+// Bytes of results kept while there is no connection, the oldest ones are dropped beyond that
+const OUTBOX_LIMIT: usize = 64 << 20;
 
 pub mod notssh {
+    use std::{
+        os::unix::process::ExitStatusExt,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use notssh_util::error::{Error, ErrorKind};
+
     include!("../../gen/notssh.rs");
 
     impl res::Result {
-        pub fn pong(data: String) -> Self {
-            Self::Pong(res::Pong { pong: data })
+        /// Answers the ping, telling server when it arrived by clock of the client
+        pub fn pong(ping: action::Ping) -> Self {
+            Self::Pong(res::Pong {
+                pong: ping.ping,
+                sent_at: ping.sent_at,
+                received_at: unix_micros(SystemTime::now()),
+            })
         }
 
         pub fn purge() -> Self {
             Self::Purge(res::Purge {})
         }
 
-        pub fn shell(code: i32, stdout: Vec<u8>, stderr: Vec<u8>) -> Self {
+        pub fn heartbeat(seq: u64, sent_at: i64) -> Self {
+            Self::Heartbeat(res::Heartbeat { seq, sent_at })
+        }
+
+        pub fn shell(exec: crate::shell::Execution) -> Self {
+            let status = exec.output.status;
             Self::Shell(res::Shell {
-                code,
-                stdout,
-                stderr,
+                code: status.code().unwrap_or(-1),
+                stdout: exec.output.stdout,
+                stderr: exec.output.stderr,
+                signal: status.signal().unwrap_or_default(),
+                started_at: unix_millis(exec.started_at),
+                finished_at: unix_millis(exec.finished_at),
+            })
+        }
+
+        pub fn session_output(data: Vec<u8>) -> Self {
+            Self::SessionOutput(res::SessionOutput { data })
+        }
+
+        pub fn session_exit(code: i32, signal: i32) -> Self {
+            Self::SessionExit(res::SessionExit { code, signal })
+        }
+
+        pub fn put_file(size: u64, sha256: Vec<u8>) -> Self {
+            Self::PutFile(res::PutFile {
+                size,
+                sha256,
+                error: String::new(),
+            })
+        }
+
+        pub fn file_chunk(offset: u64, data: Vec<u8>) -> Self {
+            Self::FileChunk(res::FileChunk { offset, data })
+        }
+
+        pub fn get_file(
+            size: u64,
+            sha256: Vec<u8>,
+            mode: u32,
+            modified_at: Option<SystemTime>,
+        ) -> Self {
+            Self::GetFile(res::GetFile {
+                size,
+                sha256,
+                mode,
+                modified_at: modified_at.map_or(0, unix_millis),
+                error: String::new(),
+            })
+        }
+
+        /// Reports that the action could not be executed
+        pub fn error(e: &Error) -> Self {
+            let kind = match e.kind() {
+                ErrorKind::Spawn => res::error::Kind::SpawnFailed,
+                ErrorKind::NotFound => res::error::Kind::NotFound,
+                ErrorKind::PermissionDenied => res::error::Kind::PermissionDenied,
+                ErrorKind::Unsupported => res::error::Kind::Unsupported,
+                ErrorKind::TimedOut => res::error::Kind::TimedOut,
+                ErrorKind::Cancelled => res::error::Kind::Cancelled,
+                _ => res::error::Kind::Unknown,
+            };
+            Self::Error(res::Error {
+                kind: kind.into(),
+                message: e.to_string(),
+            })
+        }
+
+        /// Reports that the action has been executed before, but its result did not survive
+        pub fn lost() -> Self {
+            Self::Error(res::Error {
+                kind: res::error::Kind::Lost.into(),
+                message: "action has been executed already, its result is lost".to_owned(),
             })
         }
+
+        pub fn output(stream: res::output::Stream, data: Vec<u8>) -> Self {
+            Self::Output(res::Output {
+                stream: stream.into(),
+                data,
+            })
+        }
+    }
+
+    fn unix_millis(t: SystemTime) -> i64 {
+        t.duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64)
+    }
+
+    fn unix_micros(t: SystemTime) -> i64 {
+        t.duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as i64)
     }
 }
 
 struct AuthSource {
     id: Option<String>,
+    // Signed challenge, sent along with the next request only
+    proof: Option<(Vec<u8>, Vec<u8>)>,
 }
 
 impl AuthSource {
     fn new() -> Self {
-        Self { id: None }
+        Self {
+            id: None,
+            proof: None,
+        }
     }
 
     fn with_id(id: String) -> Self {
-        Self { id: Some(id) }
+        Self {
+            id: Some(id),
+            proof: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AuthInterceptor(Arc<Mutex<AuthSource>>);
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut req: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+        let mut lock = self.0.lock().unwrap();
+        if let Some(id) = lock.id.as_ref() {
+            let id = match FromStr::from_str(id) {
+                Ok(v) => Ok(v),
+                Err(_) => Err(tonic::Status::invalid_argument("client id is not valid")),
+            }?;
+            req.metadata_mut().insert("x-client-id", id);
+        }
+        if let Some((nonce, signature)) = lock.proof.take() {
+            let metadata = req.metadata_mut();
+            metadata.insert_bin("x-nonce-bin", MetadataValue::from_bytes(&nonce));
+            metadata.insert_bin("x-signature-bin", MetadataValue::from_bytes(&signature));
+        }
+        Ok(req)
     }
 }
 
@@ -60,9 +217,120 @@ struct Args {
     #[arg(short = 'c', long, default_value = "~/.notssh_id")]
     client_id: String,
 
+    /// Path to the key the client proves its identity with. Generated if missing
+    #[arg(short = 'k', long, default_value = "~/.notssh_key")]
+    identity: String,
+
+    /// Path to the journal of executed actions, so actions delivered again are not executed twice
+    #[arg(long, default_value = "~/.notssh_journal")]
+    journal: String,
+
+    /// Replace the key with a new one once connected
+    #[arg(long)]
+    rotate_key: bool,
+
+    /// Label reported to the server, may be given multiple times (example: env=prod)
+    #[arg(short = 'L', long = "label", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+
+    /// Enrollment token to register with
+    #[arg(short, long)]
+    token: Option<String>,
+
     /// Log level
     #[arg(short = 'l', long = "log-level", default_value_t = LevelFilter::Warn)]
     log_level: LevelFilter,
+
+    /// Maximum number of shell commands executed and files sent to the server at once. Pings,
+    /// sessions and files received from the server are not limited
+    #[arg(short = 'j', long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    max_actions: u16,
+
+    /// CA certificate the server is verified with. Enables TLS, endpoint must be https then
+    #[arg(long)]
+    ca: Option<String>,
+
+    /// Certificate presented to the server. Its common name becomes id of the client
+    #[arg(long, requires_all = ["ca", "key"])]
+    cert: Option<String>,
+
+    /// Private key of the certificate
+    #[arg(long, requires = "cert")]
+    key: Option<String>,
+
+    /// Name the server certificate is verified against, host of the endpoint by default
+    #[arg(long, requires = "ca")]
+    domain: Option<String>,
+}
+
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.to_owned(), v.to_owned())),
+        _ => Err("expected key=value".into()),
+    }
+}
+
+// What the client is started with, the same for every connection
+struct Settings {
+    endpoint: Endpoint,
+    id_path: String,
+    token: String,
+    labels: HashMap<String, String>,
+}
+
+/// Delay between reconnects. Grows exponentially with every failed attempt and is randomized, so
+/// clients don't reconnect all at once after server restart
+struct Backoff {
+    attempt: u32,
+    connected_at: Option<Instant>,
+}
+
+impl Backoff {
+    const MIN_DELAY: Duration = Duration::from_secs(1);
+    const MAX_DELAY: Duration = Duration::from_secs(300);
+    // Connection dropped sooner than this counts as a failed attempt, so a server which accepts
+    // clients and drops them right away is not hammered
+    const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+    fn new() -> Self {
+        Self {
+            attempt: 0,
+            connected_at: None,
+        }
+    }
+
+    fn connected(&mut self) {
+        self.connected_at = Some(Instant::now());
+    }
+
+    fn next(&mut self) -> Duration {
+        self.next_at(Instant::now())
+    }
+
+    // Same as `next`, with current time given
+    fn next_at(&mut self, now: Instant) -> Duration {
+        if self
+            .connected_at
+            .take()
+            .is_some_and(|t| now.saturating_duration_since(t) >= Self::STABLE_AFTER)
+        {
+            self.attempt = 0;
+        }
+        let delay = Self::MIN_DELAY
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(Self::MAX_DELAY);
+        self.attempt = self.attempt.saturating_add(1);
+        // Anywhere between half and full delay
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+// Replaces leading `~` with home directory, or the current one if there is no home
+fn expand_home(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) => std::env::var("HOME").unwrap_or_else(|_| ".".to_owned()) + rest,
+        None => path.to_owned(),
+    }
 }
 
 #[tokio::main]
@@ -83,73 +351,684 @@ async fn main() -> Result<(), anyhow::Error> {
         Err(_) => AuthSource::new(),
     };
     let auth_source = Arc::new(Mutex::new(auth_source));
-    let chan = Endpoint::from_str(&args.endpoint)
-        .unwrap()
+    let key_path = expand_home(&args.identity);
+    let mut keypair =
+        key::Keypair::load(&key_path).with_context(|| format!("cannot load key {}", key_path))?;
+    if args.rotate_key {
+        keypair
+            .rotate()
+            .with_context(|| "cannot generate new key")?;
+    }
+    let journal_path = expand_home(&args.journal);
+    let mut journal = journal::Journal::load(&journal_path)
+        .with_context(|| format!("cannot load journal {}", journal_path))?;
+    // Keepalive lets a dead connection end the Poll stream instead of hanging forever
+    let mut endpoint = Endpoint::from_str(&args.endpoint)
+        .with_context(|| "invalid endpoint")?
+        .http2_keep_alive_interval(KEEPALIVE_INTERVAL)
+        .keep_alive_timeout(KEEPALIVE_TIMEOUT)
+        .keep_alive_while_idle(true);
+    if let Some(ca) = &args.ca {
+        let ca = std::fs::read(ca).with_context(|| format!("cannot read CA certificate {}", ca))?;
+        let mut tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca));
+        if let (Some(cert), Some(key)) = (&args.cert, &args.key) {
+            let cert =
+                std::fs::read(cert).with_context(|| format!("cannot read certificate {}", cert))?;
+            let key = std::fs::read(key).with_context(|| format!("cannot read key {}", key))?;
+            tls = tls.identity(Identity::from_pem(cert, key));
+        }
+        if let Some(domain) = &args.domain {
+            tls = tls.domain_name(domain);
+        }
+        endpoint = endpoint
+            .tls_config(tls)
+            .with_context(|| "invalid TLS configuration")?;
+    }
+
+    let settings = Settings {
+        endpoint,
+        id_path,
+        token: args.token.unwrap_or_default(),
+        labels: args.labels.into_iter().collect(),
+    };
+    let shared = Shared {
+        permits: Arc::new(Semaphore::new(args.max_actions as usize)),
+        running: Running::default(),
+        outbox: Outbox::default(),
+    };
+    let mut backoff = Backoff::new();
+    loop {
+        if let Err(e) = run(
+            &settings,
+            auth_source.clone(),
+            &mut keypair,
+            &shared,
+            &mut journal,
+            &mut backoff,
+        )
+        .await
+        {
+            log::error!("{:#}", e);
+        }
+        let delay = backoff.next();
+        log::info!("reconnecting in {:.1}s", delay.as_secs_f64());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Connects to server and executes actions until the connection is lost
+async fn run(
+    settings: &Settings,
+    auth_source: Arc<Mutex<AuthSource>>,
+    keypair: &mut key::Keypair,
+    shared: &Shared,
+    journal: &mut journal::Journal,
+    backoff: &mut Backoff,
+) -> Result<(), anyhow::Error> {
+    let chan = settings
+        .endpoint
         .connect()
         .await
         .with_context(|| "cannot connect")?;
 
-    let a = auth_source.clone();
-    let mut client = NotSshClient::with_interceptor(chan, move |mut req: tonic::Request<()>| {
-        let lock = a.lock().unwrap();
-        if let Some(id) = lock.id.as_ref() {
-            let id = match FromStr::from_str(id) {
-                Ok(v) => Ok(v),
-                Err(_) => Err(tonic::Status::invalid_argument("client id is not valid")),
-            }?;
-            req.metadata_mut().insert("x-client-id", id);
-        }
-        Ok(req)
-    });
+    let mut client = NotSshClient::with_interceptor(chan, AuthInterceptor(auth_source.clone()));
 
     if auth_source.lock().unwrap().id.is_none() {
-        let req = tonic::Request::new(RegisterRequest {});
+        let req = tonic::Request::new(RegisterRequest {
+            public_key: keypair.public_key(),
+            token: settings.token.clone(),
+        });
         let res = client
             .register(req)
             .await
-            .map_err(|e| error::Error::from(e))
+            .map_err(error::Error::from)
             .with_context(|| "cannot register client")?
             .into_inner();
-        let _ = std::fs::write(id_path, &res.id)?;
+        std::fs::write(&settings.id_path, &res.id)?;
+        log::info!("registered with id '{}'", res.id);
         auth_source.lock().unwrap().id = Some(res.id);
     }
 
+    let nonce = challenge(&mut client, &auth_source, keypair).await?;
+    if let Some((public_key, signature)) = keypair.sign_next(&nonce) {
+        let req = RotateKeyRequest {
+            public_key,
+            signature,
+        };
+        client
+            .rotate_key(req)
+            .await
+            .map_err(error::Error::from)
+            .with_context(|| "cannot rotate key")?;
+        keypair.commit().with_context(|| "cannot save new key")?;
+        log::info!("key rotated");
+        challenge(&mut client, &auth_source, keypair).await?;
+    }
+
     let (tx, rx) = unbounded_channel();
-    let req = UnboundedReceiverStream::new(rx);
-    let mut res = client
-        .poll(req)
-        .await
-        .map_err(|e| error::Error::from(e))?
-        .into_inner();
+    let windows = window::Windows::default();
+    let mut res = match client.poll(UnboundedReceiverStream::new(rx)).await {
+        Ok(res) => res.into_inner(),
+        // Client registered before it had a key, so the key has to be set first
+        Err(s) if s.code() == tonic::Code::FailedPrecondition => {
+            log::warn!("server has no key of this client, setting it");
+            keypair.enroll();
+            return Ok(());
+        }
+        // Server has not noticed yet that the previous connection is gone, it will eventually
+        Err(s) if s.code() == tonic::Code::AlreadyExists => {
+            log::warn!("server still considers this client connected");
+            return Ok(());
+        }
+        // Client has been removed from server while offline, so it starts from scratch
+        Err(s) if s.code() == tonic::Code::NotFound => {
+            log::warn!("client is not known to server, registering again");
+            auth_source.lock().unwrap().id = None;
+            return Ok(());
+        }
+        Err(s) => return Err(error::Error::from(s)).with_context(|| "cannot start polling"),
+    };
+    log::info!("connected to server");
+    backoff.connected();
+    shared.outbox.connect(tx.clone());
+    tokio::spawn(report_facts(tx.clone(), settings.labels.clone()));
 
-    while let Some(act) = res.message().await? {
-        let cmd = act
-            .command
-            .ok_or(error::Error::bad_request("action contains no command"))?;
+    let sessions = Sessions::default();
+    let mut uploads: HashMap<String, file::Upload> = HashMap::new();
+    loop {
+        // Upload whose content stops coming is given up once its deadline passes
+        let deadline = uploads.values().filter_map(file::Upload::deadline).min();
+        let expired = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now));
+        let act = tokio::select! {
+            act = res.message() => match act? {
+                Some(act) => act,
+                None => break,
+            },
+            _ = expired, if deadline.is_some() => {
+                let now = Instant::now();
+                let expired: Vec<_> = uploads
+                    .iter()
+                    .filter(|(_, u)| u.deadline().is_some_and(|d| d <= now))
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                for id in expired {
+                    log::error!("upload '{}' has not finished in time", id);
+                    uploads.remove(&id);
+                    let e = error::Error::timed_out("gave up after deadline passed");
+                    tx.send(Res {
+                        id,
+                        result: Some(res::Result::error(&e)),
+                    })?;
+                }
+                continue;
+            }
+        };
+        // Command added to the protocol after this client was built is decoded as none
+        let cmd = match act.command {
+            Some(cmd) => cmd,
+            None => {
+                log::warn!("action '{}' contains no known command", act.id);
+                let e = error::Error::unsupported("command is not supported by client");
+                tx.send(Res {
+                    id: act.id,
+                    result: Some(res::Result::error(&e)),
+                })?;
+                continue;
+            }
+        };
+        // Action delivered again after a lost connection is not executed twice. If it still
+        // runs, its result comes through the outbox
+        let executes = matches!(
+            cmd,
+            notssh::action::Command::Shell(_)
+                | notssh::action::Command::Purge(_)
+                | notssh::action::Command::Session(_)
+        );
+        if (executes || matches!(cmd, notssh::action::Command::PutFile(_)))
+            && journal.contains(&act.id)
+        {
+            if !shared.running.lock().unwrap().contains_key(&act.id) {
+                log::warn!("action '{}' has been executed already", act.id);
+                tx.send(Res {
+                    id: act.id,
+                    result: Some(res::Result::lost()),
+                })?;
+            }
+            continue;
+        }
+        if executes {
+            if let Err(e) = journal.record(&act.id) {
+                log::error!("cannot record '{}' in journal: {}", act.id, e);
+                tx.send(Res {
+                    id: act.id,
+                    result: Some(res::Result::error(&e)),
+                })?;
+                continue;
+            }
+        }
         let res = match cmd {
             notssh::action::Command::Ping(ping) => Res {
                 id: act.id,
-                result: Some(res::Result::pong(ping.ping)),
+                result: Some(res::Result::pong(ping)),
             },
             notssh::action::Command::Purge(_) => Res {
                 id: act.id,
-                result: Some(res::Result::purge()),
+                result: Some(with_deadline(act.timeout, async { res::Result::purge() }).await),
+            },
+            notssh::action::Command::Heartbeat(hb) => Res {
+                id: act.id,
+                result: Some(res::Result::heartbeat(hb.seq, hb.sent_at)),
             },
             notssh::action::Command::Shell(shell) => {
-                let runner = shell::Runner::new(shell.cmd, shell.args, shell.stdin);
-                let out = runner.run().await?;
-                let code = match out.status.code() {
-                    Some(c) => c,
-                    None => -1,
+                let mut runner = shell::Runner::new(shell.cmd, shell.args, shell.stdin);
+                if shell.stream {
+                    let window = windows.open(&act.id, act.window);
+                    runner = runner.stream_to(act.id.clone(), tx.clone(), window);
+                }
+                if act.timeout != 0 {
+                    runner = runner.timeout(Duration::from_millis(act.timeout));
+                }
+                let id = act.id.clone();
+                let work = async move {
+                    match runner.run().await {
+                        Ok(exec) => res::Result::shell(exec),
+                        Err(e) => {
+                            log::error!("cannot execute shell command for '{}': {}", id, e);
+                            res::Result::error(&e)
+                        }
+                    }
                 };
-                let res = res::Result::shell(code, out.stdout, out.stderr);
-                Res {
-                    id: act.id,
-                    result: Some(res),
+                spawn_action(act.id, work, shared);
+                continue;
+            }
+            notssh::action::Command::Cancel(_) => {
+                cancel(&shared.running, &sessions, &mut uploads, &act.id);
+                continue;
+            }
+            notssh::action::Command::Credit(credit) => {
+                windows.credit(&credit.id, credit.chunks);
+                continue;
+            }
+            notssh::action::Command::Session(s) => {
+                let (events_tx, events_rx) = unbounded_channel();
+                sessions.lock().unwrap().insert(act.id.clone(), events_tx);
+                tokio::spawn(run_session(
+                    act.id.clone(),
+                    s,
+                    events_rx,
+                    tx.clone(),
+                    windows.open(&act.id, act.window),
+                    sessions.clone(),
+                ));
+                continue;
+            }
+            notssh::action::Command::SessionInput(input) => {
+                send_event(&sessions, &act.id, session::Event::Input(input.data));
+                continue;
+            }
+            notssh::action::Command::SessionResize(size) => {
+                send_event(
+                    &sessions,
+                    &act.id,
+                    session::Event::Resize(size.rows, size.cols),
+                );
+                continue;
+            }
+            notssh::action::Command::SessionClose(_) => {
+                send_event(&sessions, &act.id, session::Event::Close);
+                continue;
+            }
+            notssh::action::Command::PutFile(put) => {
+                let deadline =
+                    (act.timeout != 0).then(|| Instant::now() + Duration::from_millis(act.timeout));
+                match file::Upload::create(&act.id, put, deadline).await {
+                    Ok(upload) => {
+                        uploads.insert(act.id.clone(), upload);
+                    }
+                    Err(e) => {
+                        log::error!("cannot create file for '{}': {}", act.id, e);
+                        tx.send(Res {
+                            id: act.id,
+                            result: Some(res::Result::error(&e)),
+                        })?;
+                        continue;
+                    }
+                }
+                match finish_upload(&mut uploads, &act.id, journal).await {
+                    Some(res) => Res {
+                        id: act.id,
+                        result: Some(res),
+                    },
+                    None => continue,
+                }
+            }
+            notssh::action::Command::GetFile(get) => {
+                let id = act.id.clone();
+                let chunks_tx = tx.clone();
+                let window = windows.open(&act.id, act.window);
+                let work = async move {
+                    match file::send(&id, get, &chunks_tx, &window).await {
+                        Ok(res) => res,
+                        Err(e) => {
+                            log::error!("cannot read file for '{}': {}", id, e);
+                            res::Result::error(&e)
+                        }
+                    }
+                };
+                spawn_action(act.id, with_deadline(act.timeout, work), shared);
+                continue;
+            }
+            notssh::action::Command::FileChunk(chunk) => {
+                // Chunks of a failed upload keep coming, they are dropped here
+                let upload = match uploads.get_mut(&act.id) {
+                    Some(u) => u,
+                    None => continue,
+                };
+                if let Err(e) = upload.write(chunk).await {
+                    log::error!("cannot write file for '{}': {}", act.id, e);
+                    uploads.remove(&act.id);
+                    tx.send(Res {
+                        id: act.id,
+                        result: Some(res::Result::error(&e)),
+                    })?;
+                    continue;
+                }
+                match finish_upload(&mut uploads, &act.id, journal).await {
+                    Some(res) => Res {
+                        id: act.id,
+                        result: Some(res),
+                    },
+                    None => continue,
                 }
             }
         };
         tx.send(res)?;
     }
+    log::warn!("server closed connection");
     Ok(())
 }
+
+// Fails the action once its deadline passes, if it has one
+async fn with_deadline(timeout: u64, work: impl Future<Output = res::Result>) -> res::Result {
+    if timeout == 0 {
+        return work.await;
+    }
+    let timeout = Duration::from_millis(timeout);
+    match tokio::time::timeout(timeout, work).await {
+        Ok(res) => res,
+        Err(_) => res::Result::error(&error::Error::timed_out(format!(
+            "gave up after {:.1}s deadline passed",
+            timeout.as_secs_f64()
+        ))),
+    }
+}
+
+// Gets a challenge and signs it, so the next request proves the client holds its key
+async fn challenge(
+    client: &mut NotSshClient<InterceptedService<Channel, AuthInterceptor>>,
+    auth_source: &Mutex<AuthSource>,
+    keypair: &key::Keypair,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let nonce = client
+        .challenge(ChallengeRequest {})
+        .await
+        .map_err(error::Error::from)
+        .with_context(|| "cannot get challenge")?
+        .into_inner()
+        .nonce;
+    auth_source.lock().unwrap().proof = Some((nonce.clone(), keypair.sign(&nonce)));
+    Ok(nonce)
+}
+
+// Finishes the upload once all of its content has arrived. Upload is recorded in the journal
+// only once the file is in place, so an interrupted one starts over when delivered again
+async fn finish_upload(
+    uploads: &mut HashMap<String, file::Upload>,
+    id: &str,
+    journal: &mut journal::Journal,
+) -> Option<res::Result> {
+    if !uploads.get(id)?.is_complete() {
+        return None;
+    }
+    let upload = uploads.remove(id)?;
+    Some(match upload.finish().await {
+        Ok((size, sha256)) => {
+            if let Err(e) = journal.record(id) {
+                log::error!("cannot record '{}' in journal: {}", id, e);
+            }
+            res::Result::put_file(size, sha256)
+        }
+        Err(e) => {
+            log::error!("cannot finish file for '{}': {}", id, e);
+            res::Result::error(&e)
+        }
+    })
+}
+
+// Actions executed aside of the receiving loop, by id. Sender cancels the action
+type Running = Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>;
+
+// Results of actions outlive the connection the actions came with. They are sent over the
+// current connection, or kept until there is one
+#[derive(Clone, Default)]
+struct Outbox(Arc<Mutex<OutboxState>>);
+
+#[derive(Default)]
+struct OutboxState {
+    tx: Option<UnboundedSender<Res>>,
+    undelivered: VecDeque<Res>,
+    // Encoded size of undelivered results
+    size: usize,
+}
+
+impl Outbox {
+    // Sends results kept so far over the new connection, and the rest as they are ready
+    fn connect(&self, tx: UnboundedSender<Res>) {
+        let mut state = self.0.lock().unwrap();
+        state.tx = Some(tx);
+        state.size = 0;
+        for res in std::mem::take(&mut state.undelivered) {
+            state.send(res);
+        }
+    }
+
+    fn send(&self, res: Res) {
+        self.0.lock().unwrap().send(res);
+    }
+}
+
+impl OutboxState {
+    fn send(&mut self, res: Res) {
+        let res = match &self.tx {
+            Some(tx) => match tx.send(res) {
+                Ok(()) => return,
+                Err(e) => e.0,
+            },
+            None => res,
+        };
+        self.tx = None;
+        self.size += res.encoded_len();
+        self.undelivered.push_back(res);
+        while self.size > OUTBOX_LIMIT {
+            let dropped = match self.undelivered.pop_front() {
+                Some(res) => res,
+                None => break,
+            };
+            log::warn!("too many results kept, dropping result of '{}'", dropped.id);
+            self.size -= dropped.encoded_len();
+        }
+    }
+}
+
+// Shared across connections, so actions left over from a lost one still count and can be
+// cancelled, and their results are not lost
+struct Shared {
+    permits: Arc<Semaphore>,
+    running: Running,
+    outbox: Outbox,
+}
+
+// Executes the action once a permit is free, so slow actions hold up neither each other nor
+// pings. Results are sent as they are ready, the server tells them apart by id
+fn spawn_action(
+    id: String,
+    work: impl Future<Output = res::Result> + Send + 'static,
+    shared: &Shared,
+) {
+    let (cancel_tx, cancel_rx) = oneshot::channel::<()>();
+    shared.running.lock().unwrap().insert(id.clone(), cancel_tx);
+    let (permits, running, outbox) = (
+        shared.permits.clone(),
+        shared.running.clone(),
+        shared.outbox.clone(),
+    );
+    tokio::spawn(async move {
+        let work = async {
+            let _permit = permits.acquire_owned().await;
+            work.await
+        };
+        // Cancelled action is dropped whether it is queued or running
+        let res = tokio::select! {
+            res = work => Some(res),
+            Ok(()) = cancel_rx => None,
+        };
+        running.lock().unwrap().remove(&id);
+        if let Some(res) = res {
+            outbox.send(Res {
+                id,
+                result: Some(res),
+            });
+        }
+    });
+}
+
+// Stops whatever runs under the action. Server considers the action done already, so nothing
+// has to be reported unless the command itself does
+fn cancel(
+    running: &Running,
+    sessions: &Sessions,
+    uploads: &mut HashMap<String, file::Upload>,
+    id: &str,
+) {
+    log::info!("cancelling '{}'", id);
+    // File reads run aside of the loop like shell commands, so they are stopped the same way
+    if let Some(cancel) = running.lock().unwrap().remove(id) {
+        let _ = cancel.send(());
+        return;
+    }
+    if uploads.remove(id).is_some() {
+        return;
+    }
+    if sessions.lock().unwrap().contains_key(id) {
+        send_event(sessions, id, session::Event::Close);
+    } else {
+        log::debug!("'{}' is not running", id);
+    }
+}
+
+// Facts are sent on connect and then periodically, until the connection is gone
+async fn report_facts(tx: UnboundedSender<Res>, labels: HashMap<String, String>) {
+    let mut interval = tokio::time::interval(FACTS_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tx.closed() => return,
+        }
+        let labels = labels.clone();
+        let facts = match tokio::task::spawn_blocking(|| facts::collect(labels)).await {
+            Ok(facts) => facts,
+            Err(e) => {
+                log::error!("cannot collect facts: {}", e);
+                continue;
+            }
+        };
+        let res = Res {
+            id: String::new(),
+            result: Some(res::Result::Facts(facts)),
+        };
+        if tx.send(res).is_err() {
+            return;
+        }
+    }
+}
+
+type Sessions = Arc<Mutex<HashMap<String, UnboundedSender<session::Event>>>>;
+
+fn send_event(sessions: &Sessions, id: &str, event: session::Event) {
+    match sessions.lock().unwrap().get(id) {
+        Some(s) => {
+            let _ = s.send(event);
+        }
+        None => log::debug!("session '{}' does not exist", id),
+    }
+}
+
+async fn run_session(
+    id: String,
+    s: notssh::action::Session,
+    events: UnboundedReceiver<session::Event>,
+    tx: UnboundedSender<Res>,
+    window: window::Window,
+    sessions: Sessions,
+) {
+    log::info!("session '{}' opened", id);
+    if let Err(e) = session::run(id.clone(), s, events, tx.clone(), window).await {
+        log::error!("session '{}' failed: {}", id, e);
+        // Let the operator know the session is over
+        let _ = tx.send(Res {
+            id: id.clone(),
+            result: Some(res::Result::error(&e)),
+        });
+    }
+    sessions.lock().unwrap().remove(&id);
+    log::info!("session '{}' closed", id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_up_to_limit() {
+        let mut backoff = Backoff::new();
+        for attempt in 0..20 {
+            let full = Backoff::MIN_DELAY
+                .saturating_mul(2u32.saturating_pow(attempt))
+                .min(Backoff::MAX_DELAY);
+            let delay = backoff.next();
+            assert!(
+                delay >= full / 2 && delay <= full,
+                "attempt {}: {:?}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_keeps_growing_after_short_connection() {
+        let mut backoff = Backoff::new();
+        for _ in 0..5 {
+            backoff.next();
+        }
+        backoff.connected();
+        assert!(backoff.next() >= Backoff::MIN_DELAY * 16);
+    }
+
+    #[test]
+    fn backoff_resets_after_stable_connection() {
+        let mut backoff = Backoff::new();
+        for _ in 0..5 {
+            backoff.next();
+        }
+        backoff.connected();
+        assert!(backoff.next_at(Instant::now() + Backoff::STABLE_AFTER) <= Backoff::MIN_DELAY);
+        // Reset happens once per connection
+        assert!(backoff.next() >= Backoff::MIN_DELAY);
+    }
+
+    fn result(id: usize, size: usize) -> Res {
+        Res {
+            id: id.to_string(),
+            result: Some(res::Result::Output(res::Output {
+                stream: 0,
+                data: vec![0; size],
+            })),
+        }
+    }
+
+    #[test]
+    fn outbox_keeps_results_until_connected() {
+        let outbox = Outbox::default();
+        outbox.send(result(0, 0));
+        outbox.send(result(1, 0));
+        let (tx, mut rx) = unbounded_channel();
+        outbox.connect(tx);
+        outbox.send(result(2, 0));
+        for id in ["0", "1", "2"] {
+            assert_eq!(rx.try_recv().unwrap().id, id);
+        }
+        // Result sent over a lost connection is kept for the next one
+        drop(rx);
+        outbox.send(result(3, 0));
+        let (tx, mut rx) = unbounded_channel();
+        outbox.connect(tx);
+        assert_eq!(rx.try_recv().unwrap().id, "3");
+    }
+
+    #[test]
+    fn outbox_drops_oldest_results_over_limit() {
+        let outbox = Outbox::default();
+        let size = OUTBOX_LIMIT / 4;
+        for id in 0..6 {
+            outbox.send(result(id, size));
+        }
+        assert!(outbox.0.lock().unwrap().size <= OUTBOX_LIMIT);
+        let (tx, mut rx) = unbounded_channel();
+        outbox.connect(tx);
+        let ids: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|res| res.id)
+            .collect();
+        assert_eq!(ids, ["3", "4", "5"]);
+    }
+}
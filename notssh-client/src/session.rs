@@ -0,0 +1,209 @@
+use std::{
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::ExitStatusExt,
+    },
+    path::Path,
+    process::Stdio,
+    ptr,
+    time::Duration,
+};
+
+use notssh_util::error;
+use tokio::{
+    io::unix::AsyncFd,
+    process::{Child, Command},
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{
+    notssh::{res, Res},
+    window::Window,
+};
+
+const CHUNK_SIZE: usize = 16384;
+// How long to wait for the rest of output after the shell exited
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub enum Event {
+    Input(Vec<u8>),
+    Resize(u32, u32),
+    Close,
+}
+
+struct Pty {
+    master: AsyncFd<OwnedFd>,
+}
+
+impl Pty {
+    async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.master.readable().await?;
+            let r = guard.try_io(|fd| {
+                let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if n < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(n as usize)
+            });
+            if let Ok(r) = r {
+                return r;
+            }
+        }
+    }
+
+    async fn write_all(&self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let mut guard = self.master.writable().await?;
+            let r = guard.try_io(|fd| {
+                let n = unsafe { libc::write(fd.as_raw_fd(), data.as_ptr().cast(), data.len()) };
+                if n < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(n as usize)
+            });
+            if let Ok(r) = r {
+                data = &data[r?..];
+            }
+        }
+        Ok(())
+    }
+
+    fn resize(&self, rows: u32, cols: u32) -> io::Result<()> {
+        let ws = winsize(rows, cols);
+        if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &ws) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+fn winsize(rows: u32, cols: u32) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows as u16,
+        ws_col: cols as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn set_flags(fd: &OwnedFd, fl: libc::c_int, fd_fl: libc::c_int) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | fl) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | fd_fl) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Allocates a terminal and starts login shell of the current user on it
+fn spawn(rows: u32, cols: u32, term: &str) -> io::Result<(Pty, Child)> {
+    let (mut master, mut slave) = (-1, -1);
+    let ws = winsize(rows, cols);
+    if unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &ws) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    set_flags(&master, libc::O_NONBLOCK, libc::FD_CLOEXEC)?;
+    set_flags(&slave, 0, libc::FD_CLOEXEC)?;
+
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_owned());
+    let name = Path::new(&shell)
+        .file_name()
+        .map_or("sh".into(), |n| n.to_string_lossy());
+    let mut cmd = Command::new(&shell);
+    // Leading dash tells the shell it is a login shell
+    cmd.arg0(format!("-{}", name))
+        .env("TERM", if term.is_empty() { "xterm" } else { term })
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave))
+        .kill_on_drop(true);
+    if let Ok(home) = std::env::var("HOME") {
+        cmd.current_dir(home);
+    }
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = cmd.spawn()?;
+    // Parent must not keep the terminal open, otherwise reading never ends
+    drop(cmd);
+
+    Ok((
+        Pty {
+            master: AsyncFd::new(master)?,
+        },
+        child,
+    ))
+}
+
+pub async fn run(
+    id: String,
+    session: crate::notssh::action::Session,
+    mut events: UnboundedReceiver<Event>,
+    tx: UnboundedSender<Res>,
+    window: Window,
+) -> error::Result<()> {
+    let (pty, mut child) = spawn(session.rows, session.cols, &session.term)
+        .map_err(|e| error::Error::spawn(format!("cannot start session: {}", e)))?;
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut closed = false;
+    let mut exited = false;
+    // Terminal is read only once there is room for its output, input is taken meanwhile
+    let mut room = false;
+    loop {
+        tokio::select! {
+            r = window.acquire(&tx), if !room => {
+                r?;
+                room = true;
+            },
+            n = pty.read(&mut buf), if room => match n {
+                Ok(0) => break,
+                Ok(n) => {
+                    room = false;
+                    tx.send(Res {
+                        id: id.clone(),
+                        result: Some(res::Result::session_output(buf[..n].to_vec())),
+                    })?
+                },
+                // EIO means there is nobody on the other side of the terminal anymore
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+                Err(e) => return Err(e.into()),
+            },
+            ev = events.recv(), if !closed => match ev {
+                Some(Event::Input(data)) => pty.write_all(&data).await?,
+                Some(Event::Resize(rows, cols)) => pty.resize(rows, cols)?,
+                Some(Event::Close) | None => {
+                    closed = true;
+                    if let Some(pid) = child.id() {
+                        unsafe { libc::kill(-(pid as i32), libc::SIGHUP) };
+                    }
+                },
+            },
+            _ = child.wait(), if !exited => exited = true,
+            // Someone else holds the terminal after the shell exited
+            _ = tokio::time::sleep(DRAIN_TIMEOUT), if exited && room => break,
+        }
+    }
+
+    let status = child.wait().await?;
+    tx.send(Res {
+        id,
+        result: Some(res::Result::session_exit(
+            status.code().unwrap_or(-1),
+            status.signal().unwrap_or_default(),
+        )),
+    })?;
+    Ok(())
+}
@@ -1,28 +1,159 @@
-use std::process::{Output, Stdio};
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    process::{Output, Stdio},
+    time::{Duration, SystemTime},
+};
 
 use notssh_util::error;
-use tokio::{io::AsyncWriteExt, process::Command};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+    sync::mpsc::UnboundedSender,
+};
+
+use crate::{
+    notssh::{res, Res},
+    window::Window,
+};
+
+const CHUNK_SIZE: usize = 16384;
 
 pub struct Runner {
     cmd: Command,
     stdin: Vec<u8>,
+    output: Option<(String, UnboundedSender<Res>, Window)>,
+    timeout: Option<Duration>,
+}
+
+pub struct Execution {
+    pub output: Output,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
 }
 
 impl Runner {
     pub fn new(cmd: String, args: Vec<String>, stdin: Vec<u8>) -> Self {
-        let mut cmd = Command::new(cmd);
-        cmd.args(args).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut cmd = std::process::Command::new(cmd);
+        // Command leads its own process group, so whatever it starts is killed along with it
+        cmd.args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
         if !stdin.is_empty() {
             cmd.stdin(Stdio::piped());
         }
-        Self { cmd, stdin }
+        let mut cmd = Command::from(cmd);
+        cmd.kill_on_drop(true);
+        Self {
+            cmd,
+            stdin,
+            output: None,
+            timeout: None,
+        }
+    }
+
+    /// Send output to server as it appears, as fast as the window lets it. It's still collected
+    /// for the result, so it can be fetched later
+    pub fn stream_to(self, id: String, tx: UnboundedSender<Res>, window: Window) -> Self {
+        Self {
+            output: Some((id, tx, window)),
+            ..self
+        }
+    }
+
+    /// Kill the command if it does not finish in time
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
     }
 
-    pub async fn run(mut self) -> error::Result<Output> {
-        let mut child = self.cmd.spawn()?;
+    /// Command is killed along with whatever it started if the returned future is dropped
+    pub async fn run(mut self) -> error::Result<Execution> {
+        let started_at = SystemTime::now();
+        let child = self.cmd.spawn().map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => error::Error::not_found(e.to_string()),
+            io::ErrorKind::PermissionDenied => error::Error::permission_denied(e.to_string()),
+            _ => error::Error::spawn(format!("cannot start process: {}", e)),
+        })?;
+        let mut group = Group(child.id().map(|pid| pid as libc::pid_t));
+
+        let output = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.execute(child))
+                .await
+                .map_err(|_| {
+                    error::Error::timed_out(format!(
+                        "killed after {:.1}s deadline passed",
+                        timeout.as_secs_f64()
+                    ))
+                })?,
+            None => self.execute(child).await,
+        }?;
+        // Whatever the command left running in background is its own business
+        group.0 = None;
+        Ok(Execution {
+            output,
+            started_at,
+            finished_at: SystemTime::now(),
+        })
+    }
+
+    async fn execute(self, mut child: Child) -> error::Result<Output> {
         if let Some(mut stdin) = child.stdin.take() {
             stdin.write_all(&self.stdin).await?;
         }
-        child.wait_with_output().await.map_err(From::from)
+        let output = match self.output {
+            Some((id, tx, window)) => {
+                let stdout = child.stdout.take().unwrap();
+                let stderr = child.stderr.take().unwrap();
+                let (stdout, stderr) = tokio::try_join!(
+                    forward(stdout, &id, res::output::Stream::Stdout, &tx, &window),
+                    forward(stderr, &id, res::output::Stream::Stderr, &tx, &window),
+                )?;
+                Output {
+                    status: child.wait().await?,
+                    stdout,
+                    stderr,
+                }
+            }
+            None => child.wait_with_output().await?,
+        };
+        Ok(output)
+    }
+}
+
+// Process group of a command, killed on drop unless released
+struct Group(Option<libc::pid_t>);
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.0 {
+            unsafe { libc::killpg(pgid, libc::SIGKILL) };
+        }
+    }
+}
+
+async fn forward(
+    mut r: impl AsyncRead + Unpin,
+    id: &str,
+    stream: res::output::Stream,
+    tx: &UnboundedSender<Res>,
+    window: &Window,
+) -> error::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(output);
+        }
+        output.extend_from_slice(&buf[..n]);
+        window.acquire(tx).await?;
+        tx.send(Res {
+            id: id.to_owned(),
+            result: Some(res::Result::output(stream, buf[..n].to_vec())),
+        })?;
     }
 }
@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use notssh_util::error;
+use tokio::sync::{mpsc::UnboundedSender, Semaphore};
+
+use crate::notssh::Res;
+
+/// Keeps output of actions from being sent faster than whoever watches it takes it. Sender takes
+/// a permit for every chunk, which the server gives back with credit once the chunk is taken
+#[derive(Clone, Default)]
+pub struct Windows(Arc<Mutex<HashMap<String, Arc<Semaphore>>>>);
+
+impl Windows {
+    /// Opens window of the given size for output of the action, 0 means there is no limit.
+    /// Window is closed once dropped
+    pub fn open(&self, id: &str, size: u32) -> Window {
+        let permits = Arc::new(Semaphore::new(size as usize));
+        if size == 0 {
+            permits.close();
+        }
+        self.0
+            .lock()
+            .unwrap()
+            .insert(id.to_owned(), permits.clone());
+        Window {
+            windows: self.clone(),
+            id: id.to_owned(),
+            permits,
+        }
+    }
+
+    /// Gives back room for chunks taken. No chunks mean nobody watches the output anymore, so
+    /// it is not held back
+    pub fn credit(&self, id: &str, chunks: u32) {
+        let permits = match self.0.lock().unwrap().get(id) {
+            Some(p) => p.clone(),
+            None => return,
+        };
+        if chunks == 0 {
+            permits.close();
+        } else {
+            permits.add_permits(chunks as usize);
+        }
+    }
+}
+
+pub struct Window {
+    windows: Windows,
+    id: String,
+    permits: Arc<Semaphore>,
+}
+
+impl Window {
+    /// Waits for room for another chunk. Room is not given anymore once the connection is gone
+    pub async fn acquire(&self, tx: &UnboundedSender<Res>) -> error::Result<()> {
+        tokio::select! {
+            permit = self.permits.acquire() => {
+                // Closed window lets everything through
+                if let Ok(permit) = permit {
+                    permit.forget();
+                }
+                Ok(())
+            }
+            _ = tx.closed() => Err(error::Error::io("connection is closed")),
+        }
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        let mut windows = self.windows.0.lock().unwrap();
+        if let Some(p) = windows.get(&self.id) {
+            if Arc::ptr_eq(p, &self.permits) {
+                windows.remove(&self.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    // Whether there is room for a chunk right away
+    async fn has_room(window: &Window, tx: &UnboundedSender<Res>) -> bool {
+        tokio::select! {
+            biased;
+            r = window.acquire(tx) => r.is_ok(),
+            _ = std::future::ready(()) => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn window_holds_output_back_until_credited() {
+        let (tx, _rx) = unbounded_channel();
+        let windows = Windows::default();
+        let window = windows.open("a", 2);
+        assert!(has_room(&window, &tx).await);
+        assert!(has_room(&window, &tx).await);
+        assert!(!has_room(&window, &tx).await);
+        // Credit for another action does not count
+        windows.credit("b", 1);
+        assert!(!has_room(&window, &tx).await);
+        windows.credit("a", 1);
+        assert!(has_room(&window, &tx).await);
+        assert!(!has_room(&window, &tx).await);
+    }
+
+    #[tokio::test]
+    async fn window_is_lifted_without_watcher() {
+        let (tx, _rx) = unbounded_channel();
+        let windows = Windows::default();
+        let unlimited = windows.open("a", 0);
+        let lifted = windows.open("b", 1);
+        windows.credit("b", 0);
+        for _ in 0..10 {
+            assert!(has_room(&unlimited, &tx).await);
+            assert!(has_room(&lifted, &tx).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn window_is_closed_when_dropped() {
+        let windows = Windows::default();
+        drop(windows.open("a", 1));
+        assert!(windows.0.lock().unwrap().is_empty());
+        // Window reopened under the same id is not closed by the old one
+        let old = windows.open("a", 1);
+        let _new = windows.open("a", 1);
+        drop(old);
+        assert!(windows.0.lock().unwrap().contains_key("a"));
+    }
+}
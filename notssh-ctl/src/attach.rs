@@ -0,0 +1,122 @@
+use std::io::{self, Read, Write};
+
+use anyhow::Context;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::transport::Channel;
+
+use crate::notssh_cli::{
+    attach_request, attach_response, not_ssh_cli_client::NotSshCliClient, AttachRequest,
+    AttachResponse,
+};
+
+// Puts terminal in raw mode and restores previous settings when dropped
+struct RawMode {
+    orig: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        unsafe {
+            let mut orig = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut orig) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = orig;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { orig })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.orig) };
+    }
+}
+
+fn window_size() -> (u32, u32) {
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } < 0 || ws.ws_row == 0
+    {
+        return (24, 80);
+    }
+    (ws.ws_row as u32, ws.ws_col as u32)
+}
+
+fn request(event: attach_request::Event) -> AttachRequest {
+    AttachRequest { event: Some(event) }
+}
+
+/// Runs interactive session on the client until its shell exits. Returns exit code of the shell
+pub async fn attach(mut client: NotSshCliClient<Channel>, id: String) -> anyhow::Result<i32> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (rows, cols) = window_size();
+    let term = std::env::var("TERM").unwrap_or_default();
+    tx.send(request(attach_request::Event::Open(attach_request::Open {
+        id,
+        rows,
+        cols,
+        term,
+    })))?;
+    let mut output = client
+        .attach(UnboundedReceiverStream::new(rx))
+        .await
+        .with_context(|| "cannot open session")?
+        .into_inner();
+
+    let _raw = if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+        Some(RawMode::enable().with_context(|| "cannot put terminal in raw mode")?)
+    } else {
+        None
+    };
+
+    // Reading stdin blocks, so it gets its own thread which dies with the process
+    let input = tx.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0; 4096];
+        let mut stdin = io::stdin().lock();
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let req = request(attach_request::Event::Input(buf[..n].to_vec()));
+                    if input.send(req).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let mut winch = signal(SignalKind::window_change())?;
+    let mut stdout = io::stdout();
+    loop {
+        tokio::select! {
+            msg = output.message() => match msg? {
+                Some(AttachResponse { event: Some(attach_response::Event::Output(data)) }) => {
+                    stdout.write_all(&data)?;
+                    stdout.flush()?;
+                }
+                Some(AttachResponse { event: Some(attach_response::Event::Exit(exit)) }) => {
+                    if exit.signal != 0 {
+                        return Ok(128 + exit.signal);
+                    }
+                    return Ok(exit.code);
+                }
+                Some(_) => continue,
+                None => anyhow::bail!("session closed unexpectedly"),
+            },
+            _ = winch.recv() => {
+                let (rows, cols) = window_size();
+                tx.send(request(attach_request::Event::Resize(attach_request::Resize { rows, cols })))?;
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    time::Duration,
+};
+
+use anyhow::Context;
+use notssh_util::error;
+use tonic::transport::Channel;
+
+use crate::notssh_cli::{
+    job_status_response::{target::State, Target},
+    not_ssh_cli_client::NotSshCliClient,
+    JobStatusRequest, JobStatusResponse, SubmitJobRequest,
+};
+
+// How often progress of a followed job is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Submits the job and follows it unless detached. Interrupting only stops following, the job
+/// goes on
+pub async fn submit(
+    mut client: NotSshCliClient<Channel>,
+    req: SubmitJobRequest,
+    detach: bool,
+) -> anyhow::Result<i32> {
+    let res = client
+        .submit_job(req)
+        .await
+        .map_err(error::Error::from)
+        .with_context(|| "cannot submit job")?
+        .into_inner();
+    if detach {
+        println!("{}", res.id);
+        return Ok(0);
+    }
+    eprintln!(
+        "job {} submitted to {} clients, follow it with `notssh-ctl job status --wait {}`",
+        res.id,
+        res.client_ids.len(),
+        res.id
+    );
+    follow(&mut client, &res.id).await?;
+    let status = status(&mut client, &res.id, true).await?;
+    print_output(&status.targets)?;
+    print_summary(&status.targets);
+    Ok(exit_code(&status.targets))
+}
+
+/// Prints status of the job, waiting for it to finish if asked to
+pub async fn show(
+    mut client: NotSshCliClient<Channel>,
+    id: String,
+    wait: bool,
+    output: bool,
+) -> anyhow::Result<i32> {
+    if wait {
+        follow(&mut client, &id).await?;
+    }
+    let status = status(&mut client, &id, output).await?;
+    println!(
+        "JOB {} created {}",
+        status.id,
+        crate::format_age(status.created_at)
+    );
+    println!("{:<36} {:<9} ERROR", "CLIENT ID", "STATE");
+    for t in &status.targets {
+        println!(
+            "{:<36} {:<9} {}",
+            t.client_id,
+            t.state().as_str_name(),
+            t.error
+        );
+    }
+    if output {
+        print_output(&status.targets)?;
+    }
+    print_summary(&status.targets);
+    Ok(exit_code(&status.targets))
+}
+
+async fn status(
+    client: &mut NotSshCliClient<Channel>,
+    id: &str,
+    output: bool,
+) -> anyhow::Result<JobStatusResponse> {
+    let res = client
+        .job_status(JobStatusRequest {
+            id: id.to_owned(),
+            output,
+        })
+        .await
+        .map_err(error::Error::from)
+        .with_context(|| format!("cannot get status of job {}", id))?;
+    Ok(res.into_inner())
+}
+
+// Reports clients as they finish until none is left
+async fn follow(client: &mut NotSshCliClient<Channel>, id: &str) -> anyhow::Result<()> {
+    let mut reported = HashSet::new();
+    loop {
+        let status = status(client, id, false).await?;
+        for t in &status.targets {
+            if is_done(t) && reported.insert(t.action_id.clone()) {
+                match t.state() {
+                    State::Succeeded => eprintln!("{} succeeded", t.client_id),
+                    _ => eprintln!("{} failed ({})", t.client_id, t.error),
+                }
+            }
+        }
+        if status.targets.iter().all(is_done) {
+            return Ok(());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn is_done(t: &Target) -> bool {
+    matches!(t.state(), State::Succeeded | State::Failed)
+}
+
+fn print_output(targets: &[Target]) -> anyhow::Result<()> {
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
+    for t in targets {
+        if t.stdout.is_empty() && t.stderr.is_empty() {
+            continue;
+        }
+        write!(stdout, "\n{}\n{:-<36}\n", t.client_id, "")
+            .and_then(|_| stdout.write_all(&t.stdout))
+            .and_then(|_| stdout.flush())
+            .and_then(|_| stderr.write_all(&t.stderr))
+            .with_context(|| "cannot write output")?;
+    }
+    Ok(())
+}
+
+fn print_summary(targets: &[Target]) {
+    let count = |state| targets.iter().filter(|t| t.state() == state).count();
+    eprintln!(
+        "pending {}, running {}, succeeded {}, failed {}",
+        count(State::Pending),
+        count(State::Running),
+        count(State::Succeeded),
+        count(State::Failed)
+    );
+}
+
+fn exit_code(targets: &[Target]) -> i32 {
+    if targets.iter().any(|t| t.state() == State::Failed) {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(state: State) -> Target {
+        Target {
+            state: state.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_finished_targets_are_done() {
+        assert!(!is_done(&target(State::Pending)));
+        assert!(!is_done(&target(State::Running)));
+        assert!(is_done(&target(State::Succeeded)));
+        assert!(is_done(&target(State::Failed)));
+    }
+
+    #[test]
+    fn job_fails_if_any_target_fails() {
+        assert_eq!(exit_code(&[]), 0);
+        assert_eq!(
+            exit_code(&[target(State::Succeeded), target(State::Running)]),
+            0
+        );
+        assert_eq!(
+            exit_code(&[target(State::Succeeded), target(State::Failed)]),
+            1
+        );
+    }
+}
@@ -1,14 +1,26 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Seek},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
 
 use anyhow::Context;
 use clap::Parser;
 use notssh_cli::{
-    not_ssh_cli_client::NotSshCliClient, ListRequest, PingRequest, PingResponse, PurgeRequest,
-    PurgeResponse, ShellRequest, ShellResponse,
+    get_result_response, not_ssh_cli_client::NotSshCliClient, pull_response, rollout_response,
+    shell_output, shell_stream_response, submit_job_request, ApproveRequest, CancelRequest,
+    CreateTokenRequest, Delivery, GetResultRequest, LabelRequest, ListRequest, PingRequest,
+    PingResponse, PullRequest, PurgeRequest, PurgeResponse, PutFileRequest, PutFileResponse,
+    RevokeTokenRequest, RolloutRequest, ShellOutput, ShellRequest, ShellResponse, SubmitJobRequest,
+    UploadRequest,
 };
 use notssh_util::error;
+use sha2::{Digest, Sha256};
 use tokio::{
-    io::AsyncWriteExt,
+    io::{AsyncWriteExt, Stderr, Stdout},
     net::UnixStream,
     sync::{
         mpsc::{self, UnboundedReceiver, UnboundedSender},
@@ -16,8 +28,19 @@ use tokio::{
     },
     task::JoinSet,
 };
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::{Channel, Uri};
 use tower::service_fn;
+use uuid::Uuid;
+
+mod attach;
+mod job;
+mod ping;
+
+// Size of messages used to upload files
+const UPLOAD_CHUNK_SIZE: usize = 65536;
+// Chunks read ahead of the upload
+const UPLOAD_QUEUE: usize = 16;
 
 pub mod notssh_cli {
     include!("../../gen/notssh_cli.rs");
@@ -29,10 +52,18 @@ struct Cli {
     #[arg(long)]
     ids: Option<String>,
 
+    /// Clients with matching labels (example: env=prod,role!=db,zone in (a,b),!legacy)
+    #[arg(short, long, conflicts_with = "ids")]
+    selector: Option<String>,
+
     /// Number of parallel executors
     #[arg(short, long, default_value_t = 4)]
     parallel: u8,
 
+    /// Seconds the action may take, after that client stops it (server default if not set)
+    #[arg(short, long)]
+    timeout: Option<u64>,
+
     #[command(subcommand)]
     command: Command,
 
@@ -44,13 +75,159 @@ struct Cli {
 #[derive(clap::Subcommand)]
 enum Command {
     /// List connected clients
-    List,
-    /// Send ping to client
-    Ping,
+    List {
+        /// Show everything clients reported about their hosts
+        #[arg(short, long, default_value_t = false)]
+        long: bool,
+    },
+    /// Send ping to client, measuring round-trip time and offset of its clock
+    Ping {
+        /// Number of pings sent to every client
+        #[arg(short, long, default_value_t = 1)]
+        count: u32,
+        /// Seconds between pings of the same client
+        #[arg(short, long, default_value_t = 1.0)]
+        interval: f64,
+        /// Seconds clock of a client may be off before it is flagged
+        #[arg(long, default_value_t = 1.0)]
+        max_skew: f64,
+    },
     /// Purge all traces from client WARNING! This action is irreversable!
     Purge,
     /// Execute shell command on client
     Shell(ShellArgs),
+    /// Open interactive shell on client
+    Attach {
+        /// Client id
+        id: String,
+    },
+    /// Copy local file to clients
+    Push {
+        #[command(flatten)]
+        args: PushArgs,
+        /// Queue the file for clients and print action ids instead of waiting, so it gets to
+        /// offline clients once they connect within a day. Timeout counts from delivery
+        #[arg(long, default_value_t = false)]
+        submit: bool,
+    },
+    /// Copy file from clients into local directory, one file per client named after its id
+    Pull(PullArgs),
+    /// Let a client waiting for approval in
+    Approve {
+        /// Client id
+        id: String,
+    },
+    /// Set (key=value) or remove (key-) labels of a client
+    Label {
+        /// Client id
+        id: String,
+        /// Labels to change
+        #[arg(required = true)]
+        labels: Vec<String>,
+    },
+    /// Show result of a submitted action
+    Result {
+        /// Action id
+        action_id: String,
+        /// Wait for the action to be done
+        #[arg(short, long, default_value_t = false)]
+        wait: bool,
+    },
+    /// Run commands on clients by server, progress is kept even if ctl goes away
+    #[command(subcommand)]
+    Job(JobCommand),
+    /// Manage tokens clients register with
+    #[command(subcommand)]
+    Token(TokenCommand),
+}
+
+#[derive(clap::Subcommand)]
+enum JobCommand {
+    /// Execute shell command on clients
+    Shell {
+        /// Command to execute
+        cmd: String,
+        /// Command arguments
+        args: Vec<String>,
+        /// Path to file to use as input for the command
+        #[arg(long)]
+        stdin: Option<String>,
+        #[command(flatten)]
+        delivery: DeliveryArgs,
+        /// Print job id and exit instead of following the job
+        #[arg(short, long, default_value_t = false)]
+        detach: bool,
+    },
+    /// Send ping to clients
+    Ping {
+        /// Print job id and exit instead of following the job
+        #[arg(short, long, default_value_t = false)]
+        detach: bool,
+    },
+    /// Copy local file to clients
+    Push {
+        #[command(flatten)]
+        args: PushArgs,
+        /// Print job id and exit instead of following the job
+        #[arg(short, long, default_value_t = false)]
+        detach: bool,
+    },
+    /// Show progress of a job
+    Status {
+        /// Job id
+        id: String,
+        /// Wait for the job to finish
+        #[arg(short, long, default_value_t = false)]
+        wait: bool,
+        /// Print output of shell commands
+        #[arg(short, long, default_value_t = false)]
+        output: bool,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum TokenCommand {
+    /// Create a token, it is printed once
+    Create {
+        /// Token may be used by any number of clients
+        #[arg(short, long, default_value_t = false)]
+        reusable: bool,
+        /// Seconds the token is valid for (never expires if not set)
+        #[arg(long)]
+        ttl: Option<u64>,
+    },
+    /// Revoke a token, so no more clients can register with it
+    Revoke {
+        /// Token id
+        id: String,
+    },
+}
+
+#[derive(clap::Args)]
+struct PullArgs {
+    /// Path to file on clients
+    remote: String,
+    /// Local directory to put files in
+    local_dir: String,
+}
+
+#[derive(clap::Args)]
+struct PushArgs {
+    /// Path to local file
+    local: String,
+    /// Destination path on clients
+    remote: String,
+    /// Permission bits in octal (example: 644)
+    #[arg(short, long)]
+    mode: Option<String>,
+    /// Owner of the file (user[:group])
+    #[arg(short, long)]
+    owner: Option<String>,
+    /// Write directly to the destination instead of renaming a complete temporary file over it
+    #[arg(long, default_value_t = false)]
+    no_atomic: bool,
+    #[command(flatten)]
+    delivery: DeliveryArgs,
 }
 
 #[derive(clap::Args)]
@@ -65,13 +242,48 @@ struct ShellArgs {
     /// Annotate command output with clients' ids
     #[arg(short, long, default_value_t = false)]
     annotate: bool,
+    /// Print output as soon as it appears instead of waiting for the command to finish
+    #[arg(short, long, default_value_t = false)]
+    stream: bool,
+    /// Prefix every line of output with client's id
+    #[arg(long, default_value_t = false)]
+    prefix: bool,
+    /// Run on this many clients at a time, or on a percentage of them (example: 5 or 10%).
+    /// Interrupting cancels the current batch and stops further ones
+    #[arg(short, long, conflicts_with = "stream")]
+    batch: Option<String>,
+    /// Seconds to wait between batches
+    #[arg(long, default_value_t = 0, requires = "batch")]
+    pause: u64,
+    /// Don't start further batches once more than this many clients have failed
+    #[arg(long, requires = "batch")]
+    max_failures: Option<u32>,
+    /// Queue the command for clients and print action ids instead of waiting, so it runs on
+    /// offline clients once they connect within a day. Timeout counts from delivery. Output is
+    /// fetched later with `result`
+    #[arg(long, default_value_t = false, conflicts_with_all = ["stream", "batch"])]
+    submit: bool,
+    #[command(flatten)]
+    delivery: DeliveryArgs,
+}
+
+#[derive(clap::Args)]
+struct DeliveryArgs {
+    /// Deliver again to clients which disconnect while executing it, instead of giving up on
+    /// them. Clients skip it if they have executed it already
+    #[arg(long, default_value_t = false)]
+    at_least_once: bool,
 }
 
 #[derive(Debug)]
 enum ExecReq {
-    Ping(PingRequest),
+    // Sent the given number of times, with interval in between
+    Ping(PingRequest, u32, Duration),
     Purge(PurgeRequest),
     Shell(ShellRequest),
+    ShellStream(ShellRequest),
+    PutFile(PutFileRequest),
+    Pull(PullRequest, PathBuf),
 }
 
 type TonicResult<T> = Result<tonic::Response<T>, tonic::Status>;
@@ -81,6 +293,349 @@ enum ExecRes {
     Ping(TonicResult<PingResponse>, String),
     Purge(TonicResult<PurgeResponse>, String),
     Shell(TonicResult<ShellResponse>, String),
+    ShellOutput(ShellOutput, String),
+    PutFile(TonicResult<PutFileResponse>, String),
+    Pull(Result<pull_response::File, anyhow::Error>, String),
+}
+
+/// Prepends client id to every line of output. Incomplete lines are held until the rest of them
+/// arrives, so lines of different clients don't get mixed
+struct LinePrefixer {
+    enabled: bool,
+    partial: HashMap<(String, bool), Vec<u8>>,
+}
+
+impl LinePrefixer {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            partial: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, id: &str, stderr: bool, data: &[u8]) -> Vec<u8> {
+        if !self.enabled {
+            return data.to_vec();
+        }
+        let buf = self.partial.entry((id.to_owned(), stderr)).or_default();
+        buf.extend_from_slice(data);
+        let mut out = Vec::new();
+        while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+            out.extend_from_slice(format!("{}: ", id).as_bytes());
+            out.extend(buf.drain(..=pos));
+        }
+        out
+    }
+
+    fn flush(&mut self, id: &str, stderr: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Some(buf) = self.partial.remove(&(id.to_owned(), stderr)) {
+            if !buf.is_empty() {
+                out.extend_from_slice(format!("{}: ", id).as_bytes());
+                out.extend(buf);
+                out.push(b'\n');
+            }
+        }
+        out
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes == 0 {
+        return String::new();
+    }
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, units[unit])
+}
+
+// Time since unix timestamp in seconds, as in "5m ago"
+fn format_age(secs: i64) -> String {
+    if secs == 0 {
+        return String::new();
+    }
+    let now = UNIX_EPOCH.elapsed().unwrap_or_default().as_secs() as i64;
+    let age = (now - secs).max(0);
+    match age {
+        0..=59 => format!("{}s ago", age),
+        60..=3599 => format!("{}m ago", age / 60),
+        3600..=86399 => format!("{}h ago", age / 3600),
+        _ => format!("{}d ago", age / 86400),
+    }
+}
+
+// Batch is either number of clients or percentage of them
+fn parse_batch(batch: &str) -> anyhow::Result<(u32, u32)> {
+    let parsed = match batch.strip_suffix('%') {
+        Some(percent) => percent
+            .parse()
+            .ok()
+            .filter(|p| (1..=100).contains(p))
+            .map(|p| (0, p)),
+        None => batch.parse().ok().filter(|n| *n > 0).map(|n| (n, 0)),
+    };
+    parsed.ok_or_else(|| {
+        error::Error::arg("batch must be a positive number or percentage (example: 5 or 10%)")
+            .into()
+    })
+}
+
+// Runs shell command in batches, returns false if the rollout was aborted. Actions of the current
+// batch are tracked, so they are cancelled if interrupted
+async fn rollout(
+    mut client: NotSshCliClient<Channel>,
+    req: RolloutRequest,
+    in_flight: &InFlight,
+    annotate: bool,
+    prefix: bool,
+) -> anyhow::Result<bool> {
+    let mut stream = client
+        .rollout(req)
+        .await
+        .map_err(error::Error::from)
+        .with_context(|| "cannot start rollout")?
+        .into_inner();
+    let mut stdout = tokio::io::stdout();
+    let mut stderr = tokio::io::stderr();
+    let mut prefixer = LinePrefixer::new(prefix);
+    let mut completed = true;
+    while let Some(res) = stream.message().await.map_err(error::Error::from)? {
+        match res.event {
+            Some(rollout_response::Event::Batch(batch)) => {
+                eprintln!("batch {}: {}", batch.index + 1, batch.ids.join(","));
+                in_flight
+                    .lock()
+                    .unwrap()
+                    .extend(batch.ids.into_iter().zip(batch.action_ids));
+            }
+            Some(rollout_response::Event::Result(r)) => {
+                in_flight.lock().unwrap().remove(&r.id);
+                let res = match r.response {
+                    Some(response) if r.error.is_empty() => Ok(response),
+                    _ => Err(r.error),
+                };
+                print_shell_result(
+                    &r.id,
+                    res,
+                    annotate,
+                    &mut prefixer,
+                    &mut stdout,
+                    &mut stderr,
+                )
+                .await?;
+            }
+            Some(rollout_response::Event::Aborted(aborted)) => {
+                eprintln!(
+                    "rollout aborted after {} failures, skipped: {}",
+                    aborted.failed,
+                    aborted.skipped.join(",")
+                );
+                completed = false;
+            }
+            None => {}
+        }
+    }
+    Ok(completed)
+}
+
+async fn print_shell_result(
+    id: &str,
+    res: Result<ShellResponse, String>,
+    annotate: bool,
+    prefixer: &mut LinePrefixer,
+    stdout: &mut Stdout,
+    stderr: &mut Stderr,
+) -> anyhow::Result<()> {
+    if annotate {
+        let a = format!("\n{}\n{:-<36}\n", id, "");
+        stdout
+            .write_all(a.as_bytes())
+            .await
+            .with_context(|| "cannot write to stdout")?;
+    }
+    match res {
+        Ok(res) => {
+            let mut out = prefixer.push(id, false, &res.stdout);
+            out.extend(prefixer.flush(id, false));
+            stdout
+                .write_all(&out)
+                .await
+                .with_context(|| "cannot write response to stdout")?;
+            let mut err = prefixer.push(id, true, &res.stderr);
+            err.extend(prefixer.flush(id, true));
+            stderr
+                .write_all(&err)
+                .await
+                .with_context(|| "cannot write response to stderr")?;
+            if res.signal != 0 {
+                eprintln!("{} killed by signal {}", id, res.signal);
+            } else if res.code != 0 {
+                eprintln!("{} exited with code {}", id, res.code);
+            }
+        }
+        Err(e) => println!("{} Shell failed ({})", id, e),
+    }
+    if annotate {
+        let a = format!("\n{:-<36}\n", "");
+        stdout
+            .write_all(a.as_bytes())
+            .await
+            .with_context(|| "cannot write to stdout")?;
+    }
+    Ok(())
+}
+
+// Prints what is known about the action, returns exit code
+async fn show_result(
+    mut client: NotSshCliClient<Channel>,
+    action_id: String,
+    wait: bool,
+) -> anyhow::Result<i32> {
+    let res = client
+        .get_result(GetResultRequest {
+            action_id: action_id.clone(),
+            wait,
+        })
+        .await
+        .map_err(error::Error::from)
+        .with_context(|| format!("cannot get result of action {}", action_id))?
+        .into_inner();
+    let id = res.client_id.as_str();
+    match (res.state(), res.shell) {
+        (get_result_response::State::Finished, Some(shell)) => {
+            let mut prefixer = LinePrefixer::new(false);
+            print_shell_result(
+                id,
+                Ok(shell),
+                false,
+                &mut prefixer,
+                &mut tokio::io::stdout(),
+                &mut tokio::io::stderr(),
+            )
+            .await?;
+            Ok(0)
+        }
+        (get_result_response::State::Finished, None) => {
+            println!("{} Finished", id);
+            Ok(0)
+        }
+        (
+            state @ (get_result_response::State::Pending | get_result_response::State::Running),
+            _,
+        ) => {
+            println!("{} {}", id, state.as_str_name());
+            Ok(0)
+        }
+        (state, _) => {
+            println!("{} {} ({})", id, state.as_str_name(), res.error);
+            Ok(1)
+        }
+    }
+}
+
+impl DeliveryArgs {
+    fn delivery(&self) -> i32 {
+        match self.at_least_once {
+            true => Delivery::AtLeastOnce.into(),
+            false => Delivery::AtMostOnce.into(),
+        }
+    }
+}
+
+fn parse_mode(mode: Option<&str>) -> anyhow::Result<u32> {
+    let mode = mode
+        .map_or(Ok(0), |m| u32::from_str_radix(m, 8))
+        .map_err(|_| error::Error::arg("mode must be an octal number"))?;
+    Ok(mode)
+}
+
+// Uploads local file to server, returns id of the stored blob. File is read twice, first for its
+// checksum, so it is never held in memory as a whole
+async fn upload(client: &mut NotSshCliClient<Channel>, path: &str) -> anyhow::Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("cannot open file {}", path))?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("cannot read file {}", path))?;
+    let sha256 = hasher.finalize().to_vec();
+    file.rewind()
+        .with_context(|| format!("cannot read file {}", path))?;
+
+    let (tx, rx) = mpsc::channel(UPLOAD_QUEUE);
+    let reader = tokio::task::spawn_blocking(move || read_chunks(file, size, sha256, tx));
+    let res = client.upload(ReceiverStream::new(rx)).await;
+    // Upload is cut short if reading fails, which is the actual reason then
+    reader
+        .await?
+        .with_context(|| format!("cannot read file {}", path))?;
+    let blob_id = res
+        .map_err(error::Error::from)
+        .with_context(|| "cannot upload file")?
+        .into_inner()
+        .blob_id;
+    Ok(blob_id)
+}
+
+// Reads the file into upload requests until it ends or nobody takes them anymore. First request
+// carries size and checksum of the whole file
+fn read_chunks(
+    mut file: std::fs::File,
+    size: u64,
+    sha256: Vec<u8>,
+    tx: mpsc::Sender<UploadRequest>,
+) -> std::io::Result<()> {
+    let mut first = Some((size, sha256));
+    loop {
+        let mut data = vec![0; UPLOAD_CHUNK_SIZE];
+        let n = file.read(&mut data)?;
+        data.truncate(n);
+        // Empty file is uploaded as a single empty request
+        if n == 0 && first.is_none() {
+            return Ok(());
+        }
+        let (size, sha256) = first.take().unwrap_or_default();
+        let req = UploadRequest { size, sha256, data };
+        if tx.blocking_send(req).is_err() || n == 0 {
+            return Ok(());
+        }
+    }
+}
+
+// Actions started by this invocation by client id, so they are cancelled if it is interrupted
+type InFlight = Arc<std::sync::Mutex<HashMap<String, String>>>;
+
+fn track(in_flight: &InFlight, id: &str) -> String {
+    let action_id = Uuid::new_v4().to_string();
+    in_flight
+        .lock()
+        .unwrap()
+        .insert(id.to_owned(), action_id.clone());
+    action_id
+}
+
+async fn cancel_on_interrupt(mut client: NotSshCliClient<Channel>, in_flight: InFlight) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    let actions: Vec<_> = in_flight.lock().unwrap().drain().collect();
+    for (id, action_id) in actions {
+        match client.cancel(CancelRequest { action_id }).await {
+            Ok(_) => eprintln!("{} Cancelled", id),
+            // Action has not been created yet or is already done
+            Err(s)
+                if matches!(
+                    s.code(),
+                    tonic::Code::NotFound | tonic::Code::FailedPrecondition
+                ) => {}
+            Err(s) => eprintln!("{} Cancel failed ({})", id, s.message()),
+        }
+    }
+    std::process::exit(130);
 }
 
 async fn executor(
@@ -90,10 +645,14 @@ async fn executor(
 ) {
     while let Some(exec) = rx.lock().await.recv().await {
         match exec {
-            ExecReq::Ping(req) => {
-                let id = req.id.clone();
-                let res = client.ping(req).await;
-                tx.send(ExecRes::Ping(res, id)).unwrap();
+            ExecReq::Ping(req, count, interval) => {
+                for i in 0..count {
+                    if i > 0 {
+                        tokio::time::sleep(interval).await;
+                    }
+                    let res = client.ping(req.clone()).await;
+                    tx.send(ExecRes::Ping(res, req.id.clone())).unwrap();
+                }
             }
             ExecReq::Purge(req) => {
                 let id = req.id.clone();
@@ -105,10 +664,99 @@ async fn executor(
                 let res = client.shell(req).await;
                 tx.send(ExecRes::Shell(res, id)).unwrap();
             }
+            ExecReq::PutFile(req) => {
+                let id = req.id.clone();
+                let res = client.put_file(req).await;
+                tx.send(ExecRes::PutFile(res, id)).unwrap();
+            }
+            ExecReq::Pull(req, path) => {
+                let id = req.id.clone();
+                let res = pull(&mut client, req, &path).await;
+                if res.is_err() {
+                    // Partially written file is of no use to anyone
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+                tx.send(ExecRes::Pull(res, id)).unwrap();
+            }
+            ExecReq::ShellStream(req) => {
+                let id = req.id.clone();
+                let mut stream = match client.shell_stream(req).await {
+                    Ok(res) => res.into_inner(),
+                    Err(e) => {
+                        tx.send(ExecRes::Shell(Err(e), id)).unwrap();
+                        continue;
+                    }
+                };
+                loop {
+                    match stream.message().await {
+                        Ok(Some(msg)) => match msg.event {
+                            Some(shell_stream_response::Event::Output(out)) => {
+                                tx.send(ExecRes::ShellOutput(out, id.clone())).unwrap()
+                            }
+                            Some(shell_stream_response::Event::Result(res)) => {
+                                tx.send(ExecRes::Shell(Ok(tonic::Response::new(res)), id))
+                                    .unwrap();
+                                break;
+                            }
+                            None => continue,
+                        },
+                        Ok(None) => {
+                            let e = tonic::Status::unavailable("stream ended without result");
+                            tx.send(ExecRes::Shell(Err(e), id)).unwrap();
+                            break;
+                        }
+                        Err(e) => {
+                            tx.send(ExecRes::Shell(Err(e), id)).unwrap();
+                            break;
+                        }
+                    }
+                }
+            }
         }
     }
 }
 
+// Writes file content as it arrives and verifies it once the whole file is received
+async fn pull(
+    client: &mut NotSshCliClient<Channel>,
+    req: PullRequest,
+    path: &Path,
+) -> Result<pull_response::File, anyhow::Error> {
+    let mut stream = client.pull(req).await?.into_inner();
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("cannot create file {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(msg) = stream.message().await? {
+        let info = match msg.event {
+            Some(pull_response::Event::Data(data)) => {
+                hasher.update(&data);
+                size += data.len() as u64;
+                file.write_all(&data)
+                    .await
+                    .with_context(|| format!("cannot write file {}", path.display()))?;
+                continue;
+            }
+            Some(pull_response::Event::File(info)) => info,
+            None => continue,
+        };
+        if info.size != size || info.sha256 != hasher.finalize().to_vec() {
+            return Err(error::Error::io("received content does not match its checksum").into());
+        }
+        file.sync_all().await?;
+        let file = file.into_std().await;
+        if info.mode != 0 {
+            file.set_permissions(PermissionsExt::from_mode(info.mode))?;
+        }
+        if info.modified_at != 0 {
+            file.set_modified(UNIX_EPOCH + Duration::from_millis(info.modified_at as u64))?;
+        }
+        return Ok(info);
+    }
+    Err(error::Error::io("stream ended without file").into())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let cli = Cli::parse();
@@ -128,29 +776,222 @@ async fn main() -> Result<(), anyhow::Error> {
 
     let mut client = NotSshCliClient::new(chan);
 
-    if let Command::List = cli.command {
-        let req = tonic::Request::new(ListRequest {});
+    if let Command::List { long } = cli.command {
+        let req = tonic::Request::new(ListRequest {
+            selector: cli.selector.unwrap_or_default(),
+        });
         let res = client
             .list(req)
             .await
-            .map_err(|e| error::Error::from(e))?
+            .map_err(error::Error::from)?
             .into_inner();
-        println!("{:<36} CONNECTED", "CLIENT ID");
+        print!(
+            "{:<36} {:<9} {:<8} {:<24} {:<32}",
+            "CLIENT ID", "CONNECTED", "APPROVED", "HOSTNAME", "OS"
+        );
+        if long {
+            print!(
+                " {:<32} {:<24} {:<8} {:<4} {:<10} {:<10} {:<10} {:<36} {:<8} ADDRESSES",
+                "MACHINE ID",
+                "KERNEL",
+                "ARCH",
+                "CPUS",
+                "MEMORY",
+                "VERSION",
+                "UPDATED",
+                "INSTANCE",
+                "RTT"
+            );
+        }
+        println!(" LABELS");
         for client in res.clients {
-            println!("{:<36} {}", client.id, client.connected);
+            let facts = client.facts.unwrap_or_default();
+            let rtt = if client.connected {
+                format!("{}ms", client.rtt)
+            } else {
+                String::new()
+            };
+            print!(
+                "{:<36} {:<9} {:<8} {:<24} {:<32}",
+                client.id, client.connected, client.approved, facts.hostname, facts.os_release
+            );
+            if long {
+                print!(
+                    " {:<32} {:<24} {:<8} {:<4} {:<10} {:<10} {:<10} {:<36} {:<8} {}",
+                    facts.machine_id,
+                    facts.kernel,
+                    facts.arch,
+                    facts.cpus,
+                    format_size(facts.memory),
+                    facts.agent_version,
+                    format_age(facts.updated_at),
+                    client.instance,
+                    rtt,
+                    facts.addresses.join(",")
+                );
+            }
+            let labels: BTreeMap<_, _> = client.labels.into_iter().collect();
+            let labels: Vec<_> = labels
+                .into_iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect();
+            println!(" {}", labels.join(","));
+        }
+        return Ok(());
+    }
+
+    if let Command::Label { id, labels } = cli.command {
+        let mut req = LabelRequest {
+            id: id.clone(),
+            ..Default::default()
+        };
+        for label in labels {
+            if let Some((key, value)) = label.split_once('=') {
+                req.set.insert(key.to_owned(), value.to_owned());
+            } else if let Some(key) = label.strip_suffix('-') {
+                req.remove.push(key.to_owned());
+            } else {
+                return Err(error::Error::arg(format!("invalid label '{}'", label)).into());
+            }
+        }
+        client
+            .label(req)
+            .await
+            .map_err(error::Error::from)
+            .with_context(|| format!("cannot label {}", id))?;
+        return Ok(());
+    }
+
+    if let Command::Approve { id } = cli.command {
+        client
+            .approve(ApproveRequest { id: id.clone() })
+            .await
+            .map_err(error::Error::from)
+            .with_context(|| format!("cannot approve {}", id))?;
+        println!("{} Approved", id);
+        return Ok(());
+    }
+
+    if let Command::Token(cmd) = cli.command {
+        match cmd {
+            TokenCommand::Create { reusable, ttl } => {
+                let req = CreateTokenRequest {
+                    reusable,
+                    ttl: ttl.unwrap_or_default(),
+                };
+                let res = client
+                    .create_token(req)
+                    .await
+                    .map_err(error::Error::from)
+                    .with_context(|| "cannot create token")?
+                    .into_inner();
+                println!("{:<36} TOKEN", "TOKEN ID");
+                println!("{:<36} {}", res.id, res.token);
+            }
+            TokenCommand::Revoke { id } => {
+                client
+                    .revoke_token(RevokeTokenRequest { id: id.clone() })
+                    .await
+                    .map_err(error::Error::from)
+                    .with_context(|| format!("cannot revoke token {}", id))?;
+                println!("{} Revoked", id);
+            }
         }
         return Ok(());
     }
 
-    let ids: Vec<String> = cli
-        .ids
-        .ok_or(error::Error::arg("ids required for this command"))
-        .map(|ids| {
-            ids.split(',')
-                .filter(|s| !s.is_empty())
-                .map(|s| s.to_owned())
-                .collect()
-        })?;
+    if let Command::Job(cmd) = cli.command {
+        let code = match cmd {
+            JobCommand::Status { id, wait, output } => job::show(client, id, wait, output).await?,
+            cmd => {
+                let delivery = match &cmd {
+                    JobCommand::Shell { delivery, .. } => delivery.delivery(),
+                    JobCommand::Push { args, .. } => args.delivery.delivery(),
+                    _ => Delivery::AtMostOnce.into(),
+                };
+                let (command, detach) = match cmd {
+                    JobCommand::Shell {
+                        cmd,
+                        args,
+                        stdin,
+                        detach,
+                        ..
+                    } => {
+                        let stdin = stdin.map_or(Ok(Vec::new()), |path| {
+                            std::fs::read(&path)
+                                .with_context(|| format!("cannot read input file {}", path))
+                        })?;
+                        let shell = submit_job_request::Shell { cmd, args, stdin };
+                        (submit_job_request::Command::Shell(shell), detach)
+                    }
+                    JobCommand::Ping { detach } => (
+                        submit_job_request::Command::Ping(submit_job_request::Ping {}),
+                        detach,
+                    ),
+                    JobCommand::Push { args, detach } => {
+                        let put = submit_job_request::PutFile {
+                            mode: parse_mode(args.mode.as_deref())?,
+                            blob_id: upload(&mut client, &args.local).await?,
+                            path: args.remote,
+                            owner: args.owner.unwrap_or_default(),
+                            atomic: !args.no_atomic,
+                        };
+                        (submit_job_request::Command::PutFile(put), detach)
+                    }
+                    JobCommand::Status { .. } => unreachable!(),
+                };
+                let req = SubmitJobRequest {
+                    ids: cli
+                        .ids
+                        .map(|ids| {
+                            ids.split(',')
+                                .filter(|s| !s.is_empty())
+                                .map(|s| s.to_owned())
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    selector: cli.selector.unwrap_or_default(),
+                    timeout: cli.timeout.unwrap_or_default(),
+                    delivery,
+                    command: Some(command),
+                };
+                job::submit(client, req, detach).await?
+            }
+        };
+        std::process::exit(code);
+    }
+
+    if let Command::Result { action_id, wait } = cli.command {
+        let code = show_result(client, action_id, wait).await?;
+        std::process::exit(code);
+    }
+
+    if let Command::Attach { id } = cli.command {
+        let code = attach::attach(client, id).await?;
+        // Thread reading stdin is still blocked, so don't wait for it
+        std::process::exit(code);
+    }
+
+    let ids: Vec<String> = match (cli.ids, cli.selector) {
+        (Some(ids), _) => ids
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_owned())
+            .collect(),
+        (None, Some(selector)) => client
+            .list(ListRequest { selector })
+            .await
+            .map_err(error::Error::from)
+            .with_context(|| "cannot resolve selector")?
+            .into_inner()
+            .clients
+            .into_iter()
+            .map(|c| c.id)
+            .collect(),
+        (None, None) => {
+            return Err(error::Error::arg("ids or selector required for this command").into())
+        }
+    };
 
     let (req_tx, req_rx) = mpsc::unbounded_channel();
     let (res_tx, mut res_rx) = mpsc::unbounded_channel();
@@ -162,25 +1003,125 @@ async fn main() -> Result<(), anyhow::Error> {
     }
     drop(req_rx);
     drop(res_tx);
+    let timeout = cli.timeout.unwrap_or_default();
+    let in_flight = InFlight::default();
+    tokio::spawn(cancel_on_interrupt(client.clone(), in_flight.clone()));
 
     match cli.command {
-        Command::List => unreachable!(),
-        Command::Ping => {
+        Command::List { .. }
+        | Command::Attach { .. }
+        | Command::Approve { .. }
+        | Command::Label { .. }
+        | Command::Result { .. }
+        | Command::Job(_)
+        | Command::Token(_) => {
+            unreachable!()
+        }
+        Command::Pull(args) => {
+            std::fs::create_dir_all(&args.local_dir)
+                .with_context(|| format!("cannot create directory {}", args.local_dir))?;
             for id in ids {
-                let req = PingRequest { id };
-                req_tx.send(ExecReq::Ping(req)).unwrap();
+                let path = Path::new(&args.local_dir).join(&id);
+                let action_id = track(&in_flight, &id);
+                let req = PullRequest {
+                    id,
+                    path: args.remote.clone(),
+                    timeout,
+                    action_id,
+                };
+                req_tx.send(ExecReq::Pull(req, path)).unwrap();
             }
             drop(req_tx);
-            while let Some(ExecRes::Ping(res, id)) = res_rx.recv().await {
+            while let Some(ExecRes::Pull(res, id)) = res_rx.recv().await {
+                in_flight.lock().unwrap().remove(&id);
                 match res {
-                    Ok(_) => println!("{} Ping OK", id),
+                    Ok(file) => println!("{} Pulled {} bytes", id, file.size),
+                    Err(e) => println!("{} Pull failed ({})", id, e),
+                }
+            }
+        }
+        Command::Push { args, submit } => {
+            let mode = parse_mode(args.mode.as_deref())?;
+            // Content is uploaded once and then put on every client
+            let blob_id = upload(&mut client, &args.local).await?;
+
+            if submit {
+                for id in ids {
+                    let req = PutFileRequest {
+                        id: id.clone(),
+                        blob_id: blob_id.clone(),
+                        path: args.remote.clone(),
+                        mode,
+                        owner: args.owner.clone().unwrap_or_default(),
+                        atomic: !args.no_atomic,
+                        timeout,
+                        delivery: args.delivery.delivery(),
+                        ..Default::default()
+                    };
+                    match client.submit_put_file(req).await {
+                        Ok(res) => println!("{} {}", id, res.into_inner().action_id),
+                        Err(e) => println!("{} Submit failed ({})", id, e),
+                    }
+                }
+                return Ok(());
+            }
+            for id in ids {
+                let action_id = track(&in_flight, &id);
+                let req = PutFileRequest {
+                    id,
+                    blob_id: blob_id.clone(),
+                    path: args.remote.clone(),
+                    mode,
+                    owner: args.owner.clone().unwrap_or_default(),
+                    atomic: !args.no_atomic,
+                    timeout,
+                    action_id,
+                    delivery: args.delivery.delivery(),
+                };
+                req_tx.send(ExecReq::PutFile(req)).unwrap();
+            }
+            drop(req_tx);
+            while let Some(ExecRes::PutFile(res, id)) = res_rx.recv().await {
+                in_flight.lock().unwrap().remove(&id);
+                match res {
+                    Ok(res) => println!("{} Pushed {} bytes", id, res.into_inner().size),
+                    Err(e) => println!("{} Push failed ({})", id, e),
+                }
+            }
+        }
+        Command::Ping {
+            count,
+            interval,
+            max_skew,
+        } => {
+            let interval = Duration::try_from_secs_f64(interval)
+                .map_err(|_| error::Error::arg("invalid interval"))?;
+            let max_skew = Duration::try_from_secs_f64(max_skew)
+                .map_err(|_| error::Error::arg("invalid clock skew"))?;
+            let mut stats: BTreeMap<String, ping::Stats> = BTreeMap::new();
+            for id in ids {
+                let req = PingRequest { id, timeout };
+                req_tx.send(ExecReq::Ping(req, count, interval)).unwrap();
+            }
+            drop(req_tx);
+            while let Some(ExecRes::Ping(res, id)) = res_rx.recv().await {
+                let res = res.map(|r| r.into_inner());
+                match &res {
+                    Ok(r) => println!("{} Ping OK {}", id, ping::format_reply(r, max_skew)),
                     Err(e) => println!("{} Ping failed ({})", id, e),
                 }
+                stats.entry(id).or_default().record(res.as_ref().ok());
+            }
+            if count > 1 {
+                println!();
+                for (id, stats) in stats {
+                    println!("{} {}", id, stats.summary(max_skew));
+                }
             }
         }
         Command::Purge => {
             for id in ids {
-                let req = PurgeRequest { id };
+                let req = PurgeRequest { id, timeout };
                 req_tx.send(ExecReq::Purge(req)).unwrap();
             }
             drop(req_tx);
@@ -195,51 +1136,136 @@ async fn main() -> Result<(), anyhow::Error> {
             let stdin = args.stdin.map_or(Ok(Vec::new()), |path| {
                 std::fs::read(&path).with_context(|| format!("cannot read input file {}", path))
             })?;
+            if let Some(batch) = args.batch.as_deref() {
+                let (batch_size, batch_percent) = parse_batch(batch)?;
+                let req = RolloutRequest {
+                    shell: Some(ShellRequest {
+                        cmd: args.cmd,
+                        args: args.args,
+                        stdin,
+                        timeout,
+                        delivery: args.delivery.delivery(),
+                        ..Default::default()
+                    }),
+                    ids,
+                    selector: String::new(),
+                    batch_size,
+                    batch_percent,
+                    pause: args.pause,
+                    max_failures: args.max_failures,
+                };
+                let completed =
+                    rollout(client, req, &in_flight, args.annotate, args.prefix).await?;
+                std::process::exit(if completed { 0 } else { 1 });
+            }
+            if args.submit {
+                for id in ids {
+                    let req = ShellRequest {
+                        id: id.clone(),
+                        cmd: args.cmd.clone(),
+                        args: args.args.clone(),
+                        stdin: stdin.clone(),
+                        timeout,
+                        delivery: args.delivery.delivery(),
+                        ..Default::default()
+                    };
+                    match client.submit_shell(req).await {
+                        Ok(res) => println!("{} {}", id, res.into_inner().action_id),
+                        Err(e) => println!("{} Submit failed ({})", id, e),
+                    }
+                }
+                return Ok(());
+            }
             for id in ids {
+                let action_id = track(&in_flight, &id);
                 let req = ShellRequest {
                     id,
                     cmd: args.cmd.clone(),
                     args: args.args.clone(),
                     stdin: stdin.clone(),
+                    timeout,
+                    action_id,
+                    delivery: args.delivery.delivery(),
                 };
-                req_tx.send(ExecReq::Shell(req)).unwrap();
+                if args.stream {
+                    req_tx.send(ExecReq::ShellStream(req)).unwrap();
+                } else {
+                    req_tx.send(ExecReq::Shell(req)).unwrap();
+                }
             }
             drop(req_tx);
-            while let Some(ExecRes::Shell(res, id)) = res_rx.recv().await {
-                let mut stdout = tokio::io::stdout();
-                let mut stderr = tokio::io::stderr();
-                if args.annotate {
-                    let a = format!("\n{}\n{:-<36}\n", id, "");
-                    stdout
-                        .write_all(a.as_bytes())
-                        .await
-                        .with_context(|| "cannot write to stdout")?;
-                }
-                match res {
-                    Ok(res) => {
-                        let res = res.into_inner();
-                        stdout
-                            .write_all(&res.stdout)
-                            .await
-                            .with_context(|| "cannot write response to stdout")?;
-                        stderr
-                            .write_all(&res.stderr)
-                            .await
-                            .with_context(|| "cannot write response to stderr")?;
+            let mut stdout = tokio::io::stdout();
+            let mut stderr = tokio::io::stderr();
+            let mut prefixer = LinePrefixer::new(args.prefix);
+            while let Some(res) = res_rx.recv().await {
+                let (res, id) = match res {
+                    ExecRes::ShellOutput(out, id) => {
+                        let is_stderr = out.stream() == shell_output::Stream::Stderr;
+                        let data = prefixer.push(&id, is_stderr, &out.data);
+                        if is_stderr {
+                            stderr.write_all(&data).await
+                        } else {
+                            stdout.write_all(&data).await
+                        }
+                        .with_context(|| "cannot write output")?;
+                        continue;
                     }
-                    Err(e) => println!("Shell failed ({})", e),
-                }
-                if args.annotate {
-                    let a = format!("\n{:-<36}\n", "");
-                    stdout
-                        .write_all(a.as_bytes())
-                        .await
-                        .with_context(|| "cannot write to stdout")?;
-                }
+                    ExecRes::Shell(res, id) => (res, id),
+                    _ => continue,
+                };
+                in_flight.lock().unwrap().remove(&id);
+                let res = res.map(|r| r.into_inner()).map_err(|e| e.to_string());
+                print_shell_result(
+                    &id,
+                    res,
+                    args.annotate,
+                    &mut prefixer,
+                    &mut stdout,
+                    &mut stderr,
+                )
+                .await?;
             }
         }
     }
 
-    while let Some(_) = set.join_next().await {}
+    while set.join_next().await.is_some() {}
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixer_passes_output_through_if_disabled() {
+        let mut p = LinePrefixer::new(false);
+        assert_eq!(p.push("a", false, b"partial"), b"partial");
+        assert!(p.flush("a", false).is_empty());
+    }
+
+    #[test]
+    fn prefixer_prefixes_complete_lines() {
+        let mut p = LinePrefixer::new(true);
+        assert_eq!(p.push("a", false, b"one\ntwo\n"), b"a: one\na: two\n");
+        assert!(p.flush("a", false).is_empty());
+    }
+
+    #[test]
+    fn prefixer_joins_lines_split_across_chunks() {
+        let mut p = LinePrefixer::new(true);
+        assert!(p.push("a", false, b"hel").is_empty());
+        assert_eq!(p.push("a", false, b"lo\nwor"), b"a: hello\n");
+        assert_eq!(p.flush("a", false), b"a: wor\n");
+    }
+
+    #[test]
+    fn prefixer_keeps_clients_and_streams_apart() {
+        let mut p = LinePrefixer::new(true);
+        assert!(p.push("a", false, b"out").is_empty());
+        assert!(p.push("a", true, b"err").is_empty());
+        assert!(p.push("b", false, b"other").is_empty());
+        assert_eq!(p.push("a", true, b"\n"), b"a: err\n");
+        assert_eq!(p.push("a", false, b"\n"), b"a: out\n");
+        assert_eq!(p.flush("b", false), b"b: other\n");
+    }
+}
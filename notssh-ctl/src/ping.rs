@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use crate::notssh_cli::PingResponse;
+
+/// Replies to pings of a single client, summarized the way ping(8) does
+#[derive(Default)]
+pub struct Stats {
+    sent: u32,
+    received: u32,
+    // microseconds, of replies from clients which report times
+    rtts: Vec<u64>,
+    // clock offset measured by the fastest round trip, which is the most accurate one
+    offset: Option<(u64, i64)>,
+}
+
+impl Stats {
+    pub fn record(&mut self, res: Option<&PingResponse>) {
+        self.sent += 1;
+        let res = match res {
+            Some(res) => res,
+            None => return,
+        };
+        self.received += 1;
+        if !is_timed(res) {
+            return;
+        }
+        self.rtts.push(res.rtt);
+        if self.offset.is_none_or(|(rtt, _)| res.rtt < rtt) {
+            self.offset = Some((res.rtt, res.clock_offset));
+        }
+    }
+
+    /// Packet loss, min/avg/max round-trip time and clock offset
+    pub fn summary(&self, max_skew: Duration) -> String {
+        let loss = 100 * (self.sent - self.received) / self.sent.max(1);
+        let mut summary = format!(
+            "{} sent, {} received, {}% loss",
+            self.sent, self.received, loss
+        );
+        if let (Some(min), Some(max)) = (self.rtts.iter().min(), self.rtts.iter().max()) {
+            let avg = self.rtts.iter().sum::<u64>() / self.rtts.len() as u64;
+            summary += &format!(
+                ", rtt min/avg/max {}/{}/{} ms",
+                format_ms(*min as i64),
+                format_ms(avg as i64),
+                format_ms(*max as i64)
+            );
+        }
+        if let Some((_, offset)) = self.offset {
+            summary += &format!(", clock offset {} ms", format_offset(offset));
+            if is_skewed(offset, max_skew) {
+                summary += " CLOCK SKEW";
+            }
+        }
+        summary
+    }
+}
+
+/// Round-trip time and clock offset of a single reply
+pub fn format_reply(res: &PingResponse, max_skew: Duration) -> String {
+    if !is_timed(res) {
+        return "time unknown".into();
+    }
+    let mut reply = format!(
+        "time={} ms offset={} ms",
+        format_ms(res.rtt as i64),
+        format_offset(res.clock_offset)
+    );
+    if is_skewed(res.clock_offset, max_skew) {
+        reply += " CLOCK SKEW";
+    }
+    reply
+}
+
+// Clients built before pings carried timestamps report no times
+fn is_timed(res: &PingResponse) -> bool {
+    res.rtt != 0 || res.clock_offset != 0
+}
+
+fn is_skewed(offset: i64, max_skew: Duration) -> bool {
+    offset.unsigned_abs() as u128 > max_skew.as_micros()
+}
+
+// Microseconds as milliseconds
+fn format_ms(us: i64) -> String {
+    format!("{:.3}", us as f64 / 1000.0)
+}
+
+fn format_offset(us: i64) -> String {
+    format!("{:+.3}", us as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_SKEW: Duration = Duration::from_secs(1);
+
+    fn reply(rtt: u64, clock_offset: i64) -> PingResponse {
+        PingResponse { rtt, clock_offset }
+    }
+
+    #[test]
+    fn summary_reports_loss_and_round_trips() {
+        let mut stats = Stats::default();
+        stats.record(Some(&reply(3000, 200)));
+        stats.record(None);
+        stats.record(Some(&reply(1000, -100)));
+        stats.record(Some(&reply(2000, 300)));
+        // Offset comes from the fastest round trip
+        assert_eq!(
+            stats.summary(MAX_SKEW),
+            "4 sent, 3 received, 25% loss, rtt min/avg/max 1.000/2.000/3.000 ms, \
+            clock offset -0.100 ms"
+        );
+    }
+
+    #[test]
+    fn summary_flags_clock_skew() {
+        let mut stats = Stats::default();
+        stats.record(Some(&reply(1000, 1_500_000)));
+        assert!(stats
+            .summary(MAX_SKEW)
+            .ends_with("clock offset +1500.000 ms CLOCK SKEW"));
+        assert_eq!(
+            format_reply(&reply(1000, -1_500_000), MAX_SKEW),
+            "time=1.000 ms offset=-1500.000 ms CLOCK SKEW"
+        );
+    }
+
+    #[test]
+    fn replies_without_times_count_as_received_only() {
+        let mut stats = Stats::default();
+        stats.record(Some(&reply(0, 0)));
+        stats.record(None);
+        assert_eq!(stats.summary(MAX_SKEW), "2 sent, 1 received, 50% loss");
+        assert_eq!(format_reply(&reply(0, 0), MAX_SKEW), "time unknown");
+        assert_eq!(
+            Stats::default().summary(MAX_SKEW),
+            "0 sent, 0 received, 0% loss"
+        );
+    }
+}
@@ -1,29 +1,216 @@
-use std::{pin::Pin, time::Duration};
+use std::{collections::HashSet, pin::Pin, sync::Arc, time::Duration};
 
 use crate::{
-    model::{self, ShellCommand},
+    heartbeat::{Heartbeats, Pulse},
+    hub::{Hub, OUTPUT_WINDOW},
+    model::{
+        self, Blob, BlobChunk, GetFileCommand, GetFileResult, PutFileCommand, SessionCommand,
+        ShellCommand, ShellResult,
+    },
     notssh::{
-        action::Command, not_ssh_server::NotSsh, res, Action, RegisterRequest, RegisterResponse,
-        Res,
+        action::Command, not_ssh_server::NotSsh, res, Action, ChallengeRequest, ChallengeResponse,
+        RegisterRequest, RegisterResponse, Res, RotateKeyRequest, RotateKeyResponse,
     },
+    selector, EnrollmentConfig,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use model::{
+    ActionCommand, ActionError, ActionState, Challenge, Client, EnrollmentToken, Label,
+    PingCommand, PingResult,
 };
-use chrono::Utc;
-use model::{ActionCommand, ActionState, Client, PingCommand};
+use notssh_util::error;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
+use tonic::metadata::MetadataMap;
+
+// Heartbeats tell whether the client is still there. Stream of a client which has not been heard
+// from for the timeout is closed
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(60);
+// Time a client has to answer a challenge
+const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+// Fallback for action notifications that got lost
+const ACTION_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+// Session of a client whose lease has not been renewed for this long can be taken over
+pub const SESSION_LEASE: Duration = Duration::from_secs(30);
+
+// Converts unix time in milliseconds received from client. Zero means the time is unknown
+fn from_millis(ms: i64) -> Option<DateTime<Utc>> {
+    if ms == 0 {
+        return None;
+    }
+    Utc.timestamp_millis_opt(ms).single()
+}
 
-const PING_INTERVAL: Duration = Duration::from_secs(60);
+fn client_id(metadata: &MetadataMap) -> error::Result<String> {
+    metadata
+        .get("x-client-id")
+        .ok_or(error::Error::bad_request("x-client-id header is missing"))?
+        .to_str()
+        .map(String::from)
+        .map_err(From::from)
+}
+
+// Nonce of a challenge and its signature the client sends to prove possession of its key
+fn proof(metadata: &MetadataMap) -> error::Result<(Vec<u8>, Vec<u8>)> {
+    let header = |name| {
+        metadata
+            .get_bin(name)
+            .ok_or(error::Error::unauthenticated(format!(
+                "{} header is missing",
+                name
+            )))?
+            .to_bytes()
+            .map(|b| b.to_vec())
+            .map_err(|_| error::Error::bad_request(format!("{} header is invalid", name)))
+    };
+    Ok((header("x-nonce-bin")?, header("x-signature-bin")?))
+}
+
+fn verify(public_key: &[u8], nonce: &[u8], signature: &[u8]) -> error::Result<()> {
+    let key = public_key
+        .try_into()
+        .ok()
+        .and_then(|k| VerifyingKey::from_bytes(k).ok())
+        .ok_or(error::Error::bad_request("invalid public key"))?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| error::Error::unauthenticated("invalid signature"))?;
+    key.verify_strict(nonce, &signature)
+        .map_err(|_| error::Error::unauthenticated("signature does not match client key"))
+}
+
+// Consumes the challenge, making sure it was issued for the client and signed with its key
+async fn answer_challenge(
+    client_id: &str,
+    public_key: &[u8],
+    nonce: &[u8],
+    signature: &[u8],
+    ex: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+) -> error::Result<()> {
+    match Challenge::take(nonce, client_id, ex).await {
+        Ok(_) => {}
+        Err(e) if matches!(e.kind(), error::ErrorKind::NotFound) => {
+            return Err(error::Error::unauthenticated(
+                "challenge is unknown or expired",
+            ))
+        }
+        Err(e) => return Err(e),
+    }
+    verify(public_key, nonce, signature)
+}
+
+fn error_kind(kind: res::error::Kind) -> ActionError {
+    match kind {
+        res::error::Kind::Unknown => ActionError::Unknown,
+        res::error::Kind::SpawnFailed => ActionError::SpawnFailed,
+        res::error::Kind::NotFound => ActionError::NotFound,
+        res::error::Kind::PermissionDenied => ActionError::PermissionDenied,
+        res::error::Kind::Unsupported => ActionError::Unsupported,
+        res::error::Kind::TimedOut => ActionError::TimedOut,
+        res::error::Kind::Cancelled => ActionError::Cancelled,
+        res::error::Kind::Lost => ActionError::Lost,
+    }
+}
 
 pub struct Server {
     db: PgPool,
+    hub: Arc<Hub>,
+    heartbeats: Arc<Heartbeats>,
+    // Instance of server this process runs, it holds Poll streams of clients connected here
+    instance_id: String,
+    // Clients authenticate with certificates
+    tls: bool,
+    enrollment: EnrollmentConfig,
 }
 
 impl Server {
-    pub fn new(db: PgPool) -> Self {
-        Self { db }
+    pub fn new(
+        db: PgPool,
+        hub: Arc<Hub>,
+        heartbeats: Arc<Heartbeats>,
+        instance_id: String,
+        tls: bool,
+        enrollment: EnrollmentConfig,
+    ) -> Self {
+        Self {
+            db,
+            hub,
+            heartbeats,
+            instance_id,
+            tls,
+            enrollment,
+        }
+    }
+
+    /// Client id proven by the certificate of the peer. None if certificates are not required
+    fn certified_id<T>(&self, request: &tonic::Request<T>) -> error::Result<Option<String>> {
+        if !self.tls {
+            return Ok(None);
+        }
+        let certs = request.peer_certs().ok_or(error::Error::permission_denied(
+            "client certificate is required",
+        ))?;
+        let cert = certs.first().ok_or(error::Error::permission_denied(
+            "client certificate is required",
+        ))?;
+        let (_, cert) = x509_parser::parse_x509_certificate(cert.get_ref())
+            .map_err(|_| error::Error::permission_denied("invalid client certificate"))?;
+        let id = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .ok_or(error::Error::permission_denied(
+                "client certificate has no common name",
+            ))?;
+        Ok(Some(id.to_owned()))
+    }
+
+    async fn save_facts(db: &PgPool, client_id: &str, facts: res::Facts) -> error::Result<()> {
+        let mut tx = db.begin().await?;
+        Label::delete_reported(client_id, &mut tx).await?;
+        for (key, value) in facts.labels {
+            if let Err(e) = selector::validate_key(&key).and(selector::validate_value(&value)) {
+                log::warn!("client '{}' reported invalid label: {}", client_id, e);
+                continue;
+            }
+            Label::new(client_id.to_owned(), key, value, false)
+                .upsert(&mut tx)
+                .await?;
+        }
+        model::Facts {
+            client_id: client_id.to_owned(),
+            hostname: facts.hostname,
+            machine_id: facts.machine_id,
+            os_release: facts.os_release,
+            kernel: facts.kernel,
+            arch: facts.arch,
+            cpus: facts.cpus as i32,
+            memory: facts.memory as i64,
+            addresses: facts.addresses,
+            agent_version: facts.agent_version,
+            updated_at: Utc::now(),
+        }
+        .upsert(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
     }
 
-    async fn poll_results(db: PgPool, client_id: String, mut stream: tonic::Streaming<Res>) {
+    async fn poll_results(
+        db: PgPool,
+        hub: Arc<Hub>,
+        client_id: String,
+        generation: u64,
+        epoch: i64,
+        pulse: Arc<Pulse>,
+        mut stream: tonic::Streaming<Res>,
+    ) {
         log::debug!("Begin polling results for {}", client_id);
+        // Actions whose output nobody watches, the client has been told not to hold it back
+        let mut unwatched = HashSet::new();
         loop {
             let res = match stream.message().await {
                 Ok(res) => match res {
@@ -38,6 +225,62 @@ impl Server {
                     break;
                 }
             };
+            // Taken before anything else, so round trip of a ping does not include its handling
+            let returned_at = Utc::now().timestamp_micros();
+            pulse.seen();
+
+            // Heartbeats are kept in memory, they are written to the database in batches
+            if let Some(res::Result::Heartbeat(hb)) = &res.result {
+                let rtt = pulse.reply(hb);
+                log::debug!("heartbeat {} of '{}' took {:?}", hb.seq, client_id, rtt);
+                continue;
+            }
+            // Client built before heartbeats reports them as unsupported, which is a sign of life
+            // all the same
+            if res.id.is_empty() && matches!(res.result, Some(res::Result::Error(_))) {
+                continue;
+            }
+
+            // Output chunks are relayed to whoever is watching and never hit the database, unless
+            // the watcher is in another process. The client sends them only as fast as the
+            // watcher credits them, so a slow watcher holds up its action alone
+            if let Some(
+                r @ (res::Result::Output(_)
+                | res::Result::SessionOutput(_)
+                | res::Result::FileChunk(_)),
+            ) = res.result
+            {
+                match hub.forward_output(&db, &res.id, r).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        if unwatched.insert(res.id.clone()) {
+                            hub.push_action(&client_id, Action::credit(res.id, 0));
+                        }
+                    }
+                    // Chunk is gone, its room is given back
+                    Err(e) => {
+                        log::error!("cannot relay output of '{}': {}", res.id, e);
+                        hub.push_action(&client_id, Action::credit(res.id, 1));
+                    }
+                }
+                continue;
+            }
+            // Facts are not tied to any action, last report replaces the previous one
+            if let Some(res::Result::Facts(facts)) = res.result {
+                if let Err(e) = Self::save_facts(&db, &client_id, facts).await {
+                    log::error!("cannot save facts of '{}': {}", client_id, e);
+                }
+                continue;
+            }
+            // Attached operator learns about session exit or failure right away, the action is
+            // finished as usual
+            if let Some(r @ (res::Result::SessionExit(_) | res::Result::Error(_))) = &res.result {
+                if let Err(e) = hub.forward_output(&db, &res.id, r.clone()).await {
+                    log::error!("cannot relay end of '{}': {}", res.id, e);
+                }
+            }
+            unwatched.remove(&res.id);
+
             let mut tx = match db.begin().await {
                 Ok(tx) => tx,
                 Err(e) => {
@@ -46,7 +289,7 @@ impl Server {
                 }
             };
 
-            let mut act = match model::Action::get(&res.id, &mut tx).await {
+            let mut act = match model::Action::get_for_update(&res.id, &mut tx).await {
                 Ok(act) => act,
                 Err(e) => {
                     log::error!("cannot get action from database: {}", e);
@@ -54,6 +297,19 @@ impl Server {
                 }
             };
 
+            // Action may have been timed out or cancelled already, its result is of no use then.
+            // Lost action may have been finished by the client all the same, its result kept
+            // across connections settles it
+            if matches!(act.state, ActionState::Lost) {
+                log::info!("result of lost action '{}' arrived after all", res.id);
+                act.result = None;
+                act.error = None;
+                act.error_kind = None;
+            } else if act.state.is_done() {
+                log::warn!("result of '{}' arrived after the action was done", res.id);
+                continue;
+            }
+
             if let Some(r) = res.result.clone() {
                 match r {
                     res::Result::Pong(pong) => {
@@ -61,6 +317,19 @@ impl Server {
                             log::error!("cannot delete ping command from database: {}", e);
                             continue;
                         }
+                        // Clients built before pings carried timestamps don't echo them
+                        if pong.sent_at != 0 && pong.received_at != 0 {
+                            let result = PingResult::new(
+                                res.id.clone(),
+                                pong.sent_at,
+                                pong.received_at,
+                                returned_at,
+                            );
+                            if let Err(e) = result.create(&mut tx).await {
+                                log::error!("cannot create ping result in database: {}", e);
+                                continue;
+                            }
+                        }
                         act.result = Some(pong.pong.into());
                     }
                     res::Result::Purge(_) => act.result = Some("purged".into()),
@@ -69,20 +338,97 @@ impl Server {
                             log::error!("cannot delete shell command from database: {}", e);
                             continue;
                         }
-                        if shell.code != 0 {
-                            act.error = Some(shell.stderr);
+                        let mut result = ShellResult::new(
+                            res.id.clone(),
+                            shell.code,
+                            shell.stdout,
+                            shell.stderr,
+                        );
+                        if shell.signal != 0 {
+                            result.signal = Some(shell.signal);
+                        }
+                        result.started_at = from_millis(shell.started_at);
+                        result.finished_at = from_millis(shell.finished_at);
+                        if let Err(e) = result.create(&mut tx).await {
+                            log::error!("cannot create shell result in database: {}", e);
+                            continue;
+                        }
+                    }
+                    res::Result::SessionExit(exit) => {
+                        if let Err(e) = SessionCommand::delete(&res.id, &mut tx).await {
+                            log::error!("cannot delete session command from database: {}", e);
+                            continue;
+                        }
+                        act.result = Some(exit.code.to_string().into());
+                    }
+                    res::Result::PutFile(put) => {
+                        if let Err(e) = PutFileCommand::delete(&res.id, &mut tx).await {
+                            log::error!("cannot delete put file command from database: {}", e);
+                            continue;
+                        }
+                        if put.error.is_empty() {
+                            act.result = Some(put.sha256);
                         } else {
-                            act.result = Some(shell.stdout);
+                            act.error = Some(put.error.into());
                         }
                     }
+                    res::Result::GetFile(get) => {
+                        if let Err(e) = GetFileCommand::delete(&res.id, &mut tx).await {
+                            log::error!("cannot delete get file command from database: {}", e);
+                            continue;
+                        }
+                        if get.error.is_empty() {
+                            let mut result = GetFileResult::new(
+                                res.id.clone(),
+                                get.size as i64,
+                                get.sha256,
+                                get.mode as i32,
+                            );
+                            result.modified_at = from_millis(get.modified_at);
+                            if let Err(e) = result.create(&mut tx).await {
+                                log::error!("cannot create get file result in database: {}", e);
+                                continue;
+                            }
+                        } else {
+                            act.error = Some(get.error.into());
+                        }
+                    }
+                    res::Result::Error(err) => {
+                        log::warn!(
+                            "client '{}' could not execute '{}': {}",
+                            client_id,
+                            res.id,
+                            err.message
+                        );
+                        act.error_kind = Some(error_kind(err.kind()));
+                        act.error = Some(err.message.into());
+                    }
+                    res::Result::Output(_)
+                    | res::Result::SessionOutput(_)
+                    | res::Result::FileChunk(_) => {
+                        unreachable!("output is relayed before")
+                    }
+                    res::Result::Facts(_) => unreachable!("facts are saved before"),
+                    res::Result::Heartbeat(_) => unreachable!("heartbeats are recorded before"),
                 }
             }
-            act.state = ActionState::Finished;
+            act.state = match act.error_kind {
+                Some(ActionError::TimedOut) => ActionState::TimedOut,
+                Some(ActionError::Cancelled) => ActionState::Cancelled,
+                Some(ActionError::Lost) => ActionState::Lost,
+                Some(_) => ActionState::Failed,
+                None => ActionState::Finished,
+            };
             if let Err(e) = act.update(&mut tx).await {
                 log::error!("cannot update action in database: {}", e);
                 continue;
             }
 
+            if let Err(e) = model::Action::notify_finished(&res.id, &mut tx).await {
+                log::error!("cannot notify about finished action: {}", e);
+                continue;
+            }
+
             let mut client = match Client::get(&client_id, &mut tx).await {
                 Ok(client) => client,
                 Err(e) => {
@@ -99,6 +445,9 @@ impl Server {
 
             if let Err(e) = tx.commit().await {
                 log::error!("cannot commit transaction: {}", e);
+            } else {
+                // Waiters in this process don't have to wait for the notification roundtrip
+                hub.wake_result(&res.id);
             }
 
             if res.result.is_none() {
@@ -111,6 +460,7 @@ impl Server {
             }
         }
         log::debug!("stopped polling results for {}", client_id);
+        hub.disconnect_client(&client_id, generation);
 
         let mut tx = match db.begin().await {
             Ok(tx) => tx,
@@ -120,20 +470,26 @@ impl Server {
             }
         };
 
-        let mut client = match Client::get(&client_id, &mut tx).await {
-            Ok(client) => client,
+        match Client::end_session(&client_id, epoch, &mut tx).await {
+            Ok(true) => {}
+            // Newer session owns the client and its actions now
+            Ok(false) => {
+                log::info!("session {} of '{}' has been taken over", epoch, client_id);
+                return;
+            }
             Err(e) => {
-                log::error!("cannot get client '{}' from database: {}", client_id, e);
+                log::error!(
+                    "cannot update disconnected client '{}' in database: {}",
+                    client_id,
+                    e
+                );
                 return;
             }
-        };
-        client.connected = false;
-        client.address = None;
-        client.last_online = Utc::now();
+        }
 
-        if let Err(e) = client.update(&mut tx).await {
+        if let Err(e) = model::Action::release_running(&client_id, &mut tx).await {
             log::error!(
-                "cannot update disconnected client '{}' in database: {}",
+                "cannot release actions of disconnected client '{}': {}",
                 client_id,
                 e
             );
@@ -143,51 +499,9 @@ impl Server {
         if let Err(e) = tx.commit().await {
             log::error!("cannot commit transaction: {}", e);
         }
-    }
 
-    async fn ping_client(pool: PgPool, client_id: String) {
-        let mut i = tokio::time::interval(PING_INTERVAL);
-        loop {
-            i.tick().await;
-
-            let mut tx = match pool.begin().await {
-                Ok(tx) => tx,
-                Err(e) => {
-                    log::error!(target: "HC", "cannot begin transaction: {}", e);
-                    break;
-                }
-            };
-
-            let client = match Client::get(&client_id, &mut tx).await {
-                Ok(client) => client,
-                Err(e) => {
-                    log::error!(target: "HC", "cannot get client from database: {}", e);
-                    break;
-                }
-            };
-            if !client.connected {
-                log::info!(target: "HC", "client '{}' is not connected, stopping", client.id);
-                break;
-            }
-
-            let act = model::Action::new(client.id, ActionCommand::Ping);
-            let cmd = PingCommand::new(act.id.clone(), String::from("ping"));
-
-            if let Err(e) = act.create(&mut tx).await {
-                log::error!(target: "HC", "cannot create action in database: {}", e);
-                break;
-            }
-
-            if let Err(e) = cmd.create(&mut tx).await {
-                log::error!(target: "HC", "cannot create ping command in database: {}", e);
-                break;
-            }
-
-            if let Err(e) = tx.commit().await {
-                log::error!(target: "HC", "cannot commit transaction: {}", e);
-                break;
-            }
-        }
+        // Let the action stream notice that the client is gone
+        hub.wake_actions(&client_id);
     }
 }
 
@@ -207,11 +521,20 @@ impl NotSsh for Server {
     ) -> std::result::Result<tonic::Response<RegisterResponse>, tonic::Status> {
         log::info!("Server: Register");
 
-        let client = match request.remote_addr() {
-            Some(addr) => Client::with_address(format!("{}", addr)),
+        let certified_id = self.certified_id(&request)?;
+        let mut client = match certified_id.clone() {
+            Some(id) => Client::with_id(id),
             None => Client::new(),
         };
+        client.address = request.remote_addr().map(|addr| addr.to_string());
         let id = client.id.clone();
+        let request = request.into_inner();
+        let public_key = request.public_key;
+        if let Err(e) = VerifyingKey::try_from(public_key.as_slice()) {
+            log::warn!("client presented invalid public key: {}", e);
+            return Err(tonic::Status::invalid_argument("invalid public key"));
+        }
+        client.public_key = Some(public_key.clone());
         let mut tx = match self.db.begin().await {
             Ok(tx) => tx,
             Err(e) => {
@@ -220,6 +543,49 @@ impl NotSsh for Server {
             }
         };
 
+        // Certificate always maps to the same client, so registering again just returns it. The
+        // certificate proves identity, so the key is trusted as well
+        if certified_id.is_some() {
+            match Client::get(&id, &mut tx).await {
+                Ok(mut existing) => {
+                    existing.public_key = Some(public_key);
+                    if let Err(e) = existing.update(&mut tx).await {
+                        log::error!("cannot update client in database: {}", e);
+                        return Err(e.into());
+                    }
+                    if let Err(e) = tx.commit().await {
+                        log::error!("cannot commit transaction: {}", e);
+                        return Err(tonic::Status::internal("internal error"));
+                    }
+                    log::info!("Server: client '{}' registered again", id);
+                    return Ok(tonic::Response::new(RegisterResponse { id }));
+                }
+                Err(e) if matches!(e.kind(), error::ErrorKind::NotFound) => {}
+                Err(e) => {
+                    log::error!("cannot get client from database: {}", e);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        if self.enrollment.require_token || !request.token.is_empty() {
+            let sha256 = Sha256::digest(request.token.as_bytes()).to_vec();
+            match EnrollmentToken::redeem(&sha256, &mut tx).await {
+                Ok(token) => {
+                    log::info!("Server: client '{}' enrolled with token '{}'", id, token.id)
+                }
+                Err(e) if matches!(e.kind(), error::ErrorKind::NotFound) => {
+                    log::warn!("client presented unknown or expired enrollment token");
+                    return Err(tonic::Status::permission_denied("invalid enrollment token"));
+                }
+                Err(e) => {
+                    log::error!("cannot redeem enrollment token: {}", e);
+                    return Err(e.into());
+                }
+            }
+        }
+        client.approved = !self.enrollment.require_approval;
+
         if let Err(e) = client.create(&mut tx).await {
             log::error!("cannot insert client in database: {}", e);
             return Err(e.into());
@@ -230,7 +596,14 @@ impl NotSsh for Server {
             return Err(tonic::Status::internal("internal error"));
         }
 
-        log::info!("Server: new client registered. ID {}", id);
+        if self.enrollment.require_approval {
+            log::info!(
+                "Server: new client registered, waiting for approval. ID {}",
+                id
+            );
+        } else {
+            log::info!("Server: new client registered. ID {}", id);
+        }
         Ok(tonic::Response::new(RegisterResponse { id }))
     }
 
@@ -240,13 +613,20 @@ impl NotSsh for Server {
     ) -> std::result::Result<tonic::Response<Self::PollStream>, tonic::Status> {
         log::info!("Server: Poll");
 
-        let metadata = request.metadata();
-        let id = metadata
-            .get("x-client-id")
-            .ok_or(tonic::Status::invalid_argument(
-                "x-client-id header is missing",
-            ))?;
-        let id = id.to_str().unwrap().to_owned();
+        let id = client_id(request.metadata())?;
+        let (nonce, signature) = proof(request.metadata())?;
+        if let Some(certified_id) = self.certified_id(&request)? {
+            if certified_id != id {
+                log::warn!(
+                    "client '{}' presented certificate of '{}'",
+                    id,
+                    certified_id
+                );
+                return Err(tonic::Status::permission_denied(
+                    "client id does not match certificate",
+                ));
+            }
+        }
 
         let mut tx = match self.db.begin().await {
             Ok(tx) => tx,
@@ -256,20 +636,41 @@ impl NotSsh for Server {
             }
         };
 
-        let mut client = match Client::get(&id, &mut tx).await {
+        let mut client = match Client::get_for_update(&id, &mut tx).await {
             Ok(client) => client,
             Err(e) => {
                 log::error!("cannot get client from database: {}", e);
                 return Err(e.into());
             }
         };
-        let client_id = client.id.clone();
 
-        if client.connected {
-            return Err(tonic::Status::invalid_argument(
-                "client is already connected",
+        // Knowing the id is not enough, the client has to prove it holds the key as well
+        let public_key = match client.public_key.as_deref() {
+            Some(key) => key,
+            None => {
+                return Err(tonic::Status::failed_precondition(
+                    "client has no key, it has to be set first",
+                ))
+            }
+        };
+        if let Err(e) = answer_challenge(&id, public_key, &nonce, &signature, &mut tx).await {
+            log::warn!("client '{}' failed to prove its identity: {}", id, e);
+            return Err(e.into());
+        }
+
+        if !client.approved {
+            return Err(tonic::Status::permission_denied(
+                "client is waiting for approval",
             ));
         }
+
+        // Session which stopped renewing its lease is dead, e.g. server went down with it
+        if client.is_live() {
+            return Err(tonic::Status::already_exists("client is already connected"));
+        }
+        if client.connected {
+            log::warn!("taking over expired session {} of '{}'", client.epoch, id);
+        }
         client.connected = true;
         client.last_online = Utc::now();
         if let Some(addr) = request.remote_addr() {
@@ -281,74 +682,305 @@ impl NotSsh for Server {
             return Err(e.into());
         }
 
+        let epoch =
+            match Client::begin_session(&id, &self.instance_id, SESSION_LEASE, &mut tx).await {
+                Ok(epoch) => epoch,
+                Err(e) => {
+                    log::error!("cannot begin session in database: {}", e);
+                    return Err(e.into());
+                }
+            };
+
+        // Previous connection may have ended without its actions being released, e.g. when
+        // server went down along with it
+        if let Err(e) = model::Action::release_running(&id, &mut tx).await {
+            log::error!("cannot release actions of client in database: {}", e);
+            return Err(e.into());
+        }
+
         if let Err(e) = tx.commit().await {
             log::error!("cannot commit transaction: {}", e);
             return Err(tonic::Status::internal("internal error"));
         }
 
-        log::info!("Server: client with ID '{}' connected", id);
-        tokio::spawn(Self::ping_client(self.db.clone(), client_id));
-
+        log::info!(
+            "Server: client with ID '{}' connected, session {}",
+            id,
+            epoch
+        );
         let res = request.into_inner();
         let db = self.db.clone();
-        tokio::spawn(Self::poll_results(db.clone(), id.clone(), res));
+        // Lease is renewed along with the rest held by this instance
+        let mut inbox = self.hub.connect_client(&id, epoch);
+        let pulse = Arc::new(self.heartbeats.pulse(&id));
+        tokio::spawn(Self::poll_results(
+            db.clone(),
+            self.hub.clone(),
+            id.clone(),
+            inbox.generation,
+            epoch,
+            pulse.clone(),
+            res,
+        ));
+        let wakeup = self.hub.actions(&id);
         let output = async_stream::try_stream! {
+            // Notifications may get lost, so the database is swept once in a while anyway
+            let mut sweep = tokio::time::interval(ACTION_SWEEP_INTERVAL);
+            let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+            let mut dispatch = true;
             loop {
-                let mut tx = match db.begin().await {
-                    Ok(tx) => tx,
-                    Err(e) => {
-                        log::error!("cannot begin transaction: {}", e);
-                        continue;
-                    },
-                };
-
-                let client = model::Client::get(&id, &mut tx).await?;
-                // a hack to return error from try_stream macro, which only supports '?'
-                client.connected.then_some(()).ok_or(tonic::Status::cancelled("client disconnected"))?;
-
-                let mut act = match model::Action::get_next(&id, &mut tx).await? {
-                    Some(act) => act,
-                    None => {
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                        continue;
-                    },
-                };
-
-                let peer_act = match act.command {
-                    ActionCommand::Ping => {
-                        let ping_cmd = PingCommand::get(&act.id, &mut tx).await?;
-                        Action {
-                            id: act.id.clone(),
-                            command: Some(Command::ping(ping_cmd.data)),
+                // Dispatch everything that is pending, then sleep until notified
+                if dispatch {
+                    loop {
+                        let mut tx = match db.begin().await {
+                            Ok(tx) => tx,
+                            Err(e) => {
+                                log::error!("cannot begin transaction: {}", e);
+                                break;
+                            },
+                        };
+
+                        let client = model::Client::get(&id, &mut tx).await?;
+                        // a hack to return error from try_stream macro, which only supports '?'
+                        client.connected.then_some(()).ok_or(tonic::Status::cancelled("client disconnected"))?;
+                        (client.epoch == epoch).then_some(()).ok_or(tonic::Status::aborted("session has been taken over"))?;
+
+                        let mut act = match model::Action::get_next(&id, &mut tx).await? {
+                            Some(act) => act,
+                            None => break,
+                        };
+
+                        // Overdue action is not worth sending, the client would kill it right away.
+                        // Neither is a queued one, that has waited for the client for too long
+                        let expired = act.is_expired();
+                        let timeout = match act.deadline().map(|d| (d - Utc::now()).to_std()) {
+                            Some(Ok(left)) if !left.is_zero() && !expired => left.as_millis() as u64,
+                            None if !expired => 0,
+                            _ => {
+                                let act_id = act.id.clone();
+                                act.state = ActionState::TimedOut;
+                                act.update(&mut tx).await?;
+                                model::Action::notify_finished(&act_id, &mut tx).await?;
+                                if let Err(e) = tx.commit().await {
+                                    log::error!("cannot commit transaction: {}", e);
+                                    break;
+                                }
+                                continue;
+                            }
+                        };
+
+                        let mut blob = None;
+                        let mut peer_act = match act.command {
+                            ActionCommand::Ping => {
+                                let ping_cmd = PingCommand::get(&act.id, &mut tx).await?;
+                                Action::new(act.id.clone(), Command::ping(ping_cmd.data))
+                            },
+                            ActionCommand::Purge => {
+                                Action::new(act.id.clone(), Command::purge())
+                            },
+                            ActionCommand::Shell => {
+                                let shell_cmd = ShellCommand::get(&act.id, &mut tx).await?;
+                                Action::new(act.id.clone(), Command::shell(shell_cmd.cmd, shell_cmd.args, shell_cmd.stdin, shell_cmd.stream))
+                            },
+                            ActionCommand::Session => {
+                                let session_cmd = SessionCommand::get(&act.id, &mut tx).await?;
+                                Action::new(act.id.clone(), Command::session(session_cmd.rows as u32, session_cmd.cols as u32, session_cmd.term))
+                            },
+                            ActionCommand::PutFile => {
+                                let put_cmd = PutFileCommand::get(&act.id, &mut tx).await?;
+                                let b = Blob::get(&put_cmd.blob_id, &mut tx).await?;
+                                let peer_act = Action::new(act.id.clone(), Command::put_file(put_cmd.path, put_cmd.mode as u32, put_cmd.owner, b.size as u64, b.sha256.clone(), put_cmd.atomic));
+                                blob = Some(b);
+                                peer_act
+                            }
+                            ActionCommand::GetFile => {
+                                let get_cmd = GetFileCommand::get(&act.id, &mut tx).await?;
+                                Action::new(act.id.clone(), Command::get_file(get_cmd.path))
+                            }
+                        };
+                        peer_act.timeout = timeout;
+                        peer_act.window = OUTPUT_WINDOW;
+                        act.started_at = Some(Utc::now());
+                        act.state = ActionState::Running;
+                        act.update(&mut tx).await?;
+
+                        if let Err(e) = tx.commit().await {
+                            log::error!("cannot commit transaction: {}", e);
+                            break;
                         }
-                    },
-                    ActionCommand::Purge => {
-                        Action {
-                            id: act.id.clone(),
-                            command: Some(Command::purge()),
+
+                        // Round trip is measured from the moment ping leaves
+                        if let Some(Command::Ping(ping)) = &mut peer_act.command {
+                            ping.sent_at = Utc::now().timestamp_micros();
                         }
-                    },
-                    ActionCommand::Shell => {
-                        let shell_cmd = ShellCommand::get(&act.id, &mut tx).await?;
-                        Action {
-                            id: act.id.clone(),
-                            command: Some(Command::shell(shell_cmd.cmd, shell_cmd.args, shell_cmd.stdin)),
+                        let act_id = peer_act.id.clone();
+                        yield peer_act;
+
+                        // File content follows chunk by chunk, so it is never loaded as a whole
+                        if let Some(blob) = blob {
+                            let mut offset = 0;
+                            for seq in 0..blob.chunks {
+                                let chunk = BlobChunk::get(&blob.id, seq, &db).await?;
+                                let len = chunk.data.len() as u64;
+                                yield Action::new(act_id.clone(), Command::file_chunk(offset, chunk.data));
+                                offset += len;
+                            }
                         }
                     }
-                };
-                act.started_at = Some(Utc::now());
-                act.state = ActionState::Running;
-                act.update(&mut tx).await?;
-
-                if let Err(e) = tx.commit().await {
-                    log::error!("cannot commit transaction: {}", e);
-                    continue;
                 }
 
-                yield peer_act;
+                // Actions pushed through the hub don't require looking into the database
+                let pushed = tokio::select! {
+                    _ = &mut inbox.lost => Err(tonic::Status::aborted("session has been taken over")),
+                    _ = wakeup.notified() => Ok(None),
+                    _ = sweep.tick() => Ok(None),
+                    _ = heartbeat.tick() => if pulse.is_silent(HEARTBEAT_TIMEOUT) {
+                        log::warn!("client '{}' stopped answering heartbeats", id);
+                        Err(tonic::Status::unavailable("client stopped answering heartbeats"))
+                    } else {
+                        Ok(Some(pulse.beat()))
+                    },
+                    act = inbox.rx.recv() => match act {
+                        Some(act) => Ok(Some(act)),
+                        None => break,
+                    },
+                }?;
+                dispatch = pushed.is_none();
+                if let Some(act) = pushed {
+                    yield act;
+                }
             }
         };
 
         Ok(tonic::Response::new(Box::pin(output) as Self::PollStream))
     }
+
+    async fn challenge(
+        &self,
+        request: tonic::Request<ChallengeRequest>,
+    ) -> std::result::Result<tonic::Response<ChallengeResponse>, tonic::Status> {
+        log::info!("Server: Challenge");
+
+        let id = client_id(request.metadata())?;
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+
+        if let Err(e) = Client::get(&id, &mut tx).await {
+            log::error!("cannot get client from database: {}", e);
+            return Err(e.into());
+        }
+
+        let mut nonce = vec![0; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let challenge = Challenge::new(nonce.clone(), id, CHALLENGE_TTL);
+        if let Err(e) = challenge.create(&mut tx).await {
+            log::error!("cannot create challenge in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        Ok(tonic::Response::new(ChallengeResponse { nonce }))
+    }
+
+    async fn rotate_key(
+        &self,
+        request: tonic::Request<RotateKeyRequest>,
+    ) -> std::result::Result<tonic::Response<RotateKeyResponse>, tonic::Status> {
+        log::info!("Server: RotateKey");
+
+        let id = client_id(request.metadata())?;
+        let (nonce, signature) = proof(request.metadata())?;
+        let certified = match self.certified_id(&request)? {
+            Some(certified_id) if certified_id != id => {
+                return Err(tonic::Status::permission_denied(
+                    "client id does not match certificate",
+                ));
+            }
+            Some(_) => true,
+            None => false,
+        };
+        let request = request.into_inner();
+
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+
+        let mut client = match Client::get(&id, &mut tx).await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("cannot get client from database: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        // New key signs the same nonce, so the client can't set a key it does not hold
+        if let Err(e) = answer_challenge(
+            &id,
+            &request.public_key,
+            &nonce,
+            &request.signature,
+            &mut tx,
+        )
+        .await
+        {
+            log::warn!("client '{}' failed to prove its new key: {}", id, e);
+            return Err(e.into());
+        }
+        // Registered before clients had keys. Anybody knowing the id could present a key, so
+        // the first one is trusted only if certificate proves the client, otherwise operator has
+        // to approve it
+        let mut unproven = false;
+        match client.public_key.as_deref() {
+            // Response to the previous attempt got lost, the key is rotated already
+            Some(key) if key == request.public_key => {}
+            Some(key) => {
+                if let Err(e) = verify(key, &nonce, &signature) {
+                    log::warn!("client '{}' failed to prove its current key: {}", id, e);
+                    return Err(e.into());
+                }
+            }
+            None if certified => log::info!("client '{}' has set its first key", id),
+            None => {
+                log::warn!(
+                    "client '{}' has set its first key, it has to be approved again",
+                    id
+                );
+                unproven = true;
+            }
+        }
+
+        client.public_key = Some(request.public_key);
+        if let Err(e) = client.update(&mut tx).await {
+            log::error!("cannot update client in database: {}", e);
+            return Err(e.into());
+        }
+
+        if unproven {
+            if let Err(e) = Client::revoke_approval(&id, &mut tx).await {
+                log::error!("cannot update client in database: {}", e);
+                return Err(e.into());
+            }
+        }
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        log::info!("Server: client '{}' rotated its key", id);
+        Ok(tonic::Response::new(RotateKeyResponse {}))
+    }
 }
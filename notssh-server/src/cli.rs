@@ -1,25 +1,425 @@
-use std::time::Duration;
+use std::{collections::HashMap, pin::Pin, sync::Arc, time::Duration};
 
-use crate::notssh_cli::{
-    list_response, not_ssh_cli_server::NotSshCli, ListRequest, ListResponse, PingRequest,
-    PingResponse, PurgeRequest, PurgeResponse, ShellRequest, ShellResponse,
+use crate::{
+    notssh::{action::Command, res, Action},
+    notssh_cli::{
+        attach_request, attach_response, get_result_response,
+        job_status_response::{self, target},
+        list_response,
+        not_ssh_cli_server::NotSshCli,
+        pull_response, rollout_response, shell_output, shell_stream_response, submit_job_request,
+        ApproveRequest, ApproveResponse, AttachRequest, AttachResponse, CancelRequest,
+        CancelResponse, CreateTokenRequest, CreateTokenResponse, Delivery, GetResultRequest,
+        GetResultResponse, JobStatusRequest, JobStatusResponse, LabelRequest, LabelResponse,
+        ListRequest, ListResponse, PingRequest, PingResponse, PullRequest, PullResponse,
+        PurgeRequest, PurgeResponse, PutFileRequest, PutFileResponse, RevokeTokenRequest,
+        RevokeTokenResponse, RolloutRequest, RolloutResponse, ShellOutput, ShellRequest,
+        ShellResponse, ShellStreamResponse, SubmitJobRequest, SubmitJobResponse, SubmitResponse,
+        UploadRequest, UploadResponse,
+    },
 };
+use chrono::Utc;
 use notssh_util::error;
-use sqlx::PgPool;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::{sync::mpsc, task::JoinSet};
+use uuid::Uuid;
 
-use crate::model::{self, ActionCommand, ActionState, Client, ListOptions};
+use crate::{
+    hub::{Hub, OUTPUT_WINDOW},
+    model::{
+        self, ActionCommand, ActionError, ActionState, Client, Facts, Job, Label, ListOptions,
+    },
+    selector::{self, Selector},
+};
+
+// Fallback for result notifications that got lost
+const RESULT_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+// How often attached session checks that the client is still connected
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// Uploaded files are stored and sent to clients in chunks of this size
+const BLOB_CHUNK_SIZE: usize = 65536;
+// Chunks of an upload written per transaction, so a large file does not hold one open throughout
+const CHUNKS_PER_TX: i32 = 64;
 
+#[derive(Clone)]
 pub struct CliServer {
     db: PgPool,
+    hub: Arc<Hub>,
 }
 
 impl CliServer {
+    // Timeouts of actions, unless requested otherwise
     const PING_TIMEOUT: Duration = Duration::from_secs(10);
     const PURGE_TIMEOUT: Duration = Duration::from_secs(60);
     const SHELL_TIMEOUT: Duration = Duration::from_secs(3600);
+    const PUT_FILE_TIMEOUT: Duration = Duration::from_secs(3600);
+    const GET_FILE_TIMEOUT: Duration = Duration::from_secs(3600);
+    // Time submitted actions wait for their clients to connect
+    const SUBMIT_TTL: Duration = Duration::from_secs(24 * 3600);
+    // Length of generated enrollment tokens
+    const TOKEN_LENGTH: usize = 40;
+
+    pub fn new(db: PgPool, hub: Arc<Hub>) -> Self {
+        Self { db, hub }
+    }
+
+    async fn create_shell(
+        &self,
+        action_id: String,
+        request: ShellRequest,
+        stream: bool,
+        timeout: Duration,
+        ttl: Option<Duration>,
+    ) -> std::result::Result<String, tonic::Status> {
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+        let client = match Client::get(&request.id, &mut tx).await {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("cannot get client from database: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        let mut act = model::Action::with_id(action_id, client.id, ActionCommand::Shell)
+            .with_delivery(delivery(request.delivery()))
+            .with_timeout(timeout);
+        if let Some(ttl) = ttl {
+            act = act.with_expiry(ttl);
+        }
+        let cmd = model::ShellCommand::new(
+            act.id.clone(),
+            request.cmd,
+            request.args,
+            request.stdin,
+            stream,
+        );
+        let id = act.id.clone();
+
+        if let Err(e) = act.create(&mut tx).await {
+            log::error!("cannot create action in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = cmd.create(&mut tx).await {
+            log::error!("cannot create shell command in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        Ok(id)
+    }
+
+    async fn create_put_file(
+        &self,
+        request: PutFileRequest,
+        timeout: Duration,
+        ttl: Option<Duration>,
+    ) -> std::result::Result<(String, model::Blob), tonic::Status> {
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+        let client = match Client::get(&request.id, &mut tx).await {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("cannot get client from database: {}", e);
+                return Err(e.into());
+            }
+        };
+        let blob = match Self::blob_for_use(&request.blob_id, &mut tx).await {
+            Ok(b) => b,
+            Err(e) => {
+                log::error!("cannot get blob from database: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        let mut act = model::Action::with_id(
+            action_id(&request.action_id),
+            client.id,
+            ActionCommand::PutFile,
+        )
+        .with_delivery(delivery(request.delivery()))
+        .with_timeout(timeout);
+        if let Some(ttl) = ttl {
+            act = act.with_expiry(ttl);
+        }
+        let cmd = model::PutFileCommand::new(
+            act.id.clone(),
+            blob.id.clone(),
+            request.path,
+            request.mode as i32,
+            request.owner,
+            request.atomic,
+        );
+        let id = act.id.clone();
+
+        if let Err(e) = act.create(&mut tx).await {
+            log::error!("cannot create action in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = cmd.create(&mut tx).await {
+            log::error!("cannot create put file command in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        Ok((id, blob))
+    }
+
+    // Creates shell action and waits for its result
+    async fn run_shell(
+        &self,
+        action_id: String,
+        request: ShellRequest,
+    ) -> std::result::Result<ShellResponse, tonic::Status> {
+        let timeout = action_timeout(request.timeout, Self::SHELL_TIMEOUT);
+        let id = self
+            .create_shell(action_id, request, false, timeout, None)
+            .await?;
+
+        let act =
+            match tokio::time::timeout(timeout, wait_for_result(&id, self.db.clone(), &self.hub))
+                .await
+            {
+                Ok(r) => match r {
+                    Ok(r) => r,
+                    Err(e) => {
+                        log::error!("cannot get action from database: {}", e);
+                        return Err(e.into());
+                    }
+                },
+                Err(e) => {
+                    log::error!("gave up waiting for result after {}", e);
+                    return Err(tonic::Status::deadline_exceeded("action timeout"));
+                }
+            };
+        if let Some(e) = failure(&act) {
+            return Err(e);
+        }
+
+        shell_response(&act.id, &self.db).await
+    }
+
+    // Runs the command on every client of the batch at once under the given action ids and
+    // reports them as they finish. Returns number of clients which failed
+    async fn run_batch(
+        &self,
+        ids: &[String],
+        action_ids: &[String],
+        shell: &ShellRequest,
+        results: &mpsc::UnboundedSender<rollout_response::Result>,
+    ) -> u32 {
+        let mut set = JoinSet::new();
+        for (id, action_id) in ids.iter().zip(action_ids) {
+            let server = self.clone();
+            let request = ShellRequest {
+                id: id.clone(),
+                action_id: String::new(),
+                ..shell.clone()
+            };
+            let action_id = action_id.clone();
+            set.spawn(async move {
+                let id = request.id.clone();
+                let res = server.run_shell(action_id, request).await;
+                (id, res)
+            });
+        }
+        let mut failed = 0;
+        while let Some(res) = set.join_next().await {
+            let (id, res) = match res {
+                Ok(res) => res,
+                Err(e) => {
+                    log::error!("rollout task failed: {}", e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            let result = match res {
+                Ok(response) => {
+                    if response.code != 0 || response.signal != 0 {
+                        failed += 1;
+                    }
+                    rollout_response::Result {
+                        id,
+                        response: Some(response),
+                        error: String::new(),
+                    }
+                }
+                Err(e) => {
+                    failed += 1;
+                    rollout_response::Result {
+                        id,
+                        response: None,
+                        error: e.message().to_owned(),
+                    }
+                }
+            };
+            let _ = results.send(result);
+        }
+        failed
+    }
+
+    // Clients given by ids or matching the selector
+    async fn targets(
+        ids: Vec<String>,
+        selector: Option<&Selector>,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> error::Result<Vec<String>> {
+        if let Some(selector) = selector {
+            let clients = Client::list(ListOptions::new(), &mut *tx).await?;
+            let labels = Label::list(&mut *tx).await?;
+            let empty = HashMap::new();
+            return Ok(clients
+                .into_iter()
+                .filter(|c| selector.matches(labels.get(&c.id).unwrap_or(&empty)))
+                .map(|c| c.id)
+                .collect());
+        }
+        for id in &ids {
+            Client::get(id, &mut *tx).await?;
+        }
+        Ok(ids)
+    }
+
+    // Completely uploaded blob, touched so GC keeps it while it is being put
+    async fn blob_for_use(
+        id: &str,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> error::Result<model::Blob> {
+        let blob = model::Blob::get(id, &mut *tx).await?;
+        if blob.completed_at.is_none() {
+            return Err(error::Error::bad_request("upload of blob is not complete"));
+        }
+        model::Blob::touch(id, &mut *tx).await?;
+        Ok(blob)
+    }
+
+    async fn create_job_actions(
+        job_id: &str,
+        client_ids: &[String],
+        action_command: ActionCommand,
+        command: &submit_job_request::Command,
+        delivery: model::Delivery,
+        timeout: Duration,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> error::Result<()> {
+        for client_id in client_ids {
+            let act = model::Action::new(client_id.clone(), action_command)
+                .with_job(job_id.to_owned())
+                .with_delivery(delivery)
+                .with_timeout(timeout);
+            let id = act.id.clone();
+            act.create(&mut *tx).await?;
+            match command {
+                submit_job_request::Command::Shell(shell) => {
+                    model::ShellCommand::new(
+                        id,
+                        shell.cmd.clone(),
+                        shell.args.clone(),
+                        shell.stdin.clone(),
+                        false,
+                    )
+                    .create(&mut *tx)
+                    .await?
+                }
+                submit_job_request::Command::Ping(_) => {
+                    model::PingCommand::new(id, String::from("ping"))
+                        .create(&mut *tx)
+                        .await?
+                }
+                submit_job_request::Command::PutFile(put) => {
+                    model::PutFileCommand::new(
+                        id,
+                        put.blob_id.clone(),
+                        put.path.clone(),
+                        put.mode as i32,
+                        put.owner.clone(),
+                        put.atomic,
+                    )
+                    .create(&mut *tx)
+                    .await?
+                }
+            }
+        }
+        Ok(())
+    }
 
-    pub fn new(db: PgPool) -> Self {
-        Self { db }
+    // Progress of a single client of a job
+    async fn job_target(
+        &self,
+        act: model::Action,
+        output: bool,
+    ) -> error::Result<job_status_response::Target> {
+        let mut target = job_status_response::Target {
+            client_id: act.client_id.clone(),
+            action_id: act.id.clone(),
+            ..Default::default()
+        };
+        let state = match act.state {
+            ActionState::Pending => target::State::Pending,
+            ActionState::Running => target::State::Running,
+            ActionState::Finished => match act.command {
+                ActionCommand::Shell => {
+                    let result = model::ShellResult::get(&act.id, &self.db).await?;
+                    target.code = result.code;
+                    if output {
+                        target.stdout = result.stdout;
+                        target.stderr = result.stderr;
+                    }
+                    match result.signal {
+                        Some(signal) => {
+                            target.error = format!("killed by signal {}", signal);
+                            target::State::Failed
+                        }
+                        None if result.code != 0 => {
+                            target.error = format!("exited with code {}", result.code);
+                            target::State::Failed
+                        }
+                        None => target::State::Succeeded,
+                    }
+                }
+                // Put file reports its failures as error of finished action
+                _ => match &act.error {
+                    Some(error) => {
+                        target.error = String::from_utf8_lossy(error).into_owned();
+                        target::State::Failed
+                    }
+                    None => target::State::Succeeded,
+                },
+            },
+            ActionState::Failed
+            | ActionState::TimedOut
+            | ActionState::Cancelled
+            | ActionState::Lost => {
+                target.error = failure(&act)
+                    .map(|s| s.message().to_owned())
+                    .unwrap_or_default();
+                target::State::Failed
+            }
+        };
+        target.state = state.into();
+        Ok(target)
     }
 }
 
@@ -27,10 +427,17 @@ impl CliServer {
 impl NotSshCli for CliServer {
     async fn list(
         &self,
-        _request: tonic::Request<ListRequest>,
+        request: tonic::Request<ListRequest>,
     ) -> std::result::Result<tonic::Response<ListResponse>, tonic::Status> {
         log::info!("Control server: List");
 
+        let request = request.into_inner();
+        let selector = if request.selector.is_empty() {
+            None
+        } else {
+            Some(request.selector.parse::<Selector>()?)
+        };
+
         let mut tx = match self.db.begin().await {
             Ok(tx) => tx,
             Err(e) => {
@@ -47,6 +454,22 @@ impl NotSshCli for CliServer {
             }
         };
 
+        let mut facts: HashMap<_, _> = match Facts::list(&mut tx).await {
+            Ok(f) => f.into_iter().map(|f| (f.client_id.clone(), f)).collect(),
+            Err(e) => {
+                log::error!("cannot get facts from database: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        let mut labels = match Label::list(&mut tx).await {
+            Ok(l) => l,
+            Err(e) => {
+                log::error!("cannot get labels from database: {}", e);
+                return Err(e.into());
+            }
+        };
+
         if let Err(e) = tx.commit().await {
             log::error!("cannot commit transaction: {}", e);
             return Err(tonic::Status::internal("internal error"));
@@ -54,9 +477,35 @@ impl NotSshCli for CliServer {
 
         let clients = clients
             .into_iter()
-            .map(|c| list_response::Client {
+            .map(|c| (labels.remove(&c.id).unwrap_or_default(), c))
+            .filter(|(labels, _)| selector.as_ref().is_none_or(|s| s.matches(labels)))
+            .map(|(labels, c)| list_response::Client {
+                facts: facts.remove(&c.id).map(|f| list_response::Facts {
+                    hostname: f.hostname,
+                    machine_id: f.machine_id,
+                    os_release: f.os_release,
+                    kernel: f.kernel,
+                    arch: f.arch,
+                    cpus: f.cpus as u32,
+                    memory: f.memory as u64,
+                    addresses: f.addresses,
+                    agent_version: f.agent_version,
+                    updated_at: f.updated_at.timestamp(),
+                }),
+                connected: c.is_live(),
+                rtt: if c.is_live() {
+                    c.rtt_ms.unwrap_or_default() as u32
+                } else {
+                    0
+                },
+                instance: if c.is_live() {
+                    c.instance_id.unwrap_or_default()
+                } else {
+                    String::new()
+                },
                 id: c.id,
-                connected: c.connected,
+                approved: c.approved,
+                labels,
             })
             .collect();
         Ok(tonic::Response::new(ListResponse { clients }))
@@ -84,8 +533,15 @@ impl NotSshCli for CliServer {
             }
         };
 
-        let act = model::Action::new(client.id, ActionCommand::Ping);
-        let cmd = model::PingCommand::new(act.id.clone(), String::from("ping"));
+        let timeout = action_timeout(request.timeout, Self::PING_TIMEOUT);
+        let act = model::Action::new(client.id, ActionCommand::Ping).with_timeout(timeout);
+        // Random payload, so only the echo of this very ping is accepted
+        let nonce = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        let cmd = model::PingCommand::new(act.id.clone(), nonce);
         let id = act.id.clone();
         let msg = cmd.data.clone();
 
@@ -105,14 +561,14 @@ impl NotSshCli for CliServer {
         }
 
         let act =
-            match tokio::time::timeout(Self::PING_TIMEOUT, wait_for_result(&id, self.db.clone()))
+            match tokio::time::timeout(timeout, wait_for_result(&id, self.db.clone(), &self.hub))
                 .await
             {
                 Ok(r) => match r {
                     Ok(r) => r,
                     Err(e) => {
                         log::error!("cannot get action from database: {}", e);
-                        return Err(tonic::Status::internal("internal error"));
+                        return Err(e.into());
                     }
                 },
                 Err(e) => {
@@ -120,13 +576,33 @@ impl NotSshCli for CliServer {
                     return Err(tonic::Status::deadline_exceeded("action timeout"));
                 }
             };
+        if let Some(e) = failure(&act) {
+            return Err(e);
+        }
 
         if let Some(result) = act.result {
             let result = String::from_utf8(result)
                 .map_err(|_| error::Error::bad_request("cannot parse ping response"))?;
-            if result == msg {
-                return Ok(tonic::Response::new(PingResponse {}));
+            if result != msg {
+                log::warn!("ping '{}' echoed '{}' instead of '{}'", id, result, msg);
+                return Err(tonic::Status::data_loss("ping echo does not match"));
             }
+            // Client too old to echo timestamps leaves no result, times are unknown then
+            let res = match model::PingResult::get(&act.id, &self.db).await {
+                Ok(r) => PingResponse {
+                    rtt: r.rtt as u64,
+                    clock_offset: r.clock_offset,
+                },
+                Err(e) if matches!(e.kind(), error::ErrorKind::NotFound) => PingResponse {
+                    rtt: 0,
+                    clock_offset: 0,
+                },
+                Err(e) => {
+                    log::error!("cannot get ping result from database: {}", e);
+                    return Err(e.into());
+                }
+            };
+            return Ok(tonic::Response::new(res));
         }
 
         Err(tonic::Status::unavailable(
@@ -156,7 +632,8 @@ impl NotSshCli for CliServer {
             }
         };
 
-        let act = model::Action::new(client.id, ActionCommand::Purge);
+        let timeout = action_timeout(request.timeout, Self::PURGE_TIMEOUT);
+        let act = model::Action::new(client.id, ActionCommand::Purge).with_timeout(timeout);
         let id = act.id.clone();
 
         if let Err(e) = act.create(&mut tx).await {
@@ -170,14 +647,14 @@ impl NotSshCli for CliServer {
         }
 
         let act =
-            match tokio::time::timeout(Self::PURGE_TIMEOUT, wait_for_result(&id, self.db.clone()))
+            match tokio::time::timeout(timeout, wait_for_result(&id, self.db.clone(), &self.hub))
                 .await
             {
                 Ok(r) => match r {
                     Ok(r) => r,
                     Err(e) => {
                         log::error!("cannot get action from database: {}", e);
-                        return Err(tonic::Status::internal("internal error"));
+                        return Err(e.into());
                     }
                 },
                 Err(e) => {
@@ -185,6 +662,9 @@ impl NotSshCli for CliServer {
                     return Err(tonic::Status::deadline_exceeded("action timeout"));
                 }
             };
+        if let Some(e) = failure(&act) {
+            return Err(e);
+        }
 
         if let Some(result) = act.result {
             let result = String::from_utf8(result)
@@ -206,6 +686,29 @@ impl NotSshCli for CliServer {
         log::info!("Control server: Shell");
 
         let request = request.into_inner();
+        let action_id = action_id(&request.action_id);
+        Ok(tonic::Response::new(
+            self.run_shell(action_id, request).await?,
+        ))
+    }
+
+    async fn upload(
+        &self,
+        request: tonic::Request<tonic::Streaming<UploadRequest>>,
+    ) -> std::result::Result<tonic::Response<UploadResponse>, tonic::Status> {
+        log::info!("Control server: Upload");
+
+        let mut input = request.into_inner();
+
+        // Blob row must exist before its chunks, it is completed once everything is received.
+        // Every transaction touches it, so GC removes it only if the upload breaks off
+        let blob = model::Blob::new();
+        let blob_id = blob.id.clone();
+        if let Err(e) = blob.create(&self.db).await {
+            log::error!("cannot create blob in database: {}", e);
+            return Err(e.into());
+        }
+
         let mut tx = match self.db.begin().await {
             Ok(tx) => tx,
             Err(e) => {
@@ -213,26 +716,85 @@ impl NotSshCli for CliServer {
                 return Err(tonic::Status::internal("internal error"));
             }
         };
-        let client = match Client::get(&request.id, &mut tx).await {
-            Ok(c) => c,
-            Err(e) => {
-                log::error!("cannot get client from database: {}", e);
-                return Err(e.into());
+        if let Err(e) = model::Blob::touch(&blob_id, &mut tx).await {
+            log::error!("cannot touch blob in database: {}", e);
+            return Err(e.into());
+        }
+
+        let (mut size, mut chunks) = (0, 0);
+        let mut expected = None;
+        let mut hasher = Sha256::new();
+        let mut buf = Vec::new();
+        loop {
+            let req = input.message().await?;
+            let done = req.is_none();
+            if let Some(req) = req {
+                if expected.is_none() {
+                    expected = Some((req.size, req.sha256));
+                }
+                hasher.update(&req.data);
+                size += req.data.len() as u64;
+                buf.extend(req.data);
             }
-        };
 
-        let act = model::Action::new(client.id, ActionCommand::Shell);
-        let cmd =
-            model::ShellCommand::new(act.id.clone(), request.cmd, request.args, request.stdin);
-        let id = act.id.clone();
+            while buf.len() >= BLOB_CHUNK_SIZE || (done && !buf.is_empty()) {
+                let rest = buf.split_off(buf.len().min(BLOB_CHUNK_SIZE));
+                let data = std::mem::replace(&mut buf, rest);
+                let chunk = model::BlobChunk::new(blob_id.clone(), chunks, data);
+                if let Err(e) = chunk.create(&mut tx).await {
+                    log::error!("cannot create blob chunk in database: {}", e);
+                    return Err(e.into());
+                }
+                chunks += 1;
 
-        if let Err(e) = act.create(&mut tx).await {
-            log::error!("cannot create action in database: {}", e);
-            return Err(e.into());
+                if chunks % CHUNKS_PER_TX == 0 {
+                    if let Err(e) = tx.commit().await {
+                        log::error!("cannot commit transaction: {}", e);
+                        return Err(tonic::Status::internal("internal error"));
+                    }
+                    tx = match self.db.begin().await {
+                        Ok(tx) => tx,
+                        Err(e) => {
+                            log::error!("cannot begin transaction: {}", e);
+                            return Err(tonic::Status::internal("internal error"));
+                        }
+                    };
+                    // Upload has been taking too long, GC may have collected the blob already
+                    if let Err(e) = model::Blob::touch(&blob_id, &mut tx).await {
+                        log::error!("cannot touch blob in database: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+
+            if done {
+                break;
+            }
         }
 
-        if let Err(e) = cmd.create(&mut tx).await {
-            log::error!("cannot create purge command in database: {}", e);
+        let sha256 = hasher.finalize().to_vec();
+        if let Some((expected_size, expected_sha256)) = expected {
+            if expected_size != size || (!expected_sha256.is_empty() && expected_sha256 != sha256) {
+                return Err(tonic::Status::data_loss(
+                    "uploaded content does not match its size or checksum",
+                ));
+            }
+        }
+
+        let mut blob = match model::Blob::get(&blob_id, &mut tx).await {
+            Ok(b) => b,
+            Err(e) => {
+                log::error!("cannot get blob from database: {}", e);
+                return Err(e.into());
+            }
+        };
+        blob.size = size as i64;
+        blob.sha256 = sha256;
+        blob.chunks = chunks;
+        blob.completed_at = Some(Utc::now());
+
+        if let Err(e) = blob.update(&mut tx).await {
+            log::error!("cannot update blob in database: {}", e);
             return Err(e.into());
         }
 
@@ -241,15 +803,28 @@ impl NotSshCli for CliServer {
             return Err(tonic::Status::internal("internal error"));
         }
 
+        Ok(tonic::Response::new(UploadResponse { blob_id }))
+    }
+
+    async fn put_file(
+        &self,
+        request: tonic::Request<PutFileRequest>,
+    ) -> std::result::Result<tonic::Response<PutFileResponse>, tonic::Status> {
+        log::info!("Control server: PutFile");
+
+        let request = request.into_inner();
+        let timeout = action_timeout(request.timeout, Self::PUT_FILE_TIMEOUT);
+        let (id, blob) = self.create_put_file(request, timeout, None).await?;
+
         let act =
-            match tokio::time::timeout(Self::SHELL_TIMEOUT, wait_for_result(&id, self.db.clone()))
+            match tokio::time::timeout(timeout, wait_for_result(&id, self.db.clone(), &self.hub))
                 .await
             {
                 Ok(r) => match r {
                     Ok(r) => r,
                     Err(e) => {
                         log::error!("cannot get action from database: {}", e);
-                        return Err(tonic::Status::internal("internal error"));
+                        return Err(e.into());
                     }
                 },
                 Err(e) => {
@@ -257,49 +832,1089 @@ impl NotSshCli for CliServer {
                     return Err(tonic::Status::deadline_exceeded("action timeout"));
                 }
             };
-
-        if let Some(result) = act.result {
-            return Ok(tonic::Response::new(ShellResponse {
-                stdout: result,
-                stderr: Vec::new(),
-            }));
+        if let Some(e) = failure(&act) {
+            return Err(e);
         }
 
         if let Some(error) = act.error {
-            return Ok(tonic::Response::new(ShellResponse {
-                stdout: Vec::new(),
-                stderr: error,
-            }));
+            return Err(tonic::Status::aborted(String::from_utf8_lossy(&error)));
         }
 
-        Err(tonic::Status::unavailable(
-            "cannot receive shell result from client",
-        ))
+        match act.result {
+            Some(sha256) if sha256 == blob.sha256 => Ok(tonic::Response::new(PutFileResponse {
+                size: blob.size as u64,
+                sha256,
+            })),
+            _ => Err(tonic::Status::data_loss(
+                "written file does not match its checksum",
+            )),
+        }
     }
-}
 
-async fn wait_for_result(id: &str, pool: PgPool) -> error::Result<model::Action> {
-    let mut i = tokio::time::interval(Duration::from_secs(2));
-    let act = loop {
-        i.tick().await;
+    type AttachStream = Pin<
+        Box<
+            dyn futures_core::Stream<Item = std::result::Result<AttachResponse, tonic::Status>>
+                + Send
+                + 'static,
+        >,
+    >;
+
+    async fn attach(
+        &self,
+        request: tonic::Request<tonic::Streaming<AttachRequest>>,
+    ) -> std::result::Result<tonic::Response<Self::AttachStream>, tonic::Status> {
+        log::info!("Control server: Attach");
+
+        let mut input = request.into_inner();
+        let open = match input.message().await? {
+            Some(AttachRequest {
+                event: Some(attach_request::Event::Open(open)),
+            }) => open,
+            _ => {
+                return Err(tonic::Status::invalid_argument(
+                    "session must be opened first",
+                ))
+            }
+        };
 
-        let mut tx = match pool.begin().await {
+        let mut tx = match self.db.begin().await {
             Ok(tx) => tx,
             Err(e) => {
                 log::error!("cannot begin transaction: {}", e);
-                continue;
+                return Err(tonic::Status::internal("internal error"));
             }
         };
-        let act = match model::Action::get(&id, &mut tx).await {
-            Ok(act) => act,
+        let client = match Client::get(&open.id, &mut tx).await {
+            Ok(c) => c,
             Err(e) => {
-                log::error!("cannot get action from database: {}", e);
-                continue;
+                log::error!("cannot get client from database: {}", e);
+                return Err(e.into());
             }
         };
-        if let ActionState::Finished = act.state {
-            break act;
+        // Session data goes straight through the Poll stream, so it must be open
+        if !client.is_live() {
+            return Err(tonic::Status::failed_precondition(
+                "client is not connected",
+            ));
         }
-    };
-    Ok(act)
+        let client_id = client.id.clone();
+
+        // Session is opened through the hub right away and never waits for dispatch
+        let mut act = model::Action::new(client.id, ActionCommand::Session);
+        act.state = ActionState::Running;
+        act.started_at = Some(Utc::now());
+        let cmd = model::SessionCommand::new(
+            act.id.clone(),
+            open.rows as i32,
+            open.cols as i32,
+            open.term.clone(),
+        );
+        let session_id = act.id.clone();
+        let mut relay = match self
+            .hub
+            .subscribe_output(&self.db, &client_id, &session_id)
+            .await
+        {
+            Ok(relay) => relay,
+            Err(e) => {
+                log::error!("cannot subscribe to output: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        if let Err(e) = act.create(&mut tx).await {
+            log::error!("cannot create action in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = cmd.create(&mut tx).await {
+            log::error!("cannot create session command in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        let mut act = Action::new(
+            session_id.clone(),
+            Command::session(open.rows, open.cols, open.term),
+        );
+        act.window = OUTPUT_WINDOW;
+        if let Err(e) = self.hub.forward_action(&self.db, &client_id, act).await {
+            log::error!("cannot open session '{}': {}", session_id, e);
+            return Err(e.into());
+        }
+        log::info!("Control server: session '{}' opened", session_id);
+
+        let db = self.db.clone();
+        let hub = self.hub.clone();
+        let id = session_id.clone();
+        let cid = client_id.clone();
+        tokio::spawn(async move {
+            loop {
+                let cmd = match input.message().await {
+                    Ok(Some(req)) => match req.event {
+                        Some(attach_request::Event::Input(data)) => Command::session_input(data),
+                        Some(attach_request::Event::Resize(r)) => {
+                            Command::session_resize(r.rows, r.cols)
+                        }
+                        _ => continue,
+                    },
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::info!("cannot receive input of session '{}': {}", id, e);
+                        break;
+                    }
+                };
+                let act = Action::new(id.clone(), cmd);
+                if let Err(e) = hub.forward_action(&db, &cid, act).await {
+                    log::error!("cannot send input of session '{}': {}", id, e);
+                    break;
+                }
+            }
+            // Nobody is attached anymore, so the session is of no use
+            let act = Action::new(id.clone(), Command::session_close());
+            if let Err(e) = hub.forward_action(&db, &cid, act).await {
+                log::error!("cannot close session '{}': {}", id, e);
+            }
+        });
+
+        let db = self.db.clone();
+        let output = async_stream::try_stream! {
+            let mut check = tokio::time::interval(SESSION_CHECK_INTERVAL);
+            loop {
+                let r = tokio::select! {
+                    r = relay.recv() => Some(r),
+                    _ = check.tick() => None,
+                };
+                let r = match r {
+                    Some(r) => r.map_err(output_dropped)?,
+                    None => {
+                        let live = Client::get(&client_id, &db).await.is_ok_and(|c| c.is_live());
+                        // a hack to return error from try_stream macro, which only supports '?'
+                        live.then_some(()).ok_or(tonic::Status::unavailable("client disconnected"))?;
+                        continue;
+                    }
+                };
+                match r {
+                    res::Result::SessionOutput(out) => yield AttachResponse {
+                        event: Some(attach_response::Event::Output(out.data)),
+                    },
+                    res::Result::SessionExit(exit) => {
+                        yield AttachResponse {
+                            event: Some(attach_response::Event::Exit(attach_response::Exit {
+                                code: exit.code,
+                                signal: exit.signal,
+                            })),
+                        };
+                        break;
+                    },
+                    res::Result::Error(err) => {
+                        Err(tonic::Status::aborted(err.message))?;
+                    },
+                    _ => continue,
+                }
+            }
+            log::info!("Control server: session '{}' closed", session_id);
+        };
+
+        Ok(tonic::Response::new(Box::pin(output) as Self::AttachStream))
+    }
+
+    type ShellStreamStream = Pin<
+        Box<
+            dyn futures_core::Stream<Item = std::result::Result<ShellStreamResponse, tonic::Status>>
+                + Send
+                + 'static,
+        >,
+    >;
+
+    async fn shell_stream(
+        &self,
+        request: tonic::Request<ShellRequest>,
+    ) -> std::result::Result<tonic::Response<Self::ShellStreamStream>, tonic::Status> {
+        log::info!("Control server: ShellStream");
+
+        let request = request.into_inner();
+        let timeout = action_timeout(request.timeout, Self::SHELL_TIMEOUT);
+        // Subscribe before the action is created, so no output gets lost
+        let action_id = action_id(&request.action_id);
+        let mut relay = match self
+            .hub
+            .subscribe_output(&self.db, &request.id, &action_id)
+            .await
+        {
+            Ok(relay) => relay,
+            Err(e) => {
+                log::error!("cannot subscribe to output: {}", e);
+                return Err(e.into());
+            }
+        };
+        self.create_shell(action_id.clone(), request, true, timeout, None)
+            .await?;
+
+        let db = self.db.clone();
+        let hub = self.hub.clone();
+        let output = async_stream::try_stream! {
+            let finished = tokio::time::timeout(
+                timeout,
+                wait_for_result(&action_id, db.clone(), &hub),
+            );
+            tokio::pin!(finished);
+
+            let r = loop {
+                let out = tokio::select! {
+                    out = relay.recv() => out,
+                    r = &mut finished => break r,
+                };
+                if let res::Result::Output(out) = out.map_err(output_dropped)? {
+                    yield output_response(out);
+                }
+            };
+
+            let act = match r {
+                Ok(r) => r.map_err(|e| {
+                    log::error!("cannot get action from database: {}", e);
+                    tonic::Status::from(e)
+                })?,
+                Err(e) => {
+                    log::error!("gave up waiting for result after {}", e);
+                    Err(tonic::Status::deadline_exceeded("action timeout"))?
+                }
+            };
+            if let Some(e) = failure(&act) {
+                Err(e)?;
+            }
+            // Output is relayed before the action is finished, so everything is already here
+            while let Some(out) = relay.try_recv().map_err(output_dropped)? {
+                if let res::Result::Output(out) = out {
+                    yield output_response(out);
+                }
+            }
+            // Output is stored for `result`, but the caller has got it already
+            let result = ShellResponse {
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                ..shell_response(&act.id, &db).await?
+            };
+            yield ShellStreamResponse {
+                event: Some(shell_stream_response::Event::Result(result)),
+            };
+        };
+
+        Ok(tonic::Response::new(
+            Box::pin(output) as Self::ShellStreamStream
+        ))
+    }
+
+    type RolloutStream = Pin<
+        Box<
+            dyn futures_core::Stream<Item = std::result::Result<RolloutResponse, tonic::Status>>
+                + Send
+                + 'static,
+        >,
+    >;
+
+    async fn rollout(
+        &self,
+        request: tonic::Request<RolloutRequest>,
+    ) -> std::result::Result<tonic::Response<Self::RolloutStream>, tonic::Status> {
+        log::info!("Control server: Rollout");
+
+        let request = request.into_inner();
+        let shell = match request.shell {
+            Some(shell) => shell,
+            None => return Err(tonic::Status::invalid_argument("shell command required")),
+        };
+        if request.batch_size == 0 && !(1..=100).contains(&request.batch_percent) {
+            return Err(tonic::Status::invalid_argument(
+                "batch size or percentage between 1 and 100 required",
+            ));
+        }
+        let selector = match (request.ids.is_empty(), request.selector.is_empty()) {
+            (true, false) => Some(request.selector.parse::<Selector>()?),
+            (false, true) => None,
+            _ => {
+                return Err(tonic::Status::invalid_argument(
+                    "either ids or selector required",
+                ))
+            }
+        };
+
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+
+        let ids = match Self::targets(request.ids, selector.as_ref(), &mut tx).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                log::error!("cannot resolve rollout targets: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        let batch_size = batch_size(ids.len(), request.batch_size, request.batch_percent);
+        let pause = Duration::from_secs(request.pause);
+        let max_failures = request.max_failures;
+        let server = self.clone();
+        // Batches are driven by the stream, so nothing new is started once the caller is gone
+        let output = async_stream::try_stream! {
+            let mut failed = 0;
+            for (index, batch) in ids.chunks(batch_size).enumerate() {
+                if is_over_threshold(failed, max_failures) {
+                    let skipped = ids[index * batch_size..].to_vec();
+                    log::warn!("rollout aborted after {} failures, {} clients skipped", failed, skipped.len());
+                    yield RolloutResponse {
+                        event: Some(rollout_response::Event::Aborted(rollout_response::Aborted {
+                            failed,
+                            skipped,
+                        })),
+                    };
+                    break;
+                }
+                if index > 0 {
+                    tokio::time::sleep(pause).await;
+                }
+                // Caller learns action ids before they are started, so it can cancel them
+                let action_ids: Vec<_> = batch.iter().map(|_| action_id("")).collect();
+                yield RolloutResponse {
+                    event: Some(rollout_response::Event::Batch(rollout_response::Batch {
+                        index: index as u32,
+                        ids: batch.to_vec(),
+                        action_ids: action_ids.clone(),
+                    })),
+                };
+
+                let (results_tx, mut results_rx) = mpsc::unbounded_channel();
+                let run = server.run_batch(batch, &action_ids, &shell, &results_tx);
+                tokio::pin!(run);
+                let batch_failed = loop {
+                    tokio::select! {
+                        Some(result) = results_rx.recv() => {
+                            yield RolloutResponse {
+                                event: Some(rollout_response::Event::Result(result)),
+                            };
+                        }
+                        n = &mut run => break n,
+                    }
+                };
+                // Results sent right before the batch finished
+                while let Ok(result) = results_rx.try_recv() {
+                    yield RolloutResponse {
+                        event: Some(rollout_response::Event::Result(result)),
+                    };
+                }
+                failed += batch_failed;
+            }
+        };
+
+        Ok(tonic::Response::new(Box::pin(output) as Self::RolloutStream))
+    }
+
+    type PullStream = Pin<
+        Box<
+            dyn futures_core::Stream<Item = std::result::Result<PullResponse, tonic::Status>>
+                + Send
+                + 'static,
+        >,
+    >;
+
+    async fn pull(
+        &self,
+        request: tonic::Request<PullRequest>,
+    ) -> std::result::Result<tonic::Response<Self::PullStream>, tonic::Status> {
+        log::info!("Control server: Pull");
+
+        let request = request.into_inner();
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+        let client = match Client::get(&request.id, &mut tx).await {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("cannot get client from database: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        let timeout = action_timeout(request.timeout, Self::GET_FILE_TIMEOUT);
+        let act = model::Action::with_id(
+            action_id(&request.action_id),
+            client.id,
+            ActionCommand::GetFile,
+        )
+        .with_timeout(timeout);
+        let cmd = model::GetFileCommand::new(act.id.clone(), request.path);
+        let action_id = act.id.clone();
+        // Subscribe before the action is created, so no content gets lost
+        let mut relay = match self
+            .hub
+            .subscribe_output(&self.db, &act.client_id, &action_id)
+            .await
+        {
+            Ok(relay) => relay,
+            Err(e) => {
+                log::error!("cannot subscribe to output: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        if let Err(e) = act.create(&mut tx).await {
+            log::error!("cannot create action in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = cmd.create(&mut tx).await {
+            log::error!("cannot create get file command in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        let db = self.db.clone();
+        let hub = self.hub.clone();
+        let output = async_stream::try_stream! {
+            let finished = tokio::time::timeout(
+                timeout,
+                wait_for_result(&action_id, db.clone(), &hub),
+            );
+            tokio::pin!(finished);
+
+            let r = loop {
+                let chunk = tokio::select! {
+                    chunk = relay.recv() => chunk,
+                    r = &mut finished => break r,
+                };
+                if let res::Result::FileChunk(chunk) = chunk.map_err(output_dropped)? {
+                    yield data_response(chunk);
+                }
+            };
+
+            let act = match r {
+                Ok(r) => r.map_err(|e| {
+                    log::error!("cannot get action from database: {}", e);
+                    tonic::Status::from(e)
+                })?,
+                Err(e) => {
+                    log::error!("gave up waiting for result after {}", e);
+                    Err(tonic::Status::deadline_exceeded("action timeout"))?
+                }
+            };
+            if let Some(e) = failure(&act) {
+                Err(e)?;
+            }
+            // Content is relayed before the action is finished, so everything is already here
+            while let Some(chunk) = relay.try_recv().map_err(output_dropped)? {
+                if let res::Result::FileChunk(chunk) = chunk {
+                    yield data_response(chunk);
+                }
+            }
+            if let Some(error) = act.error {
+                Err(tonic::Status::aborted(String::from_utf8_lossy(&error)))?;
+            }
+
+            let result = model::GetFileResult::get(&act.id, &db).await.map_err(|e| {
+                log::error!("cannot get file result from database: {}", e);
+                tonic::Status::unavailable("cannot receive file from client")
+            })?;
+            yield PullResponse {
+                event: Some(pull_response::Event::File(pull_response::File {
+                    size: result.size as u64,
+                    sha256: result.sha256,
+                    mode: result.mode as u32,
+                    modified_at: result.modified_at.map_or(0, |t| t.timestamp_millis()),
+                })),
+            };
+        };
+
+        Ok(tonic::Response::new(Box::pin(output) as Self::PullStream))
+    }
+
+    async fn cancel(
+        &self,
+        request: tonic::Request<CancelRequest>,
+    ) -> std::result::Result<tonic::Response<CancelResponse>, tonic::Status> {
+        log::info!("Control server: Cancel");
+
+        let request = request.into_inner();
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+        // Locked, so the action is not dispatched while being cancelled
+        let mut act = match model::Action::get_for_update(&request.action_id, &mut tx).await {
+            Ok(act) => act,
+            Err(e) => {
+                log::error!("cannot get action from database: {}", e);
+                return Err(e.into());
+            }
+        };
+        if act.state.is_done() {
+            return Err(tonic::Status::failed_precondition("action is already done"));
+        }
+
+        let running = matches!(act.state, ActionState::Running);
+        let id = act.id.clone();
+        let client_id = act.client_id.clone();
+        act.state = ActionState::Cancelled;
+        if let Err(e) = act.update(&mut tx).await {
+            log::error!("cannot update action in database: {}", e);
+            return Err(e.into());
+        }
+        if let Err(e) = model::Action::notify_finished(&id, &mut tx).await {
+            log::error!("cannot notify about cancelled action: {}", e);
+            return Err(e.into());
+        }
+        if !running {
+            if let Err(e) = model::Action::notify_queued(&client_id, &mut tx).await {
+                log::error!("cannot notify about changed queue: {}", e);
+                return Err(e.into());
+            }
+        }
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        // Pending action is never sent, running one is stopped by the client. Whatever the
+        // client reports afterwards is ignored, since the action is already done
+        if running {
+            let act = Action::new(id, Command::cancel());
+            if let Err(e) = self.hub.forward_action(&self.db, &client_id, act).await {
+                log::error!("cannot send cancel to client '{}': {}", client_id, e);
+            }
+        } else {
+            // Dispatching could have skipped the locked action, leaving the rest of queue behind
+            self.hub.wake_actions(&client_id);
+        }
+
+        Ok(tonic::Response::new(CancelResponse {}))
+    }
+
+    async fn create_token(
+        &self,
+        request: tonic::Request<CreateTokenRequest>,
+    ) -> std::result::Result<tonic::Response<CreateTokenResponse>, tonic::Status> {
+        log::info!("Control server: CreateToken");
+
+        let request = request.into_inner();
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(Self::TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        let ttl = (request.ttl != 0).then(|| Duration::from_secs(request.ttl));
+        let record = model::EnrollmentToken::new(
+            Sha256::digest(token.as_bytes()).to_vec(),
+            request.reusable,
+            ttl,
+        );
+        let id = record.id.clone();
+
+        if let Err(e) = record.create(&self.db).await {
+            log::error!("cannot create enrollment token in database: {}", e);
+            return Err(e.into());
+        }
+
+        Ok(tonic::Response::new(CreateTokenResponse { id, token }))
+    }
+
+    async fn revoke_token(
+        &self,
+        request: tonic::Request<RevokeTokenRequest>,
+    ) -> std::result::Result<tonic::Response<RevokeTokenResponse>, tonic::Status> {
+        log::info!("Control server: RevokeToken");
+
+        let request = request.into_inner();
+        if let Err(e) = model::EnrollmentToken::delete(&request.id, &self.db).await {
+            log::error!("cannot delete enrollment token from database: {}", e);
+            return Err(e.into());
+        }
+
+        Ok(tonic::Response::new(RevokeTokenResponse {}))
+    }
+
+    async fn approve(
+        &self,
+        request: tonic::Request<ApproveRequest>,
+    ) -> std::result::Result<tonic::Response<ApproveResponse>, tonic::Status> {
+        log::info!("Control server: Approve");
+
+        let request = request.into_inner();
+        if let Err(e) = Client::approve(&request.id, &self.db).await {
+            log::error!("cannot approve client: {}", e);
+            return Err(e.into());
+        }
+
+        Ok(tonic::Response::new(ApproveResponse {}))
+    }
+
+    async fn label(
+        &self,
+        request: tonic::Request<LabelRequest>,
+    ) -> std::result::Result<tonic::Response<LabelResponse>, tonic::Status> {
+        log::info!("Control server: Label");
+
+        let request = request.into_inner();
+        for (key, value) in &request.set {
+            selector::validate_key(key)?;
+            selector::validate_value(value)?;
+        }
+
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+
+        if let Err(e) = Client::get(&request.id, &mut tx).await {
+            log::error!("cannot get client '{}' from database: {}", request.id, e);
+            return Err(e.into());
+        }
+
+        for key in &request.remove {
+            if let Err(e) = Label::delete(&request.id, key, true, &mut tx).await {
+                log::error!("cannot delete label from database: {}", e);
+                return Err(e.into());
+            }
+        }
+
+        for (key, value) in request.set {
+            let label = Label::new(request.id.clone(), key, value, true);
+            if let Err(e) = label.upsert(&mut tx).await {
+                log::error!("cannot set label in database: {}", e);
+                return Err(e.into());
+            }
+        }
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        Ok(tonic::Response::new(LabelResponse {}))
+    }
+
+    async fn submit_job(
+        &self,
+        request: tonic::Request<SubmitJobRequest>,
+    ) -> std::result::Result<tonic::Response<SubmitJobResponse>, tonic::Status> {
+        log::info!("Control server: SubmitJob");
+
+        let request = request.into_inner();
+        let delivery = delivery(request.delivery());
+        let command = match request.command {
+            Some(c) => c,
+            None => return Err(tonic::Status::invalid_argument("command required")),
+        };
+        let (action_command, default_timeout) = match &command {
+            submit_job_request::Command::Shell(_) => (ActionCommand::Shell, Self::SHELL_TIMEOUT),
+            submit_job_request::Command::Ping(_) => (ActionCommand::Ping, Self::PING_TIMEOUT),
+            submit_job_request::Command::PutFile(_) => {
+                (ActionCommand::PutFile, Self::PUT_FILE_TIMEOUT)
+            }
+        };
+        let timeout = action_timeout(request.timeout, default_timeout);
+        let (selector, targets) = match (request.ids.is_empty(), request.selector.is_empty()) {
+            (true, false) => (
+                Some(request.selector.parse::<Selector>()?),
+                request.selector.clone(),
+            ),
+            (false, true) => (None, request.ids.join(",")),
+            _ => {
+                return Err(tonic::Status::invalid_argument(
+                    "either ids or selector required",
+                ))
+            }
+        };
+
+        let mut tx = match self.db.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                log::error!("cannot begin transaction: {}", e);
+                return Err(tonic::Status::internal("internal error"));
+            }
+        };
+
+        let client_ids = match Self::targets(request.ids, selector.as_ref(), &mut tx).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                log::error!("cannot resolve job targets: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        if let submit_job_request::Command::PutFile(put) = &command {
+            if let Err(e) = Self::blob_for_use(&put.blob_id, &mut tx).await {
+                log::error!("cannot get blob from database: {}", e);
+                return Err(e.into());
+            }
+        }
+
+        let job = Job::new(action_command, targets);
+        let id = job.id.clone();
+        if let Err(e) = job.create(&mut tx).await {
+            log::error!("cannot create job in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = Self::create_job_actions(
+            &id,
+            &client_ids,
+            action_command,
+            &command,
+            delivery,
+            timeout,
+            &mut tx,
+        )
+        .await
+        {
+            log::error!("cannot create job actions in database: {}", e);
+            return Err(e.into());
+        }
+
+        if let Err(e) = tx.commit().await {
+            log::error!("cannot commit transaction: {}", e);
+            return Err(tonic::Status::internal("internal error"));
+        }
+
+        log::info!(
+            "Control server: job '{}' submitted to {} clients",
+            id,
+            client_ids.len()
+        );
+        Ok(tonic::Response::new(SubmitJobResponse { id, client_ids }))
+    }
+
+    async fn job_status(
+        &self,
+        request: tonic::Request<JobStatusRequest>,
+    ) -> std::result::Result<tonic::Response<JobStatusResponse>, tonic::Status> {
+        log::info!("Control server: JobStatus");
+
+        let request = request.into_inner();
+        let job = match Job::get(&request.id, &self.db).await {
+            Ok(job) => job,
+            Err(e) => {
+                log::error!("cannot get job from database: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        let actions = match model::Action::list_by_job(&job.id, &self.db).await {
+            Ok(actions) => actions,
+            Err(e) => {
+                log::error!("cannot get job actions from database: {}", e);
+                return Err(e.into());
+            }
+        };
+
+        let mut targets = Vec::with_capacity(actions.len());
+        for act in actions {
+            match self.job_target(act, request.output).await {
+                Ok(target) => targets.push(target),
+                Err(e) => {
+                    log::error!("cannot get job target from database: {}", e);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(tonic::Response::new(JobStatusResponse {
+            id: job.id,
+            created_at: job.created_at.timestamp(),
+            targets,
+        }))
+    }
+
+    async fn submit_shell(
+        &self,
+        request: tonic::Request<ShellRequest>,
+    ) -> std::result::Result<tonic::Response<SubmitResponse>, tonic::Status> {
+        log::info!("Control server: SubmitShell");
+
+        let request = request.into_inner();
+        let timeout = action_timeout(request.timeout, Self::SHELL_TIMEOUT);
+        let action_id = self
+            .create_shell(
+                action_id(&request.action_id),
+                request,
+                false,
+                timeout,
+                Some(Self::SUBMIT_TTL),
+            )
+            .await?;
+        Ok(tonic::Response::new(SubmitResponse { action_id }))
+    }
+
+    async fn submit_put_file(
+        &self,
+        request: tonic::Request<PutFileRequest>,
+    ) -> std::result::Result<tonic::Response<SubmitResponse>, tonic::Status> {
+        log::info!("Control server: SubmitPutFile");
+
+        let request = request.into_inner();
+        let timeout = action_timeout(request.timeout, Self::PUT_FILE_TIMEOUT);
+        let (action_id, _) = self
+            .create_put_file(request, timeout, Some(Self::SUBMIT_TTL))
+            .await?;
+        Ok(tonic::Response::new(SubmitResponse { action_id }))
+    }
+
+    async fn get_result(
+        &self,
+        request: tonic::Request<GetResultRequest>,
+    ) -> std::result::Result<tonic::Response<GetResultResponse>, tonic::Status> {
+        log::info!("Control server: GetResult");
+
+        let request = request.into_inner();
+        let mut act = match model::Action::get(&request.action_id, &self.db).await {
+            Ok(act) => act,
+            Err(e) => {
+                log::error!("cannot get action from database: {}", e);
+                return Err(e.into());
+            }
+        };
+        // Action is settled one way or another by its deadline, the reaper gets a while to do
+        // that. Caller gets the action as it is then
+        if request.wait && !act.state.is_done() {
+            let wait = wait_for_result(&act.id, self.db.clone(), &self.hub);
+            let res = match act.settled_by() {
+                Some(t) => {
+                    let left = (t - Utc::now()).to_std().unwrap_or_default();
+                    tokio::time::timeout(left + RESULT_SWEEP_INTERVAL, wait).await
+                }
+                None => Ok(wait.await),
+            };
+            let res = match res {
+                Ok(res) => res,
+                Err(_) => model::Action::get(&act.id, &self.db).await,
+            };
+            act = match res {
+                Ok(act) => act,
+                Err(e) => {
+                    log::error!("cannot get action from database: {}", e);
+                    return Err(e.into());
+                }
+            };
+        }
+
+        let state = match act.state {
+            ActionState::Pending => get_result_response::State::Pending,
+            ActionState::Running => get_result_response::State::Running,
+            ActionState::Finished => get_result_response::State::Finished,
+            ActionState::Failed => get_result_response::State::Failed,
+            ActionState::TimedOut => get_result_response::State::TimedOut,
+            ActionState::Cancelled => get_result_response::State::Cancelled,
+            ActionState::Lost => get_result_response::State::Lost,
+        };
+        let error = match failure(&act) {
+            Some(e) => e.message().to_owned(),
+            None => act
+                .error
+                .as_deref()
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default()
+                .into_owned(),
+        };
+        let mut res = GetResultResponse {
+            action_id: act.id.clone(),
+            client_id: act.client_id.clone(),
+            state: state.into(),
+            error,
+            ..Default::default()
+        };
+        if let ActionState::Finished = act.state {
+            match act.command {
+                ActionCommand::Shell => {
+                    res.shell = Some(shell_response(&act.id, &self.db).await?);
+                }
+                ActionCommand::PutFile if act.error.is_none() => {
+                    res.sha256 = act.result.unwrap_or_default();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(tonic::Response::new(res))
+    }
+}
+
+async fn shell_response(
+    id: &str,
+    pool: &PgPool,
+) -> std::result::Result<ShellResponse, tonic::Status> {
+    let result = match model::ShellResult::get(id, pool).await {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("cannot get shell result from database: {}", e);
+            return Err(tonic::Status::unavailable(
+                "cannot receive shell result from client",
+            ));
+        }
+    };
+
+    Ok(ShellResponse {
+        stdout: result.stdout,
+        stderr: result.stderr,
+        code: result.code,
+        signal: result.signal.unwrap_or_default(),
+        started_at: result.started_at.map_or(0, |t| t.timestamp_millis()),
+        finished_at: result.finished_at.map_or(0, |t| t.timestamp_millis()),
+    })
+}
+
+fn output_response(out: res::Output) -> ShellStreamResponse {
+    let stream = match out.stream() {
+        res::output::Stream::Stdout => shell_output::Stream::Stdout,
+        res::output::Stream::Stderr => shell_output::Stream::Stderr,
+    };
+    ShellStreamResponse {
+        event: Some(shell_stream_response::Event::Output(ShellOutput {
+            stream: stream.into(),
+            data: out.data,
+        })),
+    }
+}
+
+// Caller may pick id of the action, so it is able to cancel the action while waiting for it
+fn action_id(requested: &str) -> String {
+    match requested {
+        "" => Uuid::new_v4().to_string(),
+        id => id.to_owned(),
+    }
+}
+
+fn delivery(requested: Delivery) -> model::Delivery {
+    match requested {
+        Delivery::AtMostOnce => model::Delivery::AtMostOnce,
+        Delivery::AtLeastOnce => model::Delivery::AtLeastOnce,
+    }
+}
+
+fn action_timeout(requested: u64, default: Duration) -> Duration {
+    match requested {
+        0 => default,
+        t => Duration::from_secs(t),
+    }
+}
+
+// Clients in a batch of rollout, given either as a number or a percentage of all of them
+fn batch_size(targets: usize, size: u32, percent: u32) -> usize {
+    match size {
+        0 => (targets * percent as usize).div_ceil(100),
+        n => n as usize,
+    }
+    .max(1)
+}
+
+// Rollout is aborted once more clients than allowed have failed
+fn is_over_threshold(failed: u32, max_failures: Option<u32>) -> bool {
+    max_failures.is_some_and(|max| failed > max)
+}
+
+// Failure reported by the client is returned as is, so it is not mistaken for a missing result
+fn failure(act: &model::Action) -> Option<tonic::Status> {
+    let kind = match (&act.state, &act.error_kind) {
+        (ActionState::TimedOut, _) => {
+            return Some(tonic::Status::deadline_exceeded("action timeout"))
+        }
+        (ActionState::Cancelled, _) => return Some(tonic::Status::cancelled("action cancelled")),
+        (ActionState::Lost, _) => &ActionError::Lost,
+        (ActionState::Failed, Some(kind)) => kind,
+        _ => return None,
+    };
+    let msg = act
+        .error
+        .as_deref()
+        .map(String::from_utf8_lossy)
+        .unwrap_or_default();
+    Some(match kind {
+        ActionError::NotFound => tonic::Status::not_found(msg),
+        ActionError::PermissionDenied => tonic::Status::permission_denied(msg),
+        ActionError::Unsupported => tonic::Status::unimplemented(msg),
+        ActionError::TimedOut => tonic::Status::deadline_exceeded(msg),
+        ActionError::Cancelled => tonic::Status::cancelled(msg),
+        ActionError::Lost => tonic::Status::data_loss(msg),
+        ActionError::SpawnFailed | ActionError::Unknown => tonic::Status::aborted(msg),
+    })
+}
+
+// Watcher fell behind and part of the output is gone, so the rest of it is of no use
+fn output_dropped(e: error::Error) -> tonic::Status {
+    tonic::Status::data_loss(e.to_string())
+}
+
+fn data_response(chunk: res::FileChunk) -> PullResponse {
+    PullResponse {
+        event: Some(pull_response::Event::Data(chunk.data)),
+    }
+}
+
+async fn wait_for_result(id: &str, pool: PgPool, hub: &Hub) -> error::Result<model::Action> {
+    let waker = hub.result(id);
+    loop {
+        // Register interest before looking into the database, so the notification is not missed
+        let notified = waker.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let act = model::Action::get(id, &pool).await?;
+        if act.state.is_done() {
+            return Ok(act);
+        }
+
+        tokio::select! {
+            _ = notified => {},
+            _ = tokio::time::sleep(RESULT_SWEEP_INTERVAL) => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_size_is_number_or_percentage() {
+        assert_eq!(batch_size(10, 3, 0), 3);
+        assert_eq!(batch_size(10, 3, 50), 3);
+        assert_eq!(batch_size(10, 0, 50), 5);
+        // Partial client rounds up, so every batch makes progress
+        assert_eq!(batch_size(10, 0, 25), 3);
+        assert_eq!(batch_size(1, 0, 1), 1);
+        assert_eq!(batch_size(0, 0, 50), 1);
+    }
+
+    #[test]
+    fn rollout_stops_once_failures_exceed_threshold() {
+        assert!(!is_over_threshold(0, Some(0)));
+        assert!(is_over_threshold(1, Some(0)));
+        assert!(!is_over_threshold(2, Some(2)));
+        assert!(is_over_threshold(3, Some(2)));
+        assert!(!is_over_threshold(u32::MAX, None));
+    }
 }
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use notssh_util::error;
+use sqlx::PgPool;
+use tokio::sync::watch::Receiver;
+
+use crate::{
+    model::Client,
+    notssh::{action::Command, res, Action},
+};
+
+// How often replies are written to the database
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Heartbeats of a single Poll stream
+pub struct Pulse {
+    heartbeats: Arc<Heartbeats>,
+    client_id: String,
+    seq: AtomicU64,
+    last_seen: Mutex<Instant>,
+}
+
+impl Pulse {
+    /// Next heartbeat to send
+    pub fn beat(&self) -> Action {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed) + 1;
+        Action::new(
+            String::new(),
+            Command::heartbeat(seq, Utc::now().timestamp_millis()),
+        )
+    }
+
+    /// Client has sent something, so it is alive
+    pub fn seen(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    /// Records reply to a heartbeat and returns the round-trip time
+    pub fn reply(&self, heartbeat: &res::Heartbeat) -> Duration {
+        self.seen();
+        self.heartbeats.record(&self.client_id, heartbeat)
+    }
+
+    /// Nothing has been heard from the client for this long
+    pub fn is_silent(&self, timeout: Duration) -> bool {
+        self.last_seen.lock().unwrap().elapsed() >= timeout
+    }
+}
+
+struct Reply {
+    at: DateTime<Utc>,
+    rtt_ms: i32,
+}
+
+/// Replies to heartbeats, kept in memory and written to the database in batches
+#[derive(Default)]
+pub struct Heartbeats {
+    replies: Mutex<HashMap<String, Reply>>,
+}
+
+impl Heartbeats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Starts heartbeats of a new Poll stream of the client
+    pub fn pulse(self: &Arc<Self>, client_id: &str) -> Pulse {
+        Pulse {
+            heartbeats: self.clone(),
+            client_id: client_id.to_owned(),
+            seq: AtomicU64::new(0),
+            last_seen: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Heartbeat carries the time it was sent at by server, so clock of the client does not matter
+    fn record(&self, client_id: &str, heartbeat: &res::Heartbeat) -> Duration {
+        let at = Utc::now();
+        let rtt_ms = (at.timestamp_millis() - heartbeat.sent_at).clamp(0, i32::MAX as i64);
+        self.replies.lock().unwrap().insert(
+            client_id.to_owned(),
+            Reply {
+                at,
+                rtt_ms: rtt_ms as i32,
+            },
+        );
+        Duration::from_millis(rtt_ms as u64)
+    }
+
+    // Writes replies collected since the last flush. Returns number of clients updated
+    async fn flush(&self, pool: &PgPool) -> error::Result<usize> {
+        let replies = std::mem::take(&mut *self.replies.lock().unwrap());
+        if replies.is_empty() {
+            return Ok(0);
+        }
+        let n = replies.len();
+        let mut ids = Vec::with_capacity(n);
+        let mut seen = Vec::with_capacity(n);
+        let mut rtts = Vec::with_capacity(n);
+        for (id, reply) in replies {
+            ids.push(id);
+            seen.push(reply.at);
+            rtts.push(reply.rtt_ms);
+        }
+        Client::record_heartbeats(ids, seen, rtts, pool).await?;
+        Ok(n)
+    }
+
+    pub async fn run(self: Arc<Self>, pool: PgPool, mut rx: Receiver<()>) {
+        log::info!(target: "HC", "Starting heartbeat writer");
+        let mut i = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = rx.changed() => break,
+                _ = i.tick() => match self.flush(&pool).await {
+                    Ok(n) => log::debug!(target: "HC", "recorded heartbeats of {} clients", n),
+                    Err(e) => log::error!(target: "HC", "cannot record heartbeats: {}", e),
+                },
+            }
+        }
+        if let Err(e) = self.flush(&pool).await {
+            log::error!(target: "HC", "cannot record heartbeats: {}", e);
+        }
+        log::info!(target: "HC", "Stopping heartbeat writer");
+    }
+}
@@ -0,0 +1,609 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use notssh_util::error;
+use prost::Message;
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::sync::{
+    mpsc::{
+        self,
+        error::{TryRecvError, TrySendError},
+        Receiver, Sender, UnboundedReceiver, UnboundedSender,
+    },
+    oneshot, watch, Notify,
+};
+
+use crate::{
+    model::{Client, OutputSubscriber, Relay},
+    notssh::{res, Action, Res},
+};
+
+// Postgres channel used to announce newly created actions. Payload is the client id
+pub const ACTIONS_CHANNEL: &str = "notssh_actions";
+// Postgres channel used to announce finished actions. Payload is the action id
+pub const RESULTS_CHANNEL: &str = "notssh_results";
+// Postgres channel used to hand actions over to the instance holding Poll stream of the client.
+// Payload is the relay id and the client id
+pub const PUSH_CHANNEL: &str = "notssh_push";
+// Postgres channel used to hand output over to the instance watching the action. Payload is the
+// relay id and the action id
+pub const OUTPUT_CHANNEL: &str = "notssh_output";
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// Output chunks of an action the client may send before the watcher takes them
+pub const OUTPUT_WINDOW: u32 = 16;
+// Taken chunks are credited back to the client in batches, so credits relayed through the
+// database don't cost a message per chunk
+const CREDIT_BATCH: u32 = 4;
+// Relayed results buffered for a subscriber. Client holds output back until it is credited, so
+// the buffer fills up only if it does not. Subscriber is closed then instead of holding up
+// everyone else or piling output up in memory
+const OUTPUT_BUFFER: usize = 64;
+
+#[derive(Default)]
+struct Wakers {
+    inner: Mutex<HashMap<String, Arc<Notify>>>,
+}
+
+impl Wakers {
+    fn get(&self, key: &str) -> Arc<Notify> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entry(key.to_owned())
+            .or_default()
+            .clone()
+    }
+
+    fn wake(&self, key: &str) {
+        if let Some(n) = self.inner.lock().unwrap().get(key) {
+            n.notify_one();
+        }
+    }
+
+    fn wake_waiters(&self, key: &str) {
+        if let Some(n) = self.inner.lock().unwrap().get(key) {
+            n.notify_waiters();
+        }
+    }
+
+    fn wake_all(&self) {
+        for n in self.inner.lock().unwrap().values() {
+            n.notify_one();
+        }
+    }
+
+    fn wake_all_waiters(&self) {
+        for n in self.inner.lock().unwrap().values() {
+            n.notify_waiters();
+        }
+    }
+
+    // Drops the waker from registry if nobody else holds it
+    fn release(&self, key: &str, notify: &Arc<Notify>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(n) = inner.get(key) {
+            if Arc::ptr_eq(n, notify) && Arc::strong_count(n) <= 2 {
+                inner.remove(key);
+            }
+        }
+    }
+}
+
+/// Waker for a single action result. Unregisters itself when dropped
+pub struct ResultWaker<'a> {
+    wakers: &'a Wakers,
+    id: String,
+    notify: Arc<Notify>,
+}
+
+impl Deref for ResultWaker<'_> {
+    type Target = Notify;
+
+    fn deref(&self) -> &Self::Target {
+        &self.notify
+    }
+}
+
+impl Drop for ResultWaker<'_> {
+    fn drop(&mut self) {
+        self.wakers.release(&self.id, &self.notify);
+    }
+}
+
+/// Waker for new actions of a client. Unregisters itself when the Poll stream holding it is gone
+pub struct ActionWaker {
+    hub: Arc<Hub>,
+    id: String,
+    notify: Arc<Notify>,
+}
+
+impl Deref for ActionWaker {
+    type Target = Notify;
+
+    fn deref(&self) -> &Self::Target {
+        &self.notify
+    }
+}
+
+impl Drop for ActionWaker {
+    fn drop(&mut self) {
+        self.hub.actions.release(&self.id, &self.notify);
+    }
+}
+
+/// Receiver of results relayed without touching the database, like output chunks of a streaming
+/// shell action or terminal output of a session. Taken chunks are credited back to the client, so
+/// it sends output only as fast as it is taken. Unregisters itself when dropped
+pub struct OutputRelay {
+    hub: Arc<Hub>,
+    db: PgPool,
+    client_id: String,
+    id: String,
+    rx: Receiver<res::Result>,
+    // Chunks taken since the last credit
+    taken: u32,
+}
+
+impl OutputRelay {
+    /// Waits for the next result. Fails once output has been dropped, because the subscriber fell
+    /// behind
+    pub async fn recv(&mut self) -> error::Result<res::Result> {
+        let r = self.rx.recv().await.ok_or_else(dropped)?;
+        self.count(&r);
+        Ok(r)
+    }
+
+    /// Takes the next result if there is one already
+    pub fn try_recv(&mut self) -> error::Result<Option<res::Result>> {
+        let r = match self.rx.try_recv() {
+            Ok(r) => r,
+            Err(TryRecvError::Empty) => return Ok(None),
+            Err(TryRecvError::Disconnected) => return Err(dropped()),
+        };
+        self.count(&r);
+        Ok(Some(r))
+    }
+
+    // Credits taken chunks back to the client once there are a few of them
+    fn count(&mut self, r: &res::Result) {
+        if !matches!(
+            r,
+            res::Result::Output(_) | res::Result::SessionOutput(_) | res::Result::FileChunk(_)
+        ) {
+            return;
+        }
+        self.taken += 1;
+        if self.taken >= CREDIT_BATCH {
+            self.hub
+                .credit(&self.db, &self.client_id, &self.id, self.taken);
+            self.taken = 0;
+        }
+    }
+}
+
+fn dropped() -> error::Error {
+    error::Error::io("output has been dropped, watcher fell behind")
+}
+
+impl Drop for OutputRelay {
+    fn drop(&mut self) {
+        self.hub.outputs.lock().unwrap().remove(&self.id);
+        // Output nobody watches is not held back anymore
+        self.hub.credit(&self.db, &self.client_id, &self.id, 0);
+        let (db, id, instance_id) = (
+            self.db.clone(),
+            self.id.clone(),
+            self.hub.instance_id.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = OutputSubscriber::delete(&id, &instance_id, &db).await {
+                log::error!(target: "HUB", "cannot delete output subscriber of '{}': {}", id, e);
+            }
+        });
+    }
+}
+
+/// Actions pushed directly into Poll stream of a client, bypassing the database
+pub struct Inbox {
+    pub generation: u64,
+    pub rx: UnboundedReceiver<Action>,
+    /// Fires once the session has been taken over by another stream
+    pub lost: oneshot::Receiver<()>,
+}
+
+// Poll stream of a client connected to this process
+struct Stream {
+    generation: u64,
+    epoch: i64,
+    tx: UnboundedSender<Action>,
+    lost: Option<oneshot::Sender<()>>,
+}
+
+/// Hub delivers database notifications and relayed output to tasks waiting inside this process
+pub struct Hub {
+    instance_id: String,
+    actions: Wakers,
+    results: Wakers,
+    outputs: Mutex<HashMap<String, Sender<res::Result>>>,
+    inboxes: Mutex<HashMap<String, Stream>>,
+    generation: AtomicU64,
+}
+
+impl Hub {
+    pub fn new(instance_id: String) -> Arc<Self> {
+        Arc::new(Self {
+            instance_id,
+            actions: Wakers::default(),
+            results: Wakers::default(),
+            outputs: Mutex::default(),
+            inboxes: Mutex::default(),
+            generation: AtomicU64::default(),
+        })
+    }
+
+    /// Returns a waker which is notified whenever a new action is created for the client
+    pub fn actions(self: &Arc<Self>, client_id: &str) -> ActionWaker {
+        ActionWaker {
+            hub: self.clone(),
+            id: client_id.to_owned(),
+            notify: self.actions.get(client_id),
+        }
+    }
+
+    pub fn wake_actions(&self, client_id: &str) {
+        self.actions.wake(client_id);
+    }
+
+    /// Returns a waker which is notified once the action is finished. Waiters are woken with
+    /// `notify_waiters`, so `Notified::enable` must be called before checking the database
+    pub fn result(&self, id: &str) -> ResultWaker<'_> {
+        ResultWaker {
+            wakers: &self.results,
+            id: id.to_owned(),
+            notify: self.results.get(id),
+        }
+    }
+
+    pub fn wake_result(&self, id: &str) {
+        self.results.wake_waiters(id);
+    }
+
+    /// Starts collecting relayed results of the action executed by the client. Must be called
+    /// before the action is dispatched, otherwise the beginning of output is lost. Other instances
+    /// learn there is a watcher here from the database
+    pub async fn subscribe_output(
+        self: &Arc<Self>,
+        db: &PgPool,
+        client_id: &str,
+        id: &str,
+    ) -> error::Result<OutputRelay> {
+        let (tx, rx) = mpsc::channel(OUTPUT_BUFFER);
+        self.outputs.lock().unwrap().insert(id.to_owned(), tx);
+        let relay = OutputRelay {
+            hub: self.clone(),
+            db: db.clone(),
+            client_id: client_id.to_owned(),
+            id: id.to_owned(),
+            rx,
+            taken: 0,
+        };
+        OutputSubscriber::create(id, &self.instance_id, db).await?;
+        Ok(relay)
+    }
+
+    /// Hands output over to the subscriber without waiting. Subscriber which has no room left is
+    /// closed. Returns false if nobody in this process watches the action
+    pub fn relay_output(&self, id: &str, output: res::Result) -> bool {
+        let mut outputs = self.outputs.lock().unwrap();
+        let tx = match outputs.get(id) {
+            Some(tx) => tx,
+            None => return false,
+        };
+        if let Err(TrySendError::Full(_)) = tx.try_send(output) {
+            log::warn!(target: "HUB", "subscriber of '{}' fell behind, dropping its output", id);
+            outputs.remove(id);
+        }
+        true
+    }
+
+    /// Hands output over to the subscriber, which may be in another process. Returns false if
+    /// nobody watches the action
+    pub async fn forward_output(
+        &self,
+        db: &PgPool,
+        id: &str,
+        output: res::Result,
+    ) -> error::Result<bool> {
+        let res = Res {
+            id: id.to_owned(),
+            result: Some(output),
+        };
+        let data = res.encode_to_vec();
+        if let Some(output) = res.result {
+            if self.relay_output(id, output) {
+                return Ok(true);
+            }
+        }
+        Relay::publish_output(id, data, db).await
+    }
+
+    /// Lets the client send more output of the action, 0 chunks lift the limit. Credit is given
+    /// in background, so whoever takes output never waits for the database
+    pub fn credit(self: &Arc<Self>, db: &PgPool, client_id: &str, id: &str, chunks: u32) {
+        let (hub, db, client_id) = (self.clone(), db.clone(), client_id.to_owned());
+        let act = Action::credit(id.to_owned(), chunks);
+        tokio::spawn(async move {
+            if let Err(e) = hub.forward_action(&db, &client_id, act).await {
+                log::error!(target: "HUB", "cannot give credit to '{}': {}", client_id, e);
+            }
+        });
+    }
+
+    /// Registers Poll stream of a client connected to this process. Replaces previous stream,
+    /// which learns it has been taken over
+    pub fn connect_client(&self, client_id: &str, epoch: i64) -> Inbox {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (lost_tx, lost) = oneshot::channel();
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        let stream = Stream {
+            generation,
+            epoch,
+            tx,
+            lost: Some(lost_tx),
+        };
+        self.inboxes
+            .lock()
+            .unwrap()
+            .insert(client_id.to_owned(), stream);
+        Inbox {
+            generation,
+            rx,
+            lost,
+        }
+    }
+
+    /// Unregisters Poll stream, unless it has been replaced already
+    pub fn disconnect_client(&self, client_id: &str, generation: u64) {
+        let mut inboxes = self.inboxes.lock().unwrap();
+        if let Some(s) = inboxes.get(client_id) {
+            if s.generation == generation {
+                inboxes.remove(client_id);
+            }
+        }
+    }
+
+    /// Extends leases of all Poll streams held here with a single update. Streams whose sessions
+    /// have been taken over through another instance are told so
+    pub async fn renew_leases(&self, db: &PgPool, lease: Duration) -> error::Result<()> {
+        // Streams registered from now on may begin their sessions after the update has started
+        let generation = self.generation.load(Ordering::Relaxed);
+        let renewed: HashSet<_> = Client::renew_leases(&self.instance_id, lease, db)
+            .await?
+            .into_iter()
+            .collect();
+        for (client_id, s) in self.inboxes.lock().unwrap().iter_mut() {
+            if s.generation >= generation || renewed.contains(&(client_id.clone(), s.epoch)) {
+                continue;
+            }
+            if let Some(lost) = s.lost.take() {
+                log::warn!(target: "HUB", "session {} of '{}' has been taken over", s.epoch, client_id);
+                let _ = lost.send(());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_connected(&self, client_id: &str) -> bool {
+        self.inboxes.lock().unwrap().contains_key(client_id)
+    }
+
+    /// Sends action to the client through its Poll stream. Returns false if the client is not
+    /// connected to this process
+    pub fn push_action(&self, client_id: &str, act: Action) -> bool {
+        match self.inboxes.lock().unwrap().get(client_id) {
+            Some(s) => s.tx.send(act).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Sends action to the client through its Poll stream, which may be held by another process.
+    /// Client is supposed to be connected, otherwise the action is dropped
+    pub async fn forward_action(
+        &self,
+        db: &PgPool,
+        client_id: &str,
+        act: Action,
+    ) -> error::Result<()> {
+        let data = act.encode_to_vec();
+        if self.push_action(client_id, act) {
+            return Ok(());
+        }
+        Relay::publish(PUSH_CHANNEL, client_id, data, db).await
+    }
+
+    async fn connect(pool: &PgPool) -> sqlx::Result<PgListener> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener
+            .listen_all([
+                ACTIONS_CHANNEL,
+                RESULTS_CHANNEL,
+                PUSH_CHANNEL,
+                OUTPUT_CHANNEL,
+            ])
+            .await?;
+        Ok(listener)
+    }
+
+    // Delivers messages relayed by other processes one by one, so they arrive in order without
+    // holding up notifications
+    async fn deliveries(
+        self: Arc<Self>,
+        pool: PgPool,
+        mut rx: UnboundedReceiver<(String, String)>,
+    ) {
+        while let Some((channel, payload)) = rx.recv().await {
+            if let Err(e) = self.deliver(&pool, &channel, &payload).await {
+                log::error!(target: "HUB", "cannot deliver relayed message: {}", e);
+            }
+        }
+    }
+
+    // Delivers message relayed by another process, if its target is here
+    async fn deliver(&self, pool: &PgPool, channel: &str, payload: &str) -> error::Result<()> {
+        let (id, target) = payload
+            .split_once(' ')
+            .and_then(|(id, target)| Some((id.parse().ok()?, target)))
+            .ok_or_else(|| error::Error::bad_request(format!("invalid payload '{}'", payload)))?;
+        let here = match channel {
+            PUSH_CHANNEL => self.is_connected(target),
+            _ => self.outputs.lock().unwrap().contains_key(target),
+        };
+        if !here {
+            return Ok(());
+        }
+        let relay = match Relay::take(id, pool).await? {
+            Some(relay) => relay,
+            None => return Ok(()),
+        };
+        log::debug!(target: "HUB", "delivering relayed message {} to '{}'", relay.id, target);
+        let invalid = |e: prost::DecodeError| error::Error::bad_request(e.to_string());
+        match channel {
+            PUSH_CHANNEL => {
+                self.push_action(
+                    target,
+                    Action::decode(relay.message.as_slice()).map_err(invalid)?,
+                );
+            }
+            _ => {
+                let res = Res::decode(relay.message.as_slice()).map_err(invalid)?;
+                if let Some(output) = res.result {
+                    self.relay_output(target, output);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn listen(self: Arc<Self>, pool: PgPool, mut rx: watch::Receiver<()>) {
+        log::info!(target: "HUB", "Starting notification listener");
+        let mut listener = loop {
+            match Self::connect(&pool).await {
+                Ok(l) => break l,
+                Err(e) => {
+                    log::error!(target: "HUB", "cannot start listening for notifications: {}", e);
+                    tokio::select! {
+                        _ = rx.changed() => return,
+                        _ = tokio::time::sleep(RECONNECT_INTERVAL) => continue,
+                    }
+                }
+            }
+        };
+
+        // Pushes and output are delivered apart, so neither holds up the other
+        let (pushes, pushes_rx) = mpsc::unbounded_channel();
+        tokio::spawn(self.clone().deliveries(pool.clone(), pushes_rx));
+        let (outputs, outputs_rx) = mpsc::unbounded_channel();
+        tokio::spawn(self.clone().deliveries(pool.clone(), outputs_rx));
+        loop {
+            tokio::select! {
+                _ = rx.changed() => break,
+                n = listener.try_recv() => match n {
+                    Ok(Some(n)) => match n.channel() {
+                        ACTIONS_CHANNEL => self.actions.wake(n.payload()),
+                        RESULTS_CHANNEL => self.results.wake_waiters(n.payload()),
+                        c @ PUSH_CHANNEL => {
+                            let _ = pushes.send((c.to_owned(), n.payload().to_owned()));
+                        }
+                        c @ OUTPUT_CHANNEL => {
+                            let _ = outputs.send((c.to_owned(), n.payload().to_owned()));
+                        }
+                        c => log::warn!(target: "HUB", "notification from unknown channel '{}'", c),
+                    },
+                    Ok(None) => {
+                        // Connection is lost and will be reestablished on next call. Notifications
+                        // sent in between are lost, so everyone should check the database
+                        log::warn!(target: "HUB", "lost connection to database, waking everyone");
+                        self.actions.wake_all();
+                        self.results.wake_all_waiters();
+                    }
+                    Err(e) => {
+                        log::error!(target: "HUB", "cannot receive notification: {}", e);
+                        tokio::time::sleep(RECONNECT_INTERVAL).await;
+                    }
+                },
+            }
+        }
+        log::info!(target: "HUB", "Stopping notification listener");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(id: &str) -> Action {
+        Action {
+            id: id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn new_stream_takes_over_client() {
+        let hub = Hub::new("instance".to_owned());
+        let old = hub.connect_client("client", 1);
+        let mut new = hub.connect_client("client", 2);
+        // Old stream going away does not unregister the new one
+        hub.disconnect_client("client", old.generation);
+        assert!(hub.is_connected("client"));
+        assert!(hub.push_action("client", action("a")));
+        assert_eq!(new.rx.try_recv().unwrap().id, "a");
+        hub.disconnect_client("client", new.generation);
+        assert!(!hub.is_connected("client"));
+        assert!(!hub.push_action("client", action("b")));
+    }
+
+    #[test]
+    fn taken_over_stream_is_told() {
+        let hub = Hub::new("instance".to_owned());
+        let mut old = hub.connect_client("client", 1);
+        assert_eq!(
+            old.lost.try_recv(),
+            Err(oneshot::error::TryRecvError::Empty)
+        );
+        let mut new = hub.connect_client("client", 2);
+        assert_eq!(
+            old.lost.try_recv(),
+            Err(oneshot::error::TryRecvError::Closed)
+        );
+        assert_eq!(
+            new.lost.try_recv(),
+            Err(oneshot::error::TryRecvError::Empty)
+        );
+    }
+
+    #[test]
+    fn subscriber_falling_behind_is_closed() {
+        let hub = Hub::new("instance".to_owned());
+        let (tx, mut rx) = mpsc::channel(OUTPUT_BUFFER);
+        hub.outputs.lock().unwrap().insert("a".to_owned(), tx);
+        let output = || res::Result::SessionOutput(res::SessionOutput { data: vec![0] });
+        for _ in 0..=OUTPUT_BUFFER {
+            assert!(hub.relay_output("a", output()));
+        }
+        assert!(!hub.relay_output("a", output()));
+        // Output taken in so far is still there
+        for _ in 0..OUTPUT_BUFFER {
+            assert!(rx.try_recv().is_ok());
+        }
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     fs::{self, File},
     net::SocketAddr,
+    sync::Arc,
     time::Duration,
 };
 
@@ -15,30 +16,128 @@ use tokio::{
     sync::watch::{self, Receiver},
 };
 use tokio_stream::wrappers::UnixListenerStream;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
 
-use model::{ActionCommand, ActionState, ListOptions, PingCommand, ShellCommand};
+use model::{
+    ActionCommand, Blob, GetFileCommand, GetFileResult, ListOptions, PingCommand, PingResult,
+    PutFileCommand, SessionCommand, ShellCommand, ShellResult,
+};
 
 mod api;
 mod cli;
+mod heartbeat;
+mod hub;
 mod model;
+mod selector;
 
 // TTL do delete clients after 24 hours of inactivity
 const CLIENT_TTL: Duration = Duration::from_secs(86400);
+// TTL to delete uploaded files which are not put on any client
+const BLOB_TTL: Duration = Duration::from_secs(3600);
+// TTL to delete finished actions, their results can be fetched until then
+const RESULT_TTL: Duration = Duration::from_secs(86400);
+// TTL to delete jobs, their status is available until then
+const JOB_TTL: Duration = Duration::from_secs(7 * 86400);
+// How often actions are checked against their deadlines
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+// How often the instance tells it is alive
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+// TTL to delete instances which stopped sending heartbeats
+const INSTANCE_TTL: Duration = Duration::from_secs(300);
+// TTL to delete relayed messages nobody has taken
+const RELAY_TTL: Duration = Duration::from_secs(3600);
 
 pub mod notssh {
     include!("../../gen/notssh.rs");
 
+    impl Action {
+        pub fn new(id: String, command: action::Command) -> Self {
+            Self {
+                id,
+                command: Some(command),
+                timeout: 0,
+                window: 0,
+            }
+        }
+
+        /// Credit for output of the action, see `action::Credit`
+        pub fn credit(id: String, chunks: u32) -> Self {
+            Self::new(
+                String::new(),
+                action::Command::Credit(action::Credit { id, chunks }),
+            )
+        }
+    }
+
     impl action::Command {
         pub fn ping(data: String) -> Self {
-            Self::Ping(action::Ping { ping: data })
+            Self::Ping(action::Ping {
+                ping: data,
+                sent_at: 0,
+            })
         }
 
         pub fn purge() -> Self {
             Self::Purge(action::Purge {})
         }
 
-        pub fn shell(cmd: String, args: Vec<String>, stdin: Vec<u8>) -> Self {
-            Self::Shell(action::Shell { cmd, args, stdin })
+        pub fn session(rows: u32, cols: u32, term: String) -> Self {
+            Self::Session(action::Session { rows, cols, term })
+        }
+
+        pub fn session_input(data: Vec<u8>) -> Self {
+            Self::SessionInput(action::SessionInput { data })
+        }
+
+        pub fn session_resize(rows: u32, cols: u32) -> Self {
+            Self::SessionResize(action::SessionResize { rows, cols })
+        }
+
+        pub fn session_close() -> Self {
+            Self::SessionClose(action::SessionClose {})
+        }
+
+        pub fn put_file(
+            path: String,
+            mode: u32,
+            owner: String,
+            size: u64,
+            sha256: Vec<u8>,
+            atomic: bool,
+        ) -> Self {
+            Self::PutFile(action::PutFile {
+                path,
+                mode,
+                owner,
+                size,
+                sha256,
+                atomic,
+            })
+        }
+
+        pub fn file_chunk(offset: u64, data: Vec<u8>) -> Self {
+            Self::FileChunk(action::FileChunk { offset, data })
+        }
+
+        pub fn get_file(path: String) -> Self {
+            Self::GetFile(action::GetFile { path })
+        }
+
+        pub fn cancel() -> Self {
+            Self::Cancel(action::Cancel {})
+        }
+
+        pub fn heartbeat(seq: u64, sent_at: i64) -> Self {
+            Self::Heartbeat(action::Heartbeat { seq, sent_at })
+        }
+
+        pub fn shell(cmd: String, args: Vec<String>, stdin: Vec<u8>, stream: bool) -> Self {
+            Self::Shell(action::Shell {
+                cmd,
+                args,
+                stdin,
+                stream,
+            })
         }
     }
 }
@@ -69,6 +168,24 @@ impl DatabaseConfig {
     }
 }
 
+#[derive(serde::Deserialize)]
+struct TlsConfig {
+    cert: String,
+    key: String,
+    // Agents must present a certificate signed by this CA, its common name is the client id
+    client_ca: String,
+}
+
+#[derive(serde::Deserialize, Default, Clone, Copy)]
+struct EnrollmentConfig {
+    // Clients can't register without a token created by operator
+    #[serde(default)]
+    require_token: bool,
+    // Registered clients are given no actions until operator approves them
+    #[serde(default)]
+    require_approval: bool,
+}
+
 #[derive(serde::Deserialize)]
 struct Config {
     #[serde(default = "Config::default_address")]
@@ -78,6 +195,10 @@ struct Config {
     #[serde(default = "Config::default_socket")]
     socket: String,
     db: DatabaseConfig,
+    // Agent connections are plaintext unless set
+    tls: Option<TlsConfig>,
+    #[serde(default)]
+    enrollment: EnrollmentConfig,
 }
 
 impl Config {
@@ -128,7 +249,19 @@ async fn gc(pool: PgPool, mut rx: Receiver<()>) {
                         continue;
                     }
                 };
-                let actions = match model::Action::list_by_state(ActionState::Finished, ListOptions::new(), &mut tx).await {
+                // Instances share the database, one sweep at a time is enough
+                match model::Singleton::Gc.try_lock(&mut tx).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        log::debug!(target: "GC", "another instance is collecting garbage");
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!(target: "GC", "cannot lock GC: {}", e);
+                        continue;
+                    }
+                }
+                let actions = match model::Action::list_done(RESULT_TTL, ListOptions::new(), &mut tx).await {
                     Ok(act) => act,
                     Err(e) => {
                         log::error!(target: "GC", "cannot list finished actions: {}", e);
@@ -138,9 +271,21 @@ async fn gc(pool: PgPool, mut rx: Receiver<()>) {
                 log::debug!(target: "GC", "removing finished actions: {:?}", actions);
                 for act in actions {
                     if let Err(e) = match act.command {
-                        ActionCommand::Ping => PingCommand::delete(&act.id, &mut tx).await,
+                        ActionCommand::Ping => match PingCommand::delete(&act.id, &mut tx).await {
+                            Ok(_) => PingResult::delete(&act.id, &mut tx).await,
+                            Err(e) => Err(e),
+                        },
                         ActionCommand::Purge => Ok(()),
-                        ActionCommand::Shell => ShellCommand::delete(&act.id, &mut tx).await,
+                        ActionCommand::Shell => match ShellCommand::delete(&act.id, &mut tx).await {
+                            Ok(_) => ShellResult::delete(&act.id, &mut tx).await,
+                            Err(e) => Err(e),
+                        },
+                        ActionCommand::Session => SessionCommand::delete(&act.id, &mut tx).await,
+                        ActionCommand::PutFile => PutFileCommand::delete(&act.id, &mut tx).await,
+                        ActionCommand::GetFile => match GetFileCommand::delete(&act.id, &mut tx).await {
+                            Ok(_) => GetFileResult::delete(&act.id, &mut tx).await,
+                            Err(e) => Err(e),
+                        },
                     } {
                         log::error!(target: "GC", "cannot delete command from database: {}", e);
                         continue;
@@ -151,6 +296,54 @@ async fn gc(pool: PgPool, mut rx: Receiver<()>) {
                     }
                 }
 
+                match Blob::delete_unused(BLOB_TTL, &mut tx).await {
+                    Ok(n) => log::debug!(target: "GC", "removed {} unused blobs", n),
+                    Err(e) => {
+                        log::error!(target: "GC", "cannot delete unused blobs from database: {}", e);
+                        continue;
+                    }
+                }
+
+                match model::Job::delete_expired(JOB_TTL, &mut tx).await {
+                    Ok(n) => log::debug!(target: "GC", "removed {} expired jobs", n),
+                    Err(e) => {
+                        log::error!(target: "GC", "cannot delete expired jobs from database: {}", e);
+                        continue;
+                    }
+                }
+
+                match model::EnrollmentToken::delete_expired(&mut tx).await {
+                    Ok(n) => log::debug!(target: "GC", "removed {} expired enrollment tokens", n),
+                    Err(e) => {
+                        log::error!(target: "GC", "cannot delete expired enrollment tokens from database: {}", e);
+                        continue;
+                    }
+                }
+
+                match model::Challenge::delete_expired(&mut tx).await {
+                    Ok(n) => log::debug!(target: "GC", "removed {} expired challenges", n),
+                    Err(e) => {
+                        log::error!(target: "GC", "cannot delete expired challenges from database: {}", e);
+                        continue;
+                    }
+                }
+
+                match model::Relay::delete_expired(RELAY_TTL, &mut tx).await {
+                    Ok(n) => log::debug!(target: "GC", "removed {} expired relayed messages", n),
+                    Err(e) => {
+                        log::error!(target: "GC", "cannot delete expired relayed messages from database: {}", e);
+                        continue;
+                    }
+                }
+
+                match model::Instance::delete_stale(INSTANCE_TTL, &mut tx).await {
+                    Ok(n) => log::debug!(target: "GC", "removed {} stale instances", n),
+                    Err(e) => {
+                        log::error!(target: "GC", "cannot delete stale instances from database: {}", e);
+                        continue;
+                    }
+                }
+
                 let clients = match model::Client::list_stale(CLIENT_TTL, &mut tx).await {
                     Ok(clients) => clients,
                     Err(e) => {
@@ -174,35 +367,83 @@ async fn gc(pool: PgPool, mut rx: Receiver<()>) {
     }
 
     log::info!(target: "GC", "Stopping GC");
+}
+
+// Keeps the instance alive in the database along with leases of clients connected here. Clients
+// are released on shutdown, so they don't have to wait for their leases to expire
+async fn heartbeat(pool: PgPool, instance_id: String, hub: Arc<hub::Hub>, mut rx: Receiver<()>) {
+    log::info!(target: "HEARTBEAT", "Starting heartbeat of instance {}", instance_id);
+    let mut i = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = rx.changed() => break,
+            _ = i.tick() => {
+                match model::Instance::heartbeat(&instance_id, &pool).await {
+                    Ok(false) => {}
+                    Ok(true) => log::warn!(target: "HEARTBEAT", "instance has been removed as stale, created it again"),
+                    Err(e) => log::error!(target: "HEARTBEAT", "cannot update instance in database: {}", e),
+                }
+                // Leases are still valid for a while, renewal is retried on the next tick
+                if let Err(e) = hub.renew_leases(&pool, api::SESSION_LEASE).await {
+                    log::error!(target: "HEARTBEAT", "cannot renew leases of clients: {}", e);
+                }
+            }
+        }
+    }
+
+    log::info!(target: "HEARTBEAT", "Stopping heartbeat");
     let mut tx = match pool.begin().await {
         Ok(tx) => tx,
         Err(e) => {
-            log::error!(target: "GC", "cannot begin transaction: {}", e);
+            log::error!(target: "HEARTBEAT", "cannot begin transaction: {}", e);
             return;
         }
     };
 
-    let clients = match model::Client::list(ListOptions::new(), &mut tx).await {
-        Ok(clients) => clients,
-        Err(e) => {
-            log::error!(target: "GC", "cannot list clients: {}", e);
-            return;
-        }
-    };
+    if let Err(e) = model::Client::release_instance(&instance_id, &mut tx).await {
+        log::error!(target: "HEARTBEAT", "cannot release clients of instance: {}", e);
+        return;
+    }
 
-    for mut client in clients.into_iter().filter(|c| c.connected) {
-        client.connected = false;
-        client.address = None;
-        if let Err(e) = client.update(&mut tx).await {
-            log::error!(target: "GC", "cannot update client in database: {}", e);
-        }
+    if let Err(e) = model::Instance::delete(&instance_id, &mut tx).await {
+        log::error!(target: "HEARTBEAT", "cannot delete instance from database: {}", e);
+        return;
     }
 
     if let Err(e) = tx.commit().await {
-        log::error!(target: "GC", "cannot commit transaction: {}", e);
+        log::error!(target: "HEARTBEAT", "cannot commit transaction: {}", e);
     }
 }
 
+// Times out actions whose deadline has passed, so nobody waits for them forever
+async fn reaper(pool: PgPool, mut rx: Receiver<()>) {
+    log::info!(target: "REAPER", "Starting reaper");
+    let mut i = tokio::time::interval(REAP_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = rx.changed() => break,
+            _ = i.tick() => if let Err(e) = reap(&pool).await {
+                log::error!(target: "REAPER", "cannot time out overdue actions: {}", e);
+            },
+        }
+    }
+    log::info!(target: "REAPER", "Stopping reaper");
+}
+
+async fn reap(pool: &PgPool) -> notssh_util::error::Result<()> {
+    let mut tx = pool.begin().await?;
+    // Instances share the database, one of them is enough to watch deadlines
+    if !model::Singleton::Reaper.try_lock(&mut tx).await? {
+        return Ok(());
+    }
+    let n = model::Action::time_out_overdue(&mut tx).await?;
+    tx.commit().await?;
+    if n > 0 {
+        log::info!(target: "REAPER", "timed out {} actions", n);
+    }
+    Ok(())
+}
+
 // Helper func for graceful shutdown
 async fn waiter(mut rx: Receiver<()>) {
     let _ = rx.changed().await;
@@ -238,13 +479,47 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    let instance = model::Instance::new();
+    let instance_id = instance.id.clone();
+    instance.create(&pool).await?;
+    let hub = hub::Hub::new(instance_id.clone());
+    let heartbeat_handle = tokio::spawn(heartbeat(
+        pool.clone(),
+        instance_id.clone(),
+        hub.clone(),
+        rx.clone(),
+    ));
+
     log::info!("Starting GC");
     let gc_handle = tokio::spawn(gc(pool.clone(), rx.clone()));
+    let reaper_handle = tokio::spawn(reaper(pool.clone(), rx.clone()));
+
+    let hub_handle = tokio::spawn(hub.clone().listen(pool.clone(), rx.clone()));
+
+    let heartbeats = heartbeat::Heartbeats::new();
+    let heartbeats_handle = tokio::spawn(heartbeats.clone().run(pool.clone(), rx.clone()));
 
     log::info!("Starting server");
-    let service = api::Server::new(pool.clone());
+    let service = api::Server::new(
+        pool.clone(),
+        hub.clone(),
+        heartbeats,
+        instance_id,
+        cfg.tls.is_some(),
+        cfg.enrollment,
+    );
     let addr = SocketAddr::new(cfg.address.parse()?, cfg.port);
-    let server = tonic::transport::Server::builder().add_service(NotSshServer::new(service));
+    let mut builder = tonic::transport::Server::builder();
+    if let Some(tls) = &cfg.tls {
+        let identity = Identity::from_pem(fs::read(&tls.cert)?, fs::read(&tls.key)?);
+        let client_ca = Certificate::from_pem(fs::read(&tls.client_ca)?);
+        builder = builder.tls_config(
+            ServerTlsConfig::new()
+                .identity(identity)
+                .client_ca_root(client_ca),
+        )?;
+    }
+    let server = builder.add_service(NotSshServer::new(service));
 
     let server_handle = tokio::spawn(server.serve_with_shutdown(addr, waiter(rx.clone())));
 
@@ -258,7 +533,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }?;
     let cli_listener = UnixListener::bind(cfg.socket).unwrap();
     let cli_listener = UnixListenerStream::new(cli_listener);
-    let cli_service = cli::CliServer::new(pool);
+    let cli_service = cli::CliServer::new(pool, hub);
     let cli_server =
         tonic::transport::Server::builder().add_service(NotSshCliServer::new(cli_service));
 
@@ -275,7 +550,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     log::info!("Shutting down");
     tx.send(())?;
-    let _ = tokio::join!(gc_handle, server_handle, cli_server_handle);
+    let _ = tokio::join!(
+        heartbeat_handle,
+        heartbeats_handle,
+        gc_handle,
+        reaper_handle,
+        hub_handle,
+        server_handle,
+        cli_server_handle
+    );
 
     Ok(())
 }
@@ -1,8 +1,12 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Duration, Utc};
 use notssh_util::error;
 use sqlx::{Executor, PgExecutor, Postgres, QueryBuilder};
 use uuid::Uuid;
 
+use crate::hub;
+
 pub struct ListOptions {
     limit: Option<i64>,
     offset: Option<i64>,
@@ -37,6 +41,19 @@ pub struct Client {
     pub address: Option<String>,
     pub connected: bool,
     pub last_online: DateTime<Utc>,
+    pub public_key: Option<Vec<u8>>,
+    pub approved: bool,
+    // Incremented with every Poll stream, so a stream that has been taken over can tell
+    pub epoch: i64,
+    // Server instance holding the Poll stream
+    pub instance_id: Option<String>,
+    // Round-trip time of the last heartbeat
+    pub rtt_ms: Option<i32>,
+    // Poll stream renews the lease while it lives. Expired lease means the stream is dead, even
+    // if server did not get to mark the client disconnected. Lease is checked against the clock
+    // of the database, which all instances share
+    #[sqlx(default)]
+    pub live: bool,
 }
 
 impl Client {
@@ -47,53 +64,193 @@ impl Client {
             address: None,
             connected: false,
             last_online: Utc::now(),
+            public_key: None,
+            approved: true,
+            epoch: 0,
+            instance_id: None,
+            rtt_ms: None,
+            live: false,
         }
     }
 
-    pub fn with_address(address: String) -> Self {
-        let id = Uuid::new_v4().to_string();
+    pub fn with_id(id: String) -> Self {
         Self {
             id,
-            address: Some(address),
+            address: None,
             connected: false,
             last_online: Utc::now(),
+            public_key: None,
+            approved: true,
+            epoch: 0,
+            instance_id: None,
+            rtt_ms: None,
+            live: false,
         }
     }
 
+    /// Client has a Poll stream which renews its lease. Known only for clients got by `get`,
+    /// `get_for_update` or `list`
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
     pub async fn get(
         id: &str,
         ex: impl PgExecutor<'_, Database = Postgres>,
     ) -> error::Result<Self> {
-        sqlx::query_as("SELECT * FROM clients WHERE id = $1")
-            .bind(id)
-            .fetch_one(ex)
-            .await
-            .map_err(From::from)
+        sqlx::query_as(
+            "SELECT *, connected AND coalesce(lease_expires_at > current_timestamp, false) AS live
+            FROM clients WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(ex)
+        .await
+        .map_err(From::from)
+    }
+
+    /// Same as `get`, but keeps the row locked until the end of transaction, so concurrent
+    /// connections of the same client are serialized
+    pub async fn get_for_update(
+        id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Self> {
+        sqlx::query_as(
+            "SELECT *, connected AND coalesce(lease_expires_at > current_timestamp, false) AS live
+            FROM clients WHERE id = $1 FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(ex)
+        .await
+        .map_err(From::from)
     }
 
     pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
         sqlx::query(
-            "INSERT INTO clients (id, address, connected, last_online) VALUES ($1, $2, $3, $4)",
+            "INSERT INTO clients (id, address, connected, last_online, public_key, approved)
+            VALUES ($1, $2, $3, $4, $5, $6)",
         )
         .bind(self.id)
         .bind(self.address)
         .bind(self.connected)
         .bind(self.last_online)
+        .bind(self.public_key)
+        .bind(self.approved)
         .execute(ex)
         .await?;
         Ok(())
     }
 
     pub async fn update(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
-        sqlx::query("UPDATE clients SET (connected, last_online) = ($1, $2) WHERE id = $3")
-            .bind(self.connected)
-            .bind(self.last_online)
-            .bind(self.id)
+        sqlx::query(
+            "UPDATE clients SET (connected, last_online, public_key) = ($1, $2, $3) WHERE id = $4",
+        )
+        .bind(self.connected)
+        .bind(self.last_online)
+        .bind(self.public_key)
+        .bind(self.id)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    /// Starts a new session of the client held by the instance, taking over the previous one.
+    /// Returns its epoch
+    pub async fn begin_session(
+        id: &str,
+        instance_id: &str,
+        lease: std::time::Duration,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<i64> {
+        let (epoch,) = sqlx::query_as(
+            "UPDATE clients SET epoch = epoch + 1, lease_expires_at = current_timestamp + $1,
+            instance_id = $2 WHERE id = $3 RETURNING epoch",
+        )
+        .bind(lease)
+        .bind(instance_id)
+        .bind(id)
+        .fetch_one(ex)
+        .await?;
+        Ok(epoch)
+    }
+
+    /// Extends leases of all sessions held by the instance at once. Returns ids and epochs of
+    /// the sessions renewed
+    pub async fn renew_leases(
+        instance_id: &str,
+        lease: std::time::Duration,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Vec<(String, i64)>> {
+        sqlx::query_as(
+            "UPDATE clients SET lease_expires_at = current_timestamp + $1
+            WHERE instance_id = $2 AND connected RETURNING id, epoch",
+        )
+        .bind(lease)
+        .bind(instance_id)
+        .fetch_all(ex)
+        .await
+        .map_err(From::from)
+    }
+
+    /// Marks the client disconnected, unless the session has been taken over already. Returns
+    /// false then
+    pub async fn end_session(
+        id: &str,
+        epoch: i64,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<bool> {
+        let res = sqlx::query(
+            "UPDATE clients SET connected = false, address = NULL, lease_expires_at = NULL,
+            instance_id = NULL, last_online = current_timestamp WHERE id = $1 AND epoch = $2",
+        )
+        .bind(id)
+        .bind(epoch)
+        .execute(ex)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Lets a pending client in. Fails if the client does not exist
+    pub async fn approve(
+        id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        let res = sqlx::query("UPDATE clients SET approved = true WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(error::Error::not_found("client not found"));
+        }
+        Ok(())
+    }
+
+    /// Keeps the client out until operator approves it again
+    pub async fn revoke_approval(
+        id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query("UPDATE clients SET approved = false WHERE id = $1")
+            .bind(id)
             .execute(ex)
             .await?;
         Ok(())
     }
 
+    /// Marks clients whose streams are held by the instance disconnected, e.g. when it shuts down
+    pub async fn release_instance(
+        instance_id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<u64> {
+        let res = sqlx::query(
+            "UPDATE clients SET connected = false, address = NULL, lease_expires_at = NULL,
+            instance_id = NULL, last_online = current_timestamp WHERE instance_id = $1",
+        )
+        .bind(instance_id)
+        .execute(ex)
+        .await?;
+        Ok(res.rows_affected())
+    }
+
     pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
         sqlx::query("DELETE FROM clients WHERE id = $1")
             .bind(id)
@@ -106,7 +263,10 @@ impl Client {
         opts: ListOptions,
         ex: impl Executor<'_, Database = Postgres>,
     ) -> error::Result<Vec<Self>> {
-        let mut builder = QueryBuilder::new("SELECT * FROM clients");
+        let mut builder = QueryBuilder::new(
+            "SELECT *, connected AND coalesce(lease_expires_at > current_timestamp, false) AS live
+            FROM clients",
+        );
         if let Some(limit) = opts.limit {
             builder.push(" LIMIT ").push_bind(limit);
         }
@@ -120,6 +280,26 @@ impl Client {
             .map_err(From::from)
     }
 
+    /// Records replies to heartbeats of many clients at once. Vectors are of the same length
+    pub async fn record_heartbeats(
+        ids: Vec<String>,
+        seen: Vec<DateTime<Utc>>,
+        rtts: Vec<i32>,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query(
+            "UPDATE clients SET last_online = r.seen, rtt_ms = r.rtt
+            FROM UNNEST($1::varchar[], $2::timestamptz[], $3::integer[]) AS r (id, seen, rtt)
+            WHERE clients.id = r.id AND clients.last_online < r.seen",
+        )
+        .bind(ids)
+        .bind(seen)
+        .bind(rtts)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
     pub async fn list_stale(
         ttl: std::time::Duration,
         ex: impl Executor<'_, Database = Postgres>,
@@ -132,179 +312,1108 @@ impl Client {
     }
 }
 
-#[derive(Debug, sqlx::Type)]
-#[repr(i16)]
-pub enum ActionCommand {
-    Ping,
-    Purge,
-    Shell,
+/// Host description last reported by a client
+#[derive(Debug, sqlx::FromRow)]
+pub struct Facts {
+    pub client_id: String,
+    pub hostname: String,
+    pub machine_id: String,
+    pub os_release: String,
+    pub kernel: String,
+    pub arch: String,
+    pub cpus: i32,
+    pub memory: i64,
+    pub addresses: Vec<String>,
+    pub agent_version: String,
+    pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, sqlx::Type)]
-#[repr(i16)]
-pub enum ActionState {
-    Pending,
-    Running,
-    Finished,
+impl Facts {
+    /// Replaces previously reported facts
+    pub async fn upsert(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query(
+            "INSERT INTO facts (client_id, hostname, machine_id, os_release, kernel, arch, cpus,
+                memory, addresses, agent_version, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (client_id) DO UPDATE SET hostname = $2, machine_id = $3,
+                os_release = $4, kernel = $5, arch = $6, cpus = $7, memory = $8,
+                addresses = $9, agent_version = $10, updated_at = $11",
+        )
+        .bind(self.client_id)
+        .bind(self.hostname)
+        .bind(self.machine_id)
+        .bind(self.os_release)
+        .bind(self.kernel)
+        .bind(self.arch)
+        .bind(self.cpus)
+        .bind(self.memory)
+        .bind(self.addresses)
+        .bind(self.agent_version)
+        .bind(self.updated_at)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list(ex: impl Executor<'_, Database = Postgres>) -> error::Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM facts")
+            .fetch_all(ex)
+            .await
+            .map_err(From::from)
+    }
 }
 
+/// Label of a client. Client reports its own labels, ones set by operator take precedence
 #[derive(Debug, sqlx::FromRow)]
-pub struct Action {
-    pub id: String,
+pub struct Label {
     pub client_id: String,
-    pub created_at: DateTime<Utc>,
-    pub started_at: Option<DateTime<Utc>>,
-    pub timeout: Option<i64>,
-    pub command: ActionCommand,
-    pub state: ActionState,
-    pub error: Option<Vec<u8>>, // using bytes here, so it is possible to get files as response
-    pub result: Option<Vec<u8>>, // same
+    pub key: String,
+    pub value: String,
+    pub operator: bool,
 }
 
-impl Action {
-    pub fn new(client_id: String, command: ActionCommand) -> Self {
-        let id = Uuid::new_v4().to_string();
-        let created_at = Utc::now();
+impl Label {
+    pub fn new(client_id: String, key: String, value: String, operator: bool) -> Self {
         Self {
-            id,
             client_id,
-            created_at,
-            started_at: None,
-            timeout: None,
-            command,
-            state: ActionState::Pending,
-            error: None,
-            result: None,
+            key,
+            value,
+            operator,
         }
     }
 
-    pub fn _with_timeout(client_id: String, command: ActionCommand, timeout: Duration) -> Self {
-        let id = Uuid::new_v4().to_string();
+    pub async fn upsert(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query(
+            "INSERT INTO labels (client_id, key, value, operator) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (client_id, key, operator) DO UPDATE SET value = $3",
+        )
+        .bind(self.client_id)
+        .bind(self.key)
+        .bind(self.value)
+        .bind(self.operator)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(
+        client_id: &str,
+        key: &str,
+        operator: bool,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query("DELETE FROM labels WHERE client_id = $1 AND key = $2 AND operator = $3")
+            .bind(client_id)
+            .bind(key)
+            .bind(operator)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes labels reported by client before, so it can report its current ones
+    pub async fn delete_reported(
+        client_id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query("DELETE FROM labels WHERE client_id = $1 AND NOT operator")
+            .bind(client_id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    /// Effective labels of all clients by client id
+    pub async fn list(
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<HashMap<String, HashMap<String, String>>> {
+        // Operator labels come last, so they replace reported ones
+        let labels: Vec<Self> = sqlx::query_as("SELECT * FROM labels ORDER BY operator")
+            .fetch_all(ex)
+            .await?;
+        let mut res: HashMap<String, HashMap<String, String>> = HashMap::new();
+        for l in labels {
+            res.entry(l.client_id).or_default().insert(l.key, l.value);
+        }
+        Ok(res)
+    }
+}
+
+/// Token a client registers with. Only its hash is stored
+#[derive(Debug, sqlx::FromRow)]
+pub struct EnrollmentToken {
+    pub id: String,
+    pub sha256: Vec<u8>,
+    pub reusable: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl EnrollmentToken {
+    pub fn new(sha256: Vec<u8>, reusable: bool, ttl: Option<std::time::Duration>) -> Self {
         let created_at = Utc::now();
         Self {
-            id,
-            client_id,
+            id: Uuid::new_v4().to_string(),
+            sha256,
+            reusable,
             created_at,
-            started_at: None,
-            timeout: Some(timeout.num_seconds()),
-            command,
-            state: ActionState::Pending,
-            error: None,
-            result: None,
+            expires_at: ttl.map(|t| created_at + Duration::seconds(t.as_secs() as i64)),
         }
     }
 
-    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
-        sqlx::query_as("SELECT * FROM actions WHERE id = $1")
-            .bind(id)
-            .fetch_one(ex)
-            .await
-            .map_err(From::from)
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query(
+            "INSERT INTO enrollment_token (id, sha256, reusable, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(self.id)
+        .bind(self.sha256)
+        .bind(self.reusable)
+        .bind(self.created_at)
+        .bind(self.expires_at)
+        .execute(ex)
+        .await?;
+        Ok(())
     }
 
-    pub async fn get_next(
-        client_id: &str,
+    /// Finds valid token by its hash. Single use token is deleted, so it can't be used again
+    pub async fn redeem(
+        sha256: &[u8],
         ex: impl Executor<'_, Database = Postgres>,
-    ) -> error::Result<Option<Self>> {
+    ) -> error::Result<Self> {
         sqlx::query_as(
-            "SELECT * FROM actions WHERE client_id = $1 AND state = $2 ORDER BY created_at LIMIT 1",
+            "WITH token AS (SELECT * FROM enrollment_token WHERE sha256 = $1
+                AND (expires_at IS NULL OR expires_at > current_timestamp) FOR UPDATE),
+            used AS (DELETE FROM enrollment_token WHERE id IN (SELECT id FROM token WHERE NOT reusable))
+            SELECT * FROM token",
         )
-        .bind(client_id)
-        .bind(ActionState::Pending as i16)
-        .fetch_optional(ex)
+        .bind(sha256)
+        .fetch_one(ex)
         .await
         .map_err(From::from)
     }
 
-    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
-        sqlx::query("INSERT INTO actions (id, client_id, created_at, started_at, timeout, command, state, error, result)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
-            .bind(self.id)
-            .bind(self.client_id)
-            .bind(self.created_at)
-            .bind(self.started_at)
-            .bind(self.timeout)
-            .bind(self.command)
-            .bind(self.state)
-            .bind(self.error)
-            .bind(self.result)
+    pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        let res = sqlx::query("DELETE FROM enrollment_token WHERE id = $1")
+            .bind(id)
             .execute(ex)
             .await?;
+        if res.rows_affected() == 0 {
+            return Err(error::Error::not_found("token not found"));
+        }
         Ok(())
     }
 
-    pub async fn update(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
-        sqlx::query("UPDATE actions SET (started_at, state, error, result) = ($1, $2, $3, $4) WHERE id = $5")
-            .bind(self.started_at)
-            .bind(self.state)
-            .bind(self.error)
-            .bind(self.result)
-            .bind(self.id)
+    pub async fn delete_expired(ex: impl Executor<'_, Database = Postgres>) -> error::Result<u64> {
+        let res = sqlx::query("DELETE FROM enrollment_token WHERE expires_at <= current_timestamp")
             .execute(ex)
             .await?;
-        Ok(())
+        Ok(res.rows_affected())
     }
+}
 
-    pub async fn delete(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
-        sqlx::query("DELETE FROM actions WHERE id = $1")
-            .bind(self.id)
+/// Nonce a client has to sign to prove possession of its key
+#[derive(Debug, sqlx::FromRow)]
+pub struct Challenge {
+    pub nonce: Vec<u8>,
+    pub client_id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Challenge {
+    pub fn new(nonce: Vec<u8>, client_id: String, ttl: std::time::Duration) -> Self {
+        Self {
+            nonce,
+            client_id,
+            expires_at: Utc::now() + Duration::seconds(ttl.as_secs() as i64),
+        }
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO challenge (nonce, client_id, expires_at) VALUES ($1, $2, $3)")
+            .bind(self.nonce)
+            .bind(self.client_id)
+            .bind(self.expires_at)
             .execute(ex)
             .await?;
         Ok(())
     }
 
-    pub async fn _list(
-        opts: ListOptions,
+    /// Removes the challenge, so it can't be answered twice. Fails if it is not issued for the
+    /// client or has expired
+    pub async fn take(
+        nonce: &[u8],
+        client_id: &str,
         ex: impl Executor<'_, Database = Postgres>,
-    ) -> error::Result<Vec<Self>> {
-        let mut builder = QueryBuilder::new("SELECT * FROM actions ORDER by created_at");
-        if let Some(limit) = opts.limit {
-            builder.push(" LIMIT ").push_bind(limit);
-        }
-        if let Some(offset) = opts.offset {
-            builder.push(" OFFSET ").push_bind(offset);
-        }
-        builder
-            .build_query_as()
-            .fetch_all(ex)
-            .await
-            .map_err(From::from)
+    ) -> error::Result<Self> {
+        sqlx::query_as(
+            "DELETE FROM challenge WHERE nonce = $1 AND client_id = $2
+            AND expires_at > current_timestamp RETURNING *",
+        )
+        .bind(nonce)
+        .bind(client_id)
+        .fetch_one(ex)
+        .await
+        .map_err(From::from)
     }
 
-    pub async fn list_by_state(
-        state: ActionState,
-        opts: ListOptions,
-        ex: impl Executor<'_, Database = Postgres>,
-    ) -> error::Result<Vec<Self>> {
-        let mut builder = QueryBuilder::new("SELECT * FROM actions WHERE state = ");
-        builder.push_bind(state as i16).push(" ORDER by created_at");
-        if let Some(limit) = opts.limit {
-            builder.push(" LIMIT ").push_bind(limit);
-        }
-        if let Some(offset) = opts.offset {
-            builder.push(" OFFSET ").push_bind(offset);
+    pub async fn delete_expired(ex: impl Executor<'_, Database = Postgres>) -> error::Result<u64> {
+        let res = sqlx::query("DELETE FROM challenge WHERE expires_at <= current_timestamp")
+            .execute(ex)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+/// Command fanned out by server to a number of clients, one action per client
+#[derive(Debug, sqlx::FromRow)]
+pub struct Job {
+    pub id: String,
+    pub command: ActionCommand,
+    // Selector or ids the job was submitted with
+    pub targets: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Job {
+    pub fn new(command: ActionCommand, targets: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            command,
+            targets,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM jobs WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO jobs (id, command, targets, created_at) VALUES ($1, $2, $3, $4)")
+            .bind(self.id)
+            .bind(self.command)
+            .bind(self.targets)
+            .bind(self.created_at)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes old jobs. Their actions are left to be collected as any other finished ones
+    pub async fn delete_expired(
+        ttl: std::time::Duration,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<u64> {
+        let res = sqlx::query("DELETE FROM jobs WHERE current_timestamp - created_at >= $1")
+            .bind(ttl)
+            .execute(ex)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[derive(Debug, Clone, Copy, sqlx::Type)]
+#[repr(i16)]
+pub enum ActionCommand {
+    Ping,
+    Purge,
+    Shell,
+    Session,
+    PutFile,
+    GetFile,
+}
+
+#[derive(Debug, sqlx::Type)]
+#[repr(i16)]
+pub enum ActionState {
+    Pending,
+    Running,
+    Finished,
+    // Client could not execute the action, see error kind
+    Failed,
+    // Deadline passed before the action finished
+    TimedOut,
+    // Stopped on operator's request
+    Cancelled,
+    // Client went away while executing the action, or lost its result
+    Lost,
+}
+
+impl ActionState {
+    /// Action will not change anymore
+    pub fn is_done(&self) -> bool {
+        !matches!(self, Self::Pending | Self::Running)
+    }
+}
+
+#[derive(Debug, sqlx::Type)]
+#[repr(i16)]
+pub enum ActionError {
+    Unknown,
+    SpawnFailed,
+    NotFound,
+    PermissionDenied,
+    Unsupported,
+    TimedOut,
+    Cancelled,
+    Lost,
+}
+
+/// What happens to an action whose client disconnects while executing it
+#[derive(Debug, Clone, Copy, sqlx::Type)]
+#[repr(i16)]
+pub enum Delivery {
+    // Action is lost, so it is never executed twice
+    AtMostOnce,
+    // Action is delivered again once the client reconnects, the client skips it if it has
+    // executed it already
+    AtLeastOnce,
+}
+
+impl Delivery {
+    /// State of the action once its client disconnects while executing it
+    pub fn released_state(self) -> ActionState {
+        match self {
+            Self::AtMostOnce => ActionState::Lost,
+            Self::AtLeastOnce => ActionState::Pending,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Action {
+    pub id: String,
+    pub client_id: String,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub timeout: Option<i64>,
+    // Action waits for its client until then, and its timeout counts from dispatch instead
+    pub expires_at: Option<DateTime<Utc>>,
+    pub command: ActionCommand,
+    pub state: ActionState,
+    pub error: Option<Vec<u8>>, // using bytes here, so it is possible to get files as response
+    pub result: Option<Vec<u8>>, // same
+    pub error_kind: Option<ActionError>,
+    pub job_id: Option<String>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub delivery: Delivery,
+}
+
+impl Action {
+    pub fn new(client_id: String, command: ActionCommand) -> Self {
+        Self::with_id(Uuid::new_v4().to_string(), client_id, command)
+    }
+
+    pub fn with_id(id: String, client_id: String, command: ActionCommand) -> Self {
+        let created_at = Utc::now();
+        Self {
+            id,
+            client_id,
+            created_at,
+            started_at: None,
+            timeout: None,
+            expires_at: None,
+            command,
+            state: ActionState::Pending,
+            error: None,
+            result: None,
+            error_kind: None,
+            job_id: None,
+            finished_at: None,
+            delivery: Delivery::AtMostOnce,
+        }
+    }
+
+    pub fn with_job(self, job_id: String) -> Self {
+        Self {
+            job_id: Some(job_id),
+            ..self
+        }
+    }
+
+    pub fn with_delivery(self, delivery: Delivery) -> Self {
+        Self { delivery, ..self }
+    }
+
+    pub fn with_timeout(self, timeout: std::time::Duration) -> Self {
+        Self {
+            timeout: Some(timeout.as_secs() as i64),
+            ..self
+        }
+    }
+
+    /// Queues the action for a client, that may be offline for a while. The action is given up
+    /// if it's not dispatched within `ttl`
+    pub fn with_expiry(self, ttl: std::time::Duration) -> Self {
+        Self {
+            expires_at: Some(self.created_at + Duration::seconds(ttl.as_secs() as i64)),
+            ..self
+        }
+    }
+
+    /// Time the action must be finished by
+    pub fn deadline(&self) -> Option<DateTime<Utc>> {
+        let start = match self.expires_at {
+            Some(_) => self.started_at.unwrap_or_else(Utc::now),
+            None => self.created_at,
+        };
+        self.timeout.map(|t| start + Duration::seconds(t))
+    }
+
+    /// Time the action is done by one way or another, even if it is never dispatched
+    pub fn settled_by(&self) -> Option<DateTime<Utc>> {
+        match (self.started_at, self.expires_at) {
+            (None, Some(expires_at)) => self.timeout.map(|t| expires_at + Duration::seconds(t)),
+            _ => self.deadline(),
+        }
+    }
+
+    /// Whether the action has waited for dispatch for too long
+    pub fn is_expired(&self) -> bool {
+        self.started_at.is_none() && self.expires_at.is_some_and(|e| e <= Utc::now())
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM actions WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    /// Same as `get`, but keeps the row locked until the end of transaction, so the action
+    /// can't be dispatched or finished concurrently
+    pub async fn get_for_update(
+        id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM actions WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn get_next(
+        client_id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Option<Self>> {
+        sqlx::query_as(
+            "SELECT * FROM actions WHERE client_id = $1 AND state = $2 ORDER BY created_at LIMIT 1 FOR UPDATE",
+        )
+        .bind(client_id)
+        .bind(ActionState::Pending as i16)
+        .fetch_optional(ex)
+        .await
+        .map_err(From::from)
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        // Notification is delivered on commit, so the action is visible to whoever wakes up
+        sqlx::query("WITH action AS (INSERT INTO actions (id, client_id, created_at, started_at, timeout, expires_at, command, state, error, result, error_kind, job_id, delivery)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) RETURNING client_id)
+            SELECT pg_notify($14, client_id) FROM action")
+            .bind(self.id)
+            .bind(self.client_id)
+            .bind(self.created_at)
+            .bind(self.started_at)
+            .bind(self.timeout)
+            .bind(self.expires_at)
+            .bind(self.command)
+            .bind(self.state)
+            .bind(self.error)
+            .bind(self.result)
+            .bind(self.error_kind)
+            .bind(self.job_id)
+            .bind(self.delivery)
+            .bind(hub::ACTIONS_CHANNEL)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn update(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        // Finish time is recorded once the action is done, results are kept for a while after that
+        let finished_at = match self.state.is_done() {
+            true => self.finished_at.or_else(|| Some(Utc::now())),
+            false => None,
+        };
+        sqlx::query("UPDATE actions SET (started_at, state, error, result, error_kind, finished_at) = ($1, $2, $3, $4, $5, $6) WHERE id = $7")
+            .bind(self.started_at)
+            .bind(self.state)
+            .bind(self.error)
+            .bind(self.result)
+            .bind(self.error_kind)
+            .bind(finished_at)
+            .bind(self.id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    /// Settles actions the client was executing when its connection went away. At most once
+    /// actions are lost until the client reports their results on reconnect, the rest are put
+    /// back in the queue to be delivered again
+    pub async fn release_running(
+        client_id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query(
+            "WITH requeued AS (UPDATE actions SET state = $1, started_at = NULL
+            WHERE client_id = $2 AND state = $3 AND delivery = $4),
+            lost AS (UPDATE actions SET state = $5, error = $6, finished_at = current_timestamp
+            WHERE client_id = $2 AND state = $3 AND delivery = $7
+            RETURNING id)
+            SELECT pg_notify($8, id) FROM lost",
+        )
+        .bind(Delivery::AtLeastOnce.released_state() as i16)
+        .bind(client_id)
+        .bind(ActionState::Running as i16)
+        .bind(Delivery::AtLeastOnce as i16)
+        .bind(Delivery::AtMostOnce.released_state() as i16)
+        .bind(b"client disconnected while executing the action".as_slice())
+        .bind(Delivery::AtMostOnce as i16)
+        .bind(hub::RESULTS_CHANNEL)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    /// Lets the instance holding Poll stream of the client look into its queue again
+    pub async fn notify_queued(
+        client_id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(hub::ACTIONS_CHANNEL)
+            .bind(client_id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    /// Announces finished action to everyone waiting for it. Notification is delivered on commit
+    pub async fn notify_finished(
+        id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(hub::RESULTS_CHANNEL)
+            .bind(id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("DELETE FROM actions WHERE id = $1")
+            .bind(self.id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn _list(
+        opts: ListOptions,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Vec<Self>> {
+        let mut builder = QueryBuilder::new("SELECT * FROM actions ORDER by created_at");
+        if let Some(limit) = opts.limit {
+            builder.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = opts.offset {
+            builder.push(" OFFSET ").push_bind(offset);
+        }
+        builder
+            .build_query_as()
+            .fetch_all(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    /// Moves pending and running actions past their deadline into timed out state and announces
+    /// them as finished. Returns number of timed out actions
+    pub async fn time_out_overdue(
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<u64> {
+        let res = sqlx::query(
+            "WITH overdue AS (UPDATE actions SET state = $1, finished_at = current_timestamp
+            WHERE state IN ($2, $3) AND (
+                (expires_at IS NULL AND timeout IS NOT NULL
+                AND created_at + timeout * interval '1 second' <= current_timestamp)
+                OR (expires_at IS NOT NULL AND state = $2 AND expires_at <= current_timestamp)
+                OR (expires_at IS NOT NULL AND state = $3 AND timeout IS NOT NULL
+                AND started_at + timeout * interval '1 second' <= current_timestamp))
+            RETURNING id)
+            SELECT pg_notify($4, id) FROM overdue",
+        )
+        .bind(ActionState::TimedOut as i16)
+        .bind(ActionState::Pending as i16)
+        .bind(ActionState::Running as i16)
+        .bind(hub::RESULTS_CHANNEL)
+        .execute(ex)
+        .await?;
+        Ok(res.rows_affected())
+    }
+
+    pub async fn list_by_job(
+        job_id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM actions WHERE job_id = $1 ORDER BY client_id")
+            .bind(job_id)
+            .fetch_all(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    /// Lists actions which have not changed for `ttl` since they were done, except the ones
+    /// belonging to a job. Jobs keep theirs until they expire
+    pub async fn list_done(
+        ttl: std::time::Duration,
+        opts: ListOptions,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Vec<Self>> {
+        let mut builder = QueryBuilder::new(
+            "SELECT * FROM actions WHERE job_id IS NULL
+            AND (finished_at IS NULL OR current_timestamp - finished_at >= ",
+        );
+        builder
+            .push_bind(ttl)
+            .push(") AND state NOT IN (")
+            .push_bind(ActionState::Pending as i16)
+            .push(", ")
+            .push_bind(ActionState::Running as i16)
+            .push(") ORDER by created_at");
+        if let Some(limit) = opts.limit {
+            builder.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = opts.offset {
+            builder.push(" OFFSET ").push_bind(offset);
+        }
+        builder
+            .build_query_as()
+            .fetch_all(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn _list_by_state(
+        state: ActionState,
+        opts: ListOptions,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Vec<Self>> {
+        let mut builder = QueryBuilder::new("SELECT * FROM actions WHERE state = ");
+        builder.push_bind(state as i16).push(" ORDER by created_at");
+        if let Some(limit) = opts.limit {
+            builder.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = opts.offset {
+            builder.push(" OFFSET ").push_bind(offset);
+        }
+        builder
+            .build_query_as()
+            .fetch_all(ex)
+            .await
+            .map_err(From::from)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct PingCommand {
+    id: String,
+    pub data: String,
+}
+
+impl PingCommand {
+    pub fn new(id: String, data: String) -> Self {
+        Self { id, data }
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM ping WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO ping (id, data) VALUES ($1, $2)")
+            .bind(self.id)
+            .bind(self.data)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("DELETE FROM ping WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn _list(
+        opts: ListOptions,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Vec<Self>> {
+        let mut builder = QueryBuilder::new("SELECT * FROM ping");
+        if let Some(limit) = opts.limit {
+            builder.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = opts.offset {
+            builder.push(" OFFSET ").push_bind(offset);
+        }
+        builder
+            .build_query_as()
+            .fetch_all(ex)
+            .await
+            .map_err(From::from)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct ShellCommand {
+    id: String,
+    pub cmd: String,
+    pub args: Vec<String>,
+    pub stdin: Vec<u8>,
+    pub stream: bool,
+}
+
+impl ShellCommand {
+    pub fn new(id: String, cmd: String, args: Vec<String>, stdin: Vec<u8>, stream: bool) -> Self {
+        Self {
+            id,
+            cmd,
+            args,
+            stdin,
+            stream,
+        }
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM shell WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO shell (id, cmd, args, stdin, stream) VALUES ($1, $2, $3, $4, $5)")
+            .bind(self.id)
+            .bind(self.cmd)
+            .bind(self.args)
+            .bind(self.stdin)
+            .bind(self.stream)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("DELETE FROM shell WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn _list(
+        opts: ListOptions,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Vec<Self>> {
+        let mut builder = QueryBuilder::new("SELECT * FROM shell");
+        if let Some(limit) = opts.limit {
+            builder.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = opts.offset {
+            builder.push(" OFFSET ").push_bind(offset);
+        }
+        builder
+            .build_query_as()
+            .fetch_all(ex)
+            .await
+            .map_err(From::from)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct ShellResult {
+    id: String,
+    pub code: i32,
+    pub signal: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl ShellResult {
+    pub fn new(id: String, code: i32, stdout: Vec<u8>, stderr: Vec<u8>) -> Self {
+        Self {
+            id,
+            code,
+            signal: None,
+            stdout,
+            stderr,
+            started_at: None,
+            finished_at: None,
+        }
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM shell_result WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query(
+            "INSERT INTO shell_result (id, code, signal, stdout, stderr, started_at, finished_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(self.id)
+        .bind(self.code)
+        .bind(self.signal)
+        .bind(self.stdout)
+        .bind(self.stderr)
+        .bind(self.started_at)
+        .bind(self.finished_at)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("DELETE FROM shell_result WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct SessionCommand {
+    id: String,
+    pub rows: i32,
+    pub cols: i32,
+    pub term: String,
+}
+
+impl SessionCommand {
+    pub fn new(id: String, rows: i32, cols: i32, term: String) -> Self {
+        Self {
+            id,
+            rows,
+            cols,
+            term,
+        }
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM session WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO session (id, rows, cols, term) VALUES ($1, $2, $3, $4)")
+            .bind(self.id)
+            .bind(self.rows)
+            .bind(self.cols)
+            .bind(self.term)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("DELETE FROM session WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Blob {
+    pub id: String,
+    pub size: i64,
+    pub sha256: Vec<u8>,
+    pub chunks: i32,
+    pub created_at: DateTime<Utc>,
+    // Set once the whole content is uploaded, only complete blob can be put anywhere
+    pub completed_at: Option<DateTime<Utc>>,
+    // Last time a chunk was uploaded or the blob was put somewhere
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Blob {
+    pub fn new() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            size: 0,
+            sha256: Vec::new(),
+            chunks: 0,
+            created_at: now,
+            completed_at: None,
+            updated_at: now,
+        }
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM blob WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query(
+            "INSERT INTO blob (id, size, sha256, chunks, created_at, completed_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(self.id)
+        .bind(self.size)
+        .bind(self.sha256)
+        .bind(self.chunks)
+        .bind(self.created_at)
+        .bind(self.completed_at)
+        .bind(self.updated_at)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn update(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query(
+            "UPDATE blob SET (size, sha256, chunks, completed_at, updated_at)
+            = ($1, $2, $3, $4, current_timestamp) WHERE id = $5",
+        )
+        .bind(self.size)
+        .bind(self.sha256)
+        .bind(self.chunks)
+        .bind(self.completed_at)
+        .bind(self.id)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    /// Keeps the blob from being collected while it is uploaded or put somewhere. The row stays
+    /// locked until the end of transaction. Fails if the blob has been collected already
+    pub async fn touch(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        let res = sqlx::query("UPDATE blob SET updated_at = current_timestamp WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(error::Error::not_found("blob not found"));
         }
-        builder
-            .build_query_as()
-            .fetch_all(ex)
+        Ok(())
+    }
+
+    /// Deletes blobs which are not going to be put anywhere and have not been touched for ttl.
+    /// Those are either put somewhere already or their upload broke off
+    pub async fn delete_unused(
+        ttl: std::time::Duration,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<u64> {
+        let res = sqlx::query(
+            "DELETE FROM blob WHERE current_timestamp - updated_at >= $1
+            AND id NOT IN (SELECT blob_id FROM put_file)",
+        )
+        .bind(ttl)
+        .execute(ex)
+        .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct BlobChunk {
+    blob_id: String,
+    seq: i32,
+    pub data: Vec<u8>,
+}
+
+impl BlobChunk {
+    pub fn new(blob_id: String, seq: i32, data: Vec<u8>) -> Self {
+        Self { blob_id, seq, data }
+    }
+
+    pub async fn get(
+        blob_id: &str,
+        seq: i32,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM blob_chunk WHERE blob_id = $1 AND seq = $2")
+            .bind(blob_id)
+            .bind(seq)
+            .fetch_one(ex)
             .await
             .map_err(From::from)
     }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO blob_chunk (blob_id, seq, data) VALUES ($1, $2, $3)")
+            .bind(self.blob_id)
+            .bind(self.seq)
+            .bind(self.data)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
 }
 
 #[derive(sqlx::FromRow)]
-pub struct PingCommand {
+pub struct PutFileCommand {
     id: String,
-    pub data: String,
+    pub blob_id: String,
+    pub path: String,
+    pub mode: i32,
+    pub owner: String,
+    pub atomic: bool,
 }
 
-impl PingCommand {
-    pub fn new(id: String, data: String) -> Self {
-        Self { id, data }
+impl PutFileCommand {
+    pub fn new(
+        id: String,
+        blob_id: String,
+        path: String,
+        mode: i32,
+        owner: String,
+        atomic: bool,
+    ) -> Self {
+        Self {
+            id,
+            blob_id,
+            path,
+            mode,
+            owner,
+            atomic,
+        }
     }
 
     pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
-        sqlx::query_as("SELECT * FROM ping WHERE id = $1")
+        sqlx::query_as("SELECT * FROM put_file WHERE id = $1")
             .bind(id)
             .fetch_one(ex)
             .await
@@ -312,61 +1421,137 @@ impl PingCommand {
     }
 
     pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
-        sqlx::query("INSERT INTO ping (id, data) VALUES ($1, $2)")
+        sqlx::query(
+            "INSERT INTO put_file (id, blob_id, path, mode, owner, atomic)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(self.id)
+        .bind(self.blob_id)
+        .bind(self.path)
+        .bind(self.mode)
+        .bind(self.owner)
+        .bind(self.atomic)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("DELETE FROM put_file WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct GetFileCommand {
+    id: String,
+    pub path: String,
+}
+
+impl GetFileCommand {
+    pub fn new(id: String, path: String) -> Self {
+        Self { id, path }
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM get_file WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
+            .await
+            .map_err(From::from)
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO get_file (id, path) VALUES ($1, $2)")
             .bind(self.id)
-            .bind(self.data)
+            .bind(self.path)
             .execute(ex)
             .await?;
         Ok(())
     }
 
     pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
-        sqlx::query("DELETE FROM ping WHERE id = $1")
+        sqlx::query("DELETE FROM get_file WHERE id = $1")
             .bind(id)
             .execute(ex)
             .await?;
         Ok(())
     }
+}
 
-    pub async fn _list(
-        opts: ListOptions,
-        ex: impl Executor<'_, Database = Postgres>,
-    ) -> error::Result<Vec<Self>> {
-        let mut builder = QueryBuilder::new("SELECT * FROM ping");
-        if let Some(limit) = opts.limit {
-            builder.push(" LIMIT ").push_bind(limit);
-        }
-        if let Some(offset) = opts.offset {
-            builder.push(" OFFSET ").push_bind(offset);
+#[derive(sqlx::FromRow)]
+pub struct PingResult {
+    id: String,
+    // microseconds
+    pub rtt: i64,
+    // how far clock of the client is ahead of server clock, in microseconds
+    pub clock_offset: i64,
+}
+
+impl PingResult {
+    /// Computes round-trip time and clock offset from timestamps carried by the ping. The client
+    /// is assumed to have received the ping halfway through the round trip
+    pub fn new(id: String, sent_at: i64, received_at: i64, returned_at: i64) -> Self {
+        let rtt = (returned_at - sent_at).max(0);
+        Self {
+            id,
+            rtt,
+            clock_offset: received_at - (sent_at + rtt / 2),
         }
-        builder
-            .build_query_as()
-            .fetch_all(ex)
+    }
+
+    pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
+        sqlx::query_as("SELECT * FROM ping_result WHERE id = $1")
+            .bind(id)
+            .fetch_one(ex)
             .await
             .map_err(From::from)
     }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO ping_result (id, rtt, clock_offset) VALUES ($1, $2, $3)")
+            .bind(self.id)
+            .bind(self.rtt)
+            .bind(self.clock_offset)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("DELETE FROM ping_result WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
 }
 
 #[derive(sqlx::FromRow)]
-pub struct ShellCommand {
+pub struct GetFileResult {
     id: String,
-    pub cmd: String,
-    pub args: Vec<String>,
-    pub stdin: Vec<u8>,
+    pub size: i64,
+    pub sha256: Vec<u8>,
+    pub mode: i32,
+    pub modified_at: Option<DateTime<Utc>>,
 }
 
-impl ShellCommand {
-    pub fn new(id: String, cmd: String, args: Vec<String>, stdin: Vec<u8>) -> Self {
+impl GetFileResult {
+    pub fn new(id: String, size: i64, sha256: Vec<u8>, mode: i32) -> Self {
         Self {
             id,
-            cmd,
-            args,
-            stdin,
+            size,
+            sha256,
+            mode,
+            modified_at: None,
         }
     }
 
     pub async fn get(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<Self> {
-        sqlx::query_as("SELECT * FROM shell WHERE id = $1")
+        sqlx::query_as("SELECT * FROM get_file_result WHERE id = $1")
             .bind(id)
             .fetch_one(ex)
             .await
@@ -374,39 +1559,292 @@ impl ShellCommand {
     }
 
     pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
-        sqlx::query("INSERT INTO shell (id, cmd, args, stdin) VALUES ($1, $2, $3, $4)")
+        sqlx::query(
+            "INSERT INTO get_file_result (id, size, sha256, mode, modified_at)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(self.id)
+        .bind(self.size)
+        .bind(self.sha256)
+        .bind(self.mode)
+        .bind(self.modified_at)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("DELETE FROM get_file_result WHERE id = $1")
+            .bind(id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Running server process. Several of them may share the database
+#[derive(Debug, sqlx::FromRow)]
+pub struct Instance {
+    pub id: String,
+    pub started_at: DateTime<Utc>,
+    pub heartbeat_at: DateTime<Utc>,
+}
+
+impl Instance {
+    pub fn new() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            started_at: now,
+            heartbeat_at: now,
+        }
+    }
+
+    pub async fn create(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
+        sqlx::query("INSERT INTO instances (id, started_at, heartbeat_at) VALUES ($1, $2, $3)")
             .bind(self.id)
-            .bind(self.cmd)
-            .bind(self.args)
-            .bind(self.stdin)
+            .bind(self.started_at)
+            .bind(self.heartbeat_at)
             .execute(ex)
             .await?;
         Ok(())
     }
 
+    /// Creates the instance again if GC has removed it while it was stalled, since clients can't
+    /// connect to an instance which does not exist. Returns true if it had to be created
+    pub async fn heartbeat(
+        id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<bool> {
+        let (created,) = sqlx::query_as(
+            "INSERT INTO instances (id, started_at, heartbeat_at)
+            VALUES ($1, current_timestamp, current_timestamp)
+            ON CONFLICT (id) DO UPDATE SET heartbeat_at = current_timestamp
+            RETURNING xmax = 0",
+        )
+        .bind(id)
+        .fetch_one(ex)
+        .await?;
+        Ok(created)
+    }
+
     pub async fn delete(id: &str, ex: impl Executor<'_, Database = Postgres>) -> error::Result<()> {
-        sqlx::query("DELETE FROM shell WHERE id = $1")
+        sqlx::query("DELETE FROM instances WHERE id = $1")
             .bind(id)
             .execute(ex)
             .await?;
         Ok(())
     }
 
-    pub async fn _list(
-        opts: ListOptions,
+    /// Removes instances which stopped sending heartbeats. Their clients are taken over once
+    /// leases expire
+    pub async fn delete_stale(
+        ttl: std::time::Duration,
         ex: impl Executor<'_, Database = Postgres>,
-    ) -> error::Result<Vec<Self>> {
-        let mut builder = QueryBuilder::new("SELECT * FROM shell");
-        if let Some(limit) = opts.limit {
-            builder.push(" LIMIT ").push_bind(limit);
-        }
-        if let Some(offset) = opts.offset {
-            builder.push(" OFFSET ").push_bind(offset);
-        }
-        builder
-            .build_query_as()
-            .fetch_all(ex)
+    ) -> error::Result<u64> {
+        let res = sqlx::query("DELETE FROM instances WHERE current_timestamp - heartbeat_at >= $1")
+            .bind(ttl)
+            .execute(ex)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+/// Message handed over to the instance holding a Poll stream or a watcher of output. Postgres
+/// notifications are too small to carry it, so they carry its id only
+#[derive(Debug, sqlx::FromRow)]
+pub struct Relay {
+    pub id: i64,
+    pub message: Vec<u8>,
+}
+
+impl Relay {
+    /// Stores the message and announces it on the channel along with the target it is for
+    pub async fn publish(
+        channel: &str,
+        target: &str,
+        message: Vec<u8>,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query(
+            "WITH relay AS (INSERT INTO relay (message) VALUES ($1) RETURNING id)
+            SELECT pg_notify($2, id || ' ' || $3) FROM relay",
+        )
+        .bind(message)
+        .bind(channel)
+        .bind(target)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    /// Stores output of the action and announces it, unless no other instance watches the action.
+    /// Returns false in that case
+    pub async fn publish_output(
+        action_id: &str,
+        message: Vec<u8>,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<bool> {
+        let published = sqlx::query(
+            "WITH relay AS (
+                INSERT INTO relay (message)
+                SELECT $1 WHERE EXISTS (SELECT 1 FROM output_subscribers WHERE action_id = $3)
+                RETURNING id
+            )
+            SELECT pg_notify($2, id || ' ' || $3) FROM relay",
+        )
+        .bind(message)
+        .bind(crate::hub::OUTPUT_CHANNEL)
+        .bind(action_id)
+        .fetch_optional(ex)
+        .await?;
+        Ok(published.is_some())
+    }
+
+    /// Removes the message, so it is delivered once
+    pub async fn take(
+        id: i64,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<Option<Self>> {
+        sqlx::query_as("DELETE FROM relay WHERE id = $1 RETURNING id, message")
+            .bind(id)
+            .fetch_optional(ex)
             .await
             .map_err(From::from)
     }
+
+    /// Removes messages nobody has taken, e.g. because the watcher went away
+    pub async fn delete_expired(
+        ttl: std::time::Duration,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<u64> {
+        let res = sqlx::query("DELETE FROM relay WHERE current_timestamp - created_at >= $1")
+            .bind(ttl)
+            .execute(ex)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+/// Instance watching output of an action, so output is relayed through the database only when
+/// someone is there to take it
+pub struct OutputSubscriber;
+
+impl OutputSubscriber {
+    /// Registers the instance as the watcher, replacing the previous one
+    pub async fn create(
+        action_id: &str,
+        instance_id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query(
+            "INSERT INTO output_subscribers (action_id, instance_id) VALUES ($1, $2)
+            ON CONFLICT (action_id) DO UPDATE SET instance_id = excluded.instance_id",
+        )
+        .bind(action_id)
+        .bind(instance_id)
+        .execute(ex)
+        .await?;
+        Ok(())
+    }
+
+    /// Unregisters the instance, unless another one has become the watcher since
+    pub async fn delete(
+        action_id: &str,
+        instance_id: &str,
+        ex: impl Executor<'_, Database = Postgres>,
+    ) -> error::Result<()> {
+        sqlx::query("DELETE FROM output_subscribers WHERE action_id = $1 AND instance_id = $2")
+            .bind(action_id)
+            .bind(instance_id)
+            .execute(ex)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Jobs only one instance runs at a time
+#[derive(Debug, Clone, Copy)]
+#[repr(i32)]
+pub enum Singleton {
+    Gc = 1,
+    Reaper,
+}
+
+impl Singleton {
+    // Keeps advisory locks of notssh apart from anybody else's in the same database
+    const LOCK_NAMESPACE: i32 = 0x6e737368;
+
+    /// Takes advisory lock of the job for the rest of transaction. Returns false if another
+    /// instance holds it
+    pub async fn try_lock(self, ex: impl Executor<'_, Database = Postgres>) -> error::Result<bool> {
+        let (locked,) = sqlx::query_as("SELECT pg_try_advisory_xact_lock($1, $2)")
+            .bind(Self::LOCK_NAMESPACE)
+            .bind(self as i32)
+            .fetch_one(ex)
+            .await?;
+        Ok(locked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell(timeout: u64) -> Action {
+        Action::new("client".to_owned(), ActionCommand::Shell)
+            .with_timeout(std::time::Duration::from_secs(timeout))
+    }
+
+    #[test]
+    fn deadline_counts_from_creation() {
+        let act = shell(10);
+        assert_eq!(act.deadline(), Some(act.created_at + Duration::seconds(10)));
+        let act = Action::new("client".to_owned(), ActionCommand::Shell);
+        assert_eq!(act.deadline(), None);
+    }
+
+    #[test]
+    fn deadline_of_queued_action_counts_from_dispatch() {
+        let mut act = shell(10).with_expiry(std::time::Duration::from_secs(3600));
+        // Not dispatched yet, so it has the whole timeout whenever it is
+        assert!(act.deadline().unwrap() >= Utc::now() + Duration::seconds(9));
+        let started_at = act.created_at + Duration::minutes(30);
+        act.started_at = Some(started_at);
+        assert_eq!(act.deadline(), Some(started_at + Duration::seconds(10)));
+    }
+
+    #[test]
+    fn queued_action_is_settled_by_expiry_and_timeout() {
+        let mut act = shell(10).with_expiry(std::time::Duration::from_secs(3600));
+        let expires_at = act.expires_at.unwrap();
+        assert_eq!(act.settled_by(), Some(expires_at + Duration::seconds(10)));
+        act.started_at = Some(act.created_at);
+        assert_eq!(act.settled_by(), act.deadline());
+        let act = shell(10);
+        assert_eq!(act.settled_by(), act.deadline());
+    }
+
+    #[test]
+    fn only_undispatched_actions_expire() {
+        let mut act = shell(10).with_expiry(std::time::Duration::from_secs(3600));
+        assert!(!act.is_expired());
+        act.expires_at = Some(Utc::now() - Duration::seconds(1));
+        assert!(act.is_expired());
+        act.started_at = Some(act.created_at);
+        assert!(!act.is_expired());
+        assert!(!shell(10).is_expired());
+    }
+
+    #[test]
+    fn released_action_is_requeued_only_if_delivered_at_least_once() {
+        assert!(matches!(
+            Delivery::AtLeastOnce.released_state(),
+            ActionState::Pending
+        ));
+        assert!(matches!(
+            Delivery::AtMostOnce.released_state(),
+            ActionState::Lost
+        ));
+    }
 }
@@ -0,0 +1,261 @@
+use std::{collections::HashMap, str::FromStr};
+
+use notssh_util::error;
+
+/// Condition on a single label
+#[derive(Debug)]
+enum Requirement {
+    Equals(String, String),
+    // Also holds if the label is not set
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    // Also holds if the label is not set
+    NotIn(String, Vec<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        match self {
+            Self::Equals(k, v) => labels.get(k) == Some(v),
+            Self::NotEquals(k, v) => labels.get(k) != Some(v),
+            Self::In(k, vs) => labels.get(k).is_some_and(|v| vs.contains(v)),
+            Self::NotIn(k, vs) => labels.get(k).is_none_or(|v| !vs.contains(v)),
+            Self::Exists(k) => labels.contains_key(k),
+            Self::NotExists(k) => !labels.contains_key(k),
+        }
+    }
+}
+
+/// Selects clients by their labels. Requirements are separated by commas and all of them have to
+/// hold: `key=value`, `key==value`, `key!=value`, `key in (v1,v2)`, `key notin (v1,v2)`, `key`
+/// (label is set) and `!key` (label is not set)
+#[derive(Debug)]
+pub struct Selector(Vec<Requirement>);
+
+impl Selector {
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        self.0.iter().all(|r| r.matches(labels))
+    }
+}
+
+impl FromStr for Selector {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        split(s)?
+            .into_iter()
+            .map(requirement)
+            .collect::<error::Result<_>>()
+            .map(Self)
+    }
+}
+
+// Splits on commas outside of parentheses
+fn split(s: &str) -> error::Result<Vec<&str>> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' if depth == 0 => depth = 1,
+            ')' if depth == 1 => depth = 0,
+            '(' | ')' => return Err(error::Error::bad_request("unbalanced parentheses")),
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(error::Error::bad_request("unbalanced parentheses"));
+    }
+    parts.push(&s[start..]);
+    Ok(parts.into_iter().map(str::trim).collect())
+}
+
+fn requirement(s: &str) -> error::Result<Requirement> {
+    if let Some(open) = s.find('(') {
+        let values = s[open..]
+            .strip_prefix('(')
+            .and_then(|v| v.strip_suffix(')'))
+            .ok_or_else(|| error::Error::bad_request(format!("invalid requirement '{}'", s)))?
+            .split(',')
+            .map(|v| value(v.trim()))
+            .collect::<error::Result<Vec<_>>>()?;
+        let mut words = s[..open].split_whitespace();
+        let (key, op) = match (words.next(), words.next(), words.next()) {
+            (Some(key), Some(op), None) => (key, op),
+            _ => {
+                return Err(error::Error::bad_request(format!(
+                    "invalid requirement '{}'",
+                    s
+                )))
+            }
+        };
+        let key = key_name(key)?;
+        return match op {
+            "in" => Ok(Requirement::In(key, values)),
+            "notin" => Ok(Requirement::NotIn(key, values)),
+            _ => Err(error::Error::bad_request(format!(
+                "unknown operator '{}'",
+                op
+            ))),
+        };
+    }
+    if let Some((k, v)) = s.split_once("!=") {
+        return Ok(Requirement::NotEquals(
+            key_name(k.trim())?,
+            value(v.trim())?,
+        ));
+    }
+    if let Some((k, v)) = s.split_once("==").or_else(|| s.split_once('=')) {
+        return Ok(Requirement::Equals(key_name(k.trim())?, value(v.trim())?));
+    }
+    if let Some(k) = s.strip_prefix('!') {
+        return Ok(Requirement::NotExists(key_name(k.trim())?));
+    }
+    Ok(Requirement::Exists(key_name(s)?))
+}
+
+fn key_name(s: &str) -> error::Result<String> {
+    validate_key(s)?;
+    Ok(s.to_owned())
+}
+
+fn value(s: &str) -> error::Result<String> {
+    validate_value(s)?;
+    Ok(s.to_owned())
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')
+}
+
+/// Label key is non-empty and consists of ASCII letters, digits and `-_./`
+pub fn validate_key(key: &str) -> error::Result<()> {
+    if key.is_empty() || !key.chars().all(is_label_char) {
+        return Err(error::Error::bad_request(format!(
+            "invalid label key '{}'",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// Label value consists of the same characters as key, but may be empty
+pub fn validate_value(value: &str) -> error::Result<()> {
+    if !value.chars().all(is_label_char) {
+        return Err(error::Error::bad_request(format!(
+            "invalid label value '{}'",
+            value
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn matches(selector: &str, pairs: &[(&str, &str)]) -> bool {
+        selector
+            .parse::<Selector>()
+            .unwrap()
+            .matches(&labels(pairs))
+    }
+
+    #[test]
+    fn equality() {
+        assert!(matches("env=prod", &[("env", "prod")]));
+        assert!(matches("env==prod", &[("env", "prod")]));
+        assert!(!matches("env=prod", &[("env", "dev")]));
+        assert!(!matches("env=prod", &[]));
+        assert!(matches("env=", &[("env", "")]));
+    }
+
+    #[test]
+    fn inequality_holds_for_missing_label() {
+        assert!(matches("env!=prod", &[("env", "dev")]));
+        assert!(matches("env!=prod", &[]));
+        assert!(!matches("env!=prod", &[("env", "prod")]));
+    }
+
+    #[test]
+    fn operators_take_precedence_over_negation() {
+        // `!=` is not read as `!key` followed by `=`
+        let s: Selector = "env!=prod".parse().unwrap();
+        assert!(matches!(&s.0[..], [Requirement::NotEquals(k, v)] if k == "env" && v == "prod"));
+        // `==` is not read as `=` with value starting with `=`
+        let s: Selector = "env==prod".parse().unwrap();
+        assert!(matches!(&s.0[..], [Requirement::Equals(k, v)] if k == "env" && v == "prod"));
+        let s: Selector = "!env".parse().unwrap();
+        assert!(matches!(&s.0[..], [Requirement::NotExists(k)] if k == "env"));
+    }
+
+    #[test]
+    fn set_membership() {
+        assert!(matches("env in (prod, stage)", &[("env", "stage")]));
+        assert!(!matches("env in (prod,stage)", &[("env", "dev")]));
+        assert!(!matches("env in (prod,stage)", &[]));
+        assert!(matches("env notin (prod,stage)", &[("env", "dev")]));
+        assert!(matches("env notin (prod,stage)", &[]));
+        assert!(!matches("env notin (prod,stage)", &[("env", "prod")]));
+    }
+
+    #[test]
+    fn existence() {
+        assert!(matches("gpu", &[("gpu", "")]));
+        assert!(!matches("gpu", &[]));
+        assert!(matches("!gpu", &[]));
+        assert!(!matches("! gpu", &[("gpu", "a100")]));
+    }
+
+    #[test]
+    fn all_requirements_have_to_hold() {
+        let pairs = [("env", "prod"), ("zone", "b")];
+        assert!(matches("env=prod, zone in (a,b), !gpu", &pairs));
+        assert!(!matches("env=prod,zone in (a,c)", &pairs));
+    }
+
+    #[test]
+    fn commas_inside_parentheses_do_not_split() {
+        let s: Selector = "env in (a,b),zone=c".parse().unwrap();
+        assert_eq!(s.0.len(), 2);
+    }
+
+    #[test]
+    fn quotes_are_not_part_of_syntax() {
+        assert!("env=\"prod\"".parse::<Selector>().is_err());
+        assert!("env in ('a','b')".parse::<Selector>().is_err());
+        assert!("\"env\"=prod".parse::<Selector>().is_err());
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        for s in [
+            "",
+            "env=prod,",
+            "=prod",
+            "env in (a,b",
+            "env in a,b)",
+            "env in ((a))",
+            "env in (a) extra",
+            "env like (a)",
+            "in (a)",
+            "env=pro d",
+            "env=prod!",
+        ] {
+            assert!(s.parse::<Selector>().is_err(), "'{}' should be rejected", s);
+        }
+    }
+}
@@ -9,6 +9,12 @@ pub enum ErrorKind {
     BadRequest,
     Tonic,
     Arg,
+    PermissionDenied,
+    Unsupported,
+    Spawn,
+    TimedOut,
+    Cancelled,
+    Unauthenticated,
 }
 
 #[derive(Debug)]
@@ -49,6 +55,34 @@ impl Error {
     pub fn arg(description: impl Into<String>) -> Self {
         Self::new(ErrorKind::Arg, description.into())
     }
+
+    pub fn permission_denied(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::PermissionDenied, description.into())
+    }
+
+    pub fn unsupported(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Unsupported, description.into())
+    }
+
+    pub fn spawn(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Spawn, description.into())
+    }
+
+    pub fn timed_out(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::TimedOut, description.into())
+    }
+
+    pub fn cancelled(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Cancelled, description.into())
+    }
+
+    pub fn unauthenticated(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Unauthenticated, description.into())
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -74,6 +108,11 @@ impl From<Error> for tonic::Status {
         match value.kind {
             ErrorKind::NotFound => Self::not_found(value.description),
             ErrorKind::BadRequest => Self::invalid_argument(value.description),
+            ErrorKind::PermissionDenied => Self::permission_denied(value.description),
+            ErrorKind::Unsupported => Self::unimplemented(value.description),
+            ErrorKind::TimedOut => Self::deadline_exceeded(value.description),
+            ErrorKind::Cancelled => Self::cancelled(value.description),
+            ErrorKind::Unauthenticated => Self::unauthenticated(value.description),
             _ => Self::internal("internal error"),
         }
     }